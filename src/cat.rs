@@ -1,23 +1,373 @@
 use anyhow::bail;
 use clap::Parser;
-use std::io::{BufReader, BufWriter};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
 use tracing::{error, info, warn};
 
+use crate::record::{
+    with_record_type, ChunkBuffer, RecordDecoder, RecordEncoder, RecordTypeVisitor, SupportedRecord,
+};
+use crate::utils::{
+    buffered_writer, byte_progress_bar, compressed_writer, count_chunk_read, is_gzip_compressed,
+    is_stdin, is_url, open_and_parse, rad_reader, read_chunk_header, rewrite_num_chunks,
+    skip_bytes, Compression, ParsedRad,
+};
+use crate::validate::check_file;
+use crate::view::{resolve_rad_type, RadFileType};
+
 /// options relevant to building the minimizer space suffix array
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct CatOpts {
     /// ',' separated list of input RAD files
     #[arg(short, long, required = true, value_delimiter = ',')]
-    inputs: Vec<std::path::PathBuf>,
+    pub inputs: Vec<std::path::PathBuf>,
 
-    /// output RAD file
+    /// output RAD file; if it ends in `.gz` (or `.zst`), it is compressed.
+    /// Compressed inputs are always decompressed as they are read.
     #[arg(short, long, required = true)]
-    output: std::path::PathBuf,
+    pub output: std::path::PathBuf,
+
+    /// rather than requiring the preludes of all inputs to be identical,
+    /// require only that they be compatible (see `preludes_compatible`)
+    #[arg(long)]
+    pub force: bool,
+
+    /// the inputs may list the same references in different orders; rewrite
+    /// the reference IDs of the records of each input to refer to the
+    /// references as they are ordered in the first input. This requires
+    /// decoding and re-encoding the records of all but the first input.
+    #[arg(long)]
+    pub remap_refs: bool,
+
+    /// the type of the input RAD files (needed for `--remap-refs`); if not
+    /// provided (or `unknown`), the type will be detected from the
+    /// file-level tags of the first input.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// be quiet (no progress bar or per-file messages); only warnings,
+    /// errors and the final summary are reported
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// rather than requiring all inputs to be of the same type, partition
+    /// them by their type and write one output per type, named by inserting
+    /// the type before the extension of `--output` (e.g. `out.bulk.rad`
+    /// and `out.sc.rad`)
+    #[arg(long)]
+    pub group_by_type: bool,
+
+    /// write the chunks of the inputs in the order of their values of this
+    /// (integer or string) file-level tag, e.g. a genomic bin ID, rather
+    /// than in the order the inputs are given; the inputs may differ in
+    /// the value of this tag, and the output has the value of the first
+    /// input listed
+    #[arg(long, value_name = "TAG")]
+    pub merge_sorted: Option<String>,
+
+    /// concatenate the inputs in the order of the last number in each of
+    /// their file names (e.g. `out_2.rad` before `out_10.rad`), rather than
+    /// in the order they are given; inputs with no number in their names
+    /// follow the numbered ones, in lexicographic order
+    #[arg(long, conflicts_with = "merge_sorted")]
+    pub sort_inputs: bool,
+
+    /// drop the chunks of the inputs that contain no records (which some
+    /// producers emit as keepalives), rather than copying them; the
+    /// number of chunks in the output prelude counts only the chunks kept
+    /// (or, if the output is compressed, is left as 0, i.e. unknown)
+    #[arg(long)]
+    pub prune_empty: bool,
+
+    /// write a JSON summary of the concatenation to this file, listing
+    /// each input with the output it was copied into and the number of
+    /// records, chunks and bytes of chunks copied from it
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<std::path::PathBuf>,
+
+    /// rather than creating `--output`, append the chunks of the inputs to
+    /// it, and update the number of chunks in its prelude; it must be an
+    /// existing (uncompressed) RAD file, with which the inputs must be
+    /// compatible as they would be with a first input. Its own chunks are
+    /// left in place rather than copied.
+    #[arg(long, conflicts_with_all = ["group_by_type", "merge_sorted"])]
+    pub append: bool,
+}
+
+/// The number of records, chunks and bytes of chunks copied
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct CopyCounts {
+    pub num_records: u64,
+    pub num_chunks: u64,
+    pub num_bytes: u64,
+}
+
+/// What was copied from one input of `cat`, and into which output
+#[derive(Serialize, Debug)]
+pub struct InputSummary {
+    pub file: std::path::PathBuf,
+    pub output: std::path::PathBuf,
+    #[serde(flatten)]
+    pub copied: CopyCounts,
+}
+
+/// The JSON summary of a concatenation written with `--report`
+#[derive(Serialize, Debug)]
+pub struct CatReport<'a> {
+    pub inputs: &'a [InputSummary],
+    pub outputs: Vec<&'a std::path::Path>,
+    pub num_chunks: u64,
+    pub num_records: u64,
+    pub bytes_copied: u64,
+}
+
+/// Write the `--report` of a concatenation of `inputs`, if one was requested
+fn write_report(cat_opts: &CatOpts, inputs: &[InputSummary]) -> anyhow::Result<()> {
+    let Some(ref path) = cat_opts.report else {
+        return Ok(());
+    };
+    let mut outputs: Vec<&std::path::Path> = Vec::new();
+    for i in inputs.iter() {
+        if !outputs.contains(&i.output.as_path()) {
+            outputs.push(&i.output);
+        }
+    }
+    let report = CatReport {
+        inputs,
+        outputs,
+        num_chunks: inputs.iter().map(|i| i.copied.num_chunks).sum(),
+        num_records: inputs.iter().map(|i| i.copied.num_records).sum(),
+        bytes_copied: inputs.iter().map(|i| i.copied.num_bytes).sum(),
+    };
+    let mut w = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(&mut w, &report)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+/// The value of the file-level tag by which inputs are ordered with
+/// `--merge-sorted`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Int(u64),
+    Str(String),
+}
+
+impl SortKey {
+    /// Get the value of the tag `name` from the file-level tags of `in_file`
+    fn from_tag_map(
+        tag_map: &libradicl::rad_types::TagMap,
+        name: &str,
+        in_file: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        use libradicl::rad_types::TagValue;
+        match tag_map.get(name) {
+            Some(TagValue::U8(x)) => Ok(Self::Int(*x as u64)),
+            Some(TagValue::U16(x)) => Ok(Self::Int(*x as u64)),
+            Some(TagValue::U32(x)) => Ok(Self::Int(*x as u64)),
+            Some(TagValue::U64(x)) => Ok(Self::Int(*x)),
+            Some(TagValue::String(x)) => Ok(Self::Str(x.clone())),
+            Some(tv) => bail!(
+                "the file-level tag {} of {} has the value {:?}, which can't be used to order the inputs",
+                name,
+                in_file.display(),
+                tv
+            ),
+            None => bail!(
+                "{} has no file-level tag {} by which to order it",
+                in_file.display(),
+                name
+            ),
+        }
+    }
+}
+
+/// Returns `true` if the tags described by `a` and `b` have the same names
+/// and types, regardless of the order in which they appear.
+fn tag_sections_equivalent(
+    a: &libradicl::rad_types::TagSection,
+    b: &libradicl::rad_types::TagSection,
+) -> bool {
+    let mut a_tags: Vec<_> = a.tags.iter().map(|td| (&td.name, &td.typeid)).collect();
+    let mut b_tags: Vec<_> = b.tags.iter().map(|td| (&td.name, &td.typeid)).collect();
+    a_tags.sort_by_key(|(name, _)| *name);
+    b_tags.sort_by_key(|(name, _)| *name);
+    a_tags == b_tags
+}
+
+/// Returns `true` if the records of RAD files with preludes `a` and `b`
+/// can be concatenated. This compares only the `ref_count`, `ref_names`,
+/// `is_paired` and tag types, and ignores `num_chunks`. The file-level
+/// tags may appear in any order, but the read and alignment-level tags
+/// must appear in the same order, since they determine the layout of the
+/// records themselves.
+pub fn preludes_compatible(
+    a: &libradicl::header::RadPrelude,
+    b: &libradicl::header::RadPrelude,
+) -> bool {
+    let same_typeids = |x: &libradicl::rad_types::TagSection,
+                        y: &libradicl::rad_types::TagSection| {
+        x.tags.len() == y.tags.len()
+            && x.tags
+                .iter()
+                .zip(y.tags.iter())
+                .all(|(xt, yt)| xt.name == yt.name && xt.typeid == yt.typeid)
+    };
+    a.hdr.ref_count == b.hdr.ref_count
+        && a.hdr.ref_names == b.hdr.ref_names
+        && a.hdr.is_paired == b.hdr.is_paired
+        && tag_sections_equivalent(&a.file_tags, &b.file_tags)
+        && same_typeids(&a.read_tags, &b.read_tags)
+        && same_typeids(&a.aln_tags, &b.aln_tags)
+}
+
+/// Returns `true` if every file-level tag described by `prelude` has the
+/// same value in `a` and `b`, regardless of the order of the tags.
+pub fn file_tag_values_equal(
+    prelude: &libradicl::header::RadPrelude,
+    a: &libradicl::rad_types::TagMap,
+    b: &libradicl::rad_types::TagMap,
+) -> bool {
+    prelude
+        .file_tags
+        .tags
+        .iter()
+        .all(|td| a.get(&td.name) == b.get(&td.name))
+}
+
+/// Build the map from the reference IDs of the file with the given
+/// `prelude` to the canonical reference IDs given by `canonical_ids`. It
+/// is an error if any reference of this file is not among the canonical
+/// references.
+fn build_ref_id_map(
+    canonical_ids: &HashMap<&str, u32>,
+    prelude: &libradicl::header::RadPrelude,
+    in_file: &std::path::Path,
+) -> anyhow::Result<Vec<u32>> {
+    prelude
+        .hdr
+        .ref_names
+        .iter()
+        .map(|name| match canonical_ids.get(name.as_str()) {
+            Some(&id) => Ok(id),
+            None => bail!(
+                "reference {} of {} does not appear in the first input file",
+                name,
+                in_file.display()
+            ),
+        })
+        .collect()
+}
+
+/// Copy the chunks of `ifile` verbatim to `owriter`, dropping those
+/// that contain no records if `prune_empty` is set.
+fn copy_chunks<R: BufRead, W: Write>(
+    ifile: &mut R,
+    owriter: &mut W,
+    prune_empty: bool,
+) -> anyhow::Result<CopyCounts> {
+    let mut copied = CopyCounts::default();
+    while libradicl::utils::has_data_left(ifile)? {
+        let (num_bytes, num_rec) = read_chunk_header(ifile)?;
+        count_chunk_read(num_rec as u64);
+        let body_bytes = (num_bytes as u64).saturating_sub(8);
+        if prune_empty && num_rec == 0 {
+            skip_bytes(ifile, body_bytes)?;
+            continue;
+        }
+        owriter.write_all(&num_bytes.to_le_bytes())?;
+        owriter.write_all(&num_rec.to_le_bytes())?;
+        let copied_body = std::io::copy(&mut ifile.by_ref().take(body_bytes), owriter)?;
+        if copied_body < body_bytes {
+            bail!(
+                "expected a chunk of {} bytes, but only {} bytes remained",
+                num_bytes,
+                copied_body + 8
+            );
+        }
+        copied.num_records += num_rec as u64;
+        copied.num_chunks += 1;
+        copied.num_bytes += num_bytes as u64;
+    }
+    Ok(copied)
+}
+
+/// Copy the chunks of `ifile` (the input at `path`) to `owriter`,
+/// rewriting the references of every record through `id_map`. Each input
+/// chunk produces exactly one output chunk, unless it is empty and
+/// `prune_empty` is set. It is an error if a record aligns to a reference
+/// that isn't in `id_map` (i.e. in the header of the input).
+fn copy_remapped_chunks<T: SupportedRecord, R: BufRead, W: Write>(
+    path: &std::path::Path,
+    prelude: &libradicl::header::RadPrelude,
+    id_map: &[u32],
+    ifile: &mut R,
+    owriter: &mut W,
+    prune_empty: bool,
+) -> anyhow::Result<CopyCounts> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    let encoder = RecordEncoder::from_prelude::<T>(prelude)?;
+    let mut chunk_buf = ChunkBuffer::new();
+    let mut copied = CopyCounts::default();
+    let mut chunk_num = 0_usize;
+    while libradicl::utils::has_data_left(ifile)? {
+        let mut chunk = decoder.read_chunk(ifile)?;
+        chunk_num += 1;
+        if prune_empty && chunk.reads.is_empty() {
+            continue;
+        }
+        for (i, r) in chunk.reads.iter_mut().enumerate() {
+            for ref_id in r.ref_ids_mut() {
+                let Some(&new_id) = id_map.get(*ref_id as usize) else {
+                    bail!(
+                        "record {} of chunk {} of {} aligns to reference {}, but its header lists only {} references",
+                        i,
+                        chunk_num - 1,
+                        path.display(),
+                        ref_id,
+                        id_map.len()
+                    );
+                };
+                *ref_id = new_id;
+            }
+            chunk_buf.push(r, chunk.tags.get(i), &encoder)?;
+        }
+        copied.num_records += chunk_buf.num_records() as u64;
+        copied.num_bytes += chunk_buf.write_chunk(owriter)?;
+        copied.num_chunks += 1;
+    }
+    Ok(copied)
+}
+
+/// Copies the chunks of an input with [copy_remapped_chunks]
+struct CopyRemappedChunks<'a, R: BufRead, W: Write> {
+    path: &'a std::path::Path,
+    prelude: &'a libradicl::header::RadPrelude,
+    id_map: &'a [u32],
+    ifile: &'a mut R,
+    owriter: &'a mut W,
+    prune_empty: bool,
+}
+
+impl<R: BufRead, W: Write> RecordTypeVisitor for CopyRemappedChunks<'_, R, W> {
+    type Output = CopyCounts;
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<CopyCounts> {
+        copy_remapped_chunks::<T, _, _>(
+            self.path,
+            self.prelude,
+            self.id_map,
+            self.ifile,
+            self.owriter,
+            self.prune_empty,
+        )
+    }
 }
 
 pub fn cat(cat_opts: &CatOpts) -> anyhow::Result<()> {
-    if cat_opts.inputs.len() <= 1 {
+    if cat_opts.inputs.len() <= 1 && !cat_opts.append {
         if let Some(input) = cat_opts.inputs.first() {
             warn!("You are attempting to concatenate a single input RAD file ({}) into a new output RAD file ({}); this operation does not make sense",
                 input.display(),
@@ -29,84 +379,424 @@ pub fn cat(cat_opts: &CatOpts) -> anyhow::Result<()> {
         return Ok(());
     }
 
-    let fname = cat_opts
-        .inputs
-        .first()
-        .expect("input should contain multiple RAD files");
+    // the size of each input is needed up front (for the progress bar),
+    // so none of them can be the standard input.
+    if cat_opts.inputs.iter().any(is_stdin) {
+        bail!("cat can't read its inputs from stdin; please provide RAD files with --inputs");
+    }
+    if let Some(url) = cat_opts.inputs.iter().find(|p| is_url(p)) {
+        bail!(
+            "cat can't read its inputs from URLs ({}); please download them first",
+            url.display()
+        );
+    }
 
-    let f = std::fs::File::open(fname)?;
-    let mut ifile = BufReader::new(f);
-    let mut first_prelude = libradicl::header::RadPrelude::from_bytes(&mut ifile)?;
-    let first_tag_map = first_prelude
-        .file_tags
-        .try_parse_tags_from_bytes(&mut ifile)?;
-    drop(ifile);
+    if cat_opts.append && cat_opts.inputs.contains(&cat_opts.output) {
+        bail!("{} can't be appended to itself", cat_opts.output.display());
+    }
+
+    let mut inputs = cat_opts.inputs.clone();
+    if cat_opts.sort_inputs {
+        sort_by_numeric_suffix(&mut inputs);
+    }
+    let summaries = if cat_opts.group_by_type {
+        cat_grouped_by_type(&inputs, cat_opts)?
+    } else {
+        cat_inputs(&inputs, &cat_opts.output, cat_opts)?
+    };
+    write_report(cat_opts, &summaries)
+}
+
+/// The last run of digits in the file name of `path`, as a number
+fn numeric_suffix(path: &std::path::Path) -> Option<u64> {
+    let name = path.file_name()?.to_string_lossy();
+    let end = name.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = name[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    name[start..end].parse().ok()
+}
+
+/// Sort `paths` by the last number in their file names, with the paths
+/// that have no number following the others, in lexicographic order.
+pub fn sort_by_numeric_suffix(paths: &mut [std::path::PathBuf]) {
+    paths.sort_by_cached_key(|p| {
+        let n = numeric_suffix(p);
+        (n.is_none(), n, p.clone())
+    });
+}
+
+/// The suffix of the output for the inputs of type `t` in `--group-by-type` mode
+fn type_suffix(t: &RadFileType) -> &'static str {
+    match t {
+        RadFileType::Bulk => "bulk",
+        RadFileType::SingleCell => "sc",
+        RadFileType::Unknown => "unknown",
+    }
+}
+
+/// Partition the inputs by their (detected) RAD file type, and
+/// concatenate each group into its own output, whose name is that of
+/// `--output` with the type inserted before the extension.
+fn cat_grouped_by_type(
+    inputs: &[std::path::PathBuf],
+    cat_opts: &CatOpts,
+) -> anyhow::Result<Vec<InputSummary>> {
+    let mut groups: Vec<(RadFileType, Vec<std::path::PathBuf>)> = Vec::new();
+    for in_file in inputs.iter() {
+        let t = open_and_parse(in_file)?.rad_type;
+        match groups.iter_mut().find(|(gt, _)| *gt == t) {
+            Some((_, files)) => files.push(in_file.clone()),
+            None => groups.push((t, vec![in_file.clone()])),
+        }
+    }
+
+    let mut summaries = Vec::with_capacity(inputs.len());
+    for (t, files) in groups.iter() {
+        let ext = match cat_opts.output.extension() {
+            Some(e) => format!("{}.{}", type_suffix(t), e.to_string_lossy()),
+            None => type_suffix(t).to_string(),
+        };
+        let output = cat_opts.output.with_extension(ext);
+        if !cat_opts.quiet {
+            info!(
+                "concatenating {} {:?} input files into {}",
+                files.len(),
+                t,
+                output.display()
+            );
+        }
+        summaries.extend(cat_inputs(files, &output, cat_opts)?);
+    }
+    Ok(summaries)
+}
+
+/// The number of chunks of the existing RAD file `output` to which the
+/// inputs are appended with `--append`. Its chunks are walked, so that a
+/// truncated (or otherwise malformed) file isn't appended to.
+fn existing_output_chunks(output: &std::path::Path) -> anyhow::Result<u64> {
+    if !output.exists() {
+        bail!(
+            "{} doesn't exist, so it can't be appended to",
+            output.display()
+        );
+    }
+    let mut reader = BufReader::new(std::fs::File::open(output)?);
+    if is_gzip_compressed(&mut reader)? || Compression::from_path(output) != Compression::None {
+        bail!(
+            "{} is compressed, so it can't be appended to",
+            output.display()
+        );
+    }
+    match check_file(output) {
+        Ok((_, summary)) => Ok(summary.num_chunks),
+        Err(e) => bail!("{} can't be appended to: {}", output.display(), e),
+    }
+}
+
+/// Concatenate the RAD files `inputs` into `output` (or, with `--append`,
+/// onto the end of it), as described by the rest of `cat_opts`, returning
+/// what was copied from each input.
+fn cat_inputs(
+    inputs: &[std::path::PathBuf],
+    output: &std::path::Path,
+    cat_opts: &CatOpts,
+) -> anyhow::Result<Vec<InputSummary>> {
+    // when appending, the existing output takes the place of the first
+    // input, as the file with whose prelude the inputs must be compatible.
+    let fname = if cat_opts.append {
+        output
+    } else {
+        inputs
+            .first()
+            .expect("input should contain at least one RAD file")
+            .as_path()
+    };
+    let existing_chunks = if cat_opts.append {
+        existing_output_chunks(output)?
+    } else {
+        0
+    };
+
+    // progress is tracked in terms of the bytes read from the input files
+    // (which, for compressed inputs, are the compressed bytes).
+    let mut total_bytes = 0_u64;
+    for in_file in inputs.iter() {
+        total_bytes += std::fs::metadata(in_file)?.len();
+    }
+    let pbar = byte_progress_bar(total_bytes, cat_opts.quiet);
+    let open_input = |path: &std::path::Path| -> anyhow::Result<ParsedRad> {
+        let mut parsed =
+            ParsedRad::from_reader(rad_reader(pbar.wrap_read(std::fs::File::open(path)?))?)?;
+        if !libradicl::utils::has_data_left(&mut parsed.reader)? {
+            warn!(
+                "{} contains no chunks; it contributes no records to {}",
+                path.display(),
+                output.display()
+            );
+        }
+        Ok(parsed)
+    };
 
-    let mut total_chunks = first_prelude.hdr.num_chunks;
+    // each input is opened, and its prelude parsed, only once; the readers
+    // are kept (positioned at the first chunk) until the output prelude,
+    // which needs the total number of chunks, has been written.
+    let ParsedRad {
+        reader: first_ifile,
+        prelude: mut first_prelude,
+        tag_map: first_tag_map,
+        rad_type: first_type,
+        ..
+    } = if cat_opts.append {
+        open_and_parse(output)?
+    } else {
+        open_input(fname)?
+    };
 
-    for in_file in cat_opts.inputs.iter().skip(1) {
-        let f = std::fs::File::open(in_file)?;
-        let mut ifile = BufReader::new(f);
-        let new_prelude = libradicl::header::RadPrelude::from_bytes(&mut ifile)?;
-        let new_tag_map = new_prelude
+    let mut total_chunks = if cat_opts.append {
+        existing_chunks
+    } else {
+        first_prelude.hdr.num_chunks
+    };
+    let sort_tag = cat_opts.merge_sorted.as_deref();
+    let sort_key = |tag_map: &libradicl::rad_types::TagMap, in_file: &std::path::Path| {
+        sort_tag
+            .map(|name| SortKey::from_tag_map(tag_map, name, in_file))
+            .transpose()
+    };
+    // the values of the file-level tags must match, except for that of
+    // the tag by which the inputs are ordered.
+    let tag_values_match = |a: &libradicl::rad_types::TagMap, b: &libradicl::rad_types::TagMap| {
+        first_prelude
             .file_tags
-            .try_parse_tags_from_bytes(&mut ifile)?;
+            .tags
+            .iter()
+            .filter(|td| Some(td.name.as_str()) != sort_tag)
+            .all(|td| a.get(&td.name) == b.get(&td.name))
+    };
+    let first_key = sort_key(&first_tag_map, fname)?;
+    let mut other_inputs = Vec::with_capacity(inputs.len() - 1);
+
+    let num_reference_inputs = if cat_opts.append { 0 } else { 1 };
+    for in_file in inputs.iter().skip(num_reference_inputs) {
+        let ParsedRad {
+            reader: ifile,
+            prelude: new_prelude,
+            tag_map: new_tag_map,
+            rad_type: new_type,
+            ..
+        } = open_input(in_file)?;
 
-        if (new_prelude == first_prelude) && (first_tag_map == new_tag_map) {
+        // records of different types have different layouts, so they can
+        // never be mixed, even if the preludes happen to match.
+        if new_type != first_type {
+            error!(
+                "{} appears to be a {:?} RAD file, but {} appears to be a {:?} RAD file; cannot proceed",
+                in_file.display(),
+                new_type,
+                fname.display(),
+                first_type
+            );
+            bail!("Input RAD files are of different types.");
+        }
+        // nor can paired and unpaired records, whatever else is relaxed
+        if new_prelude.hdr.is_paired != first_prelude.hdr.is_paired {
+            let paired = |p: &libradicl::header::RadPrelude| {
+                if p.hdr.is_paired != 0 {
+                    "paired"
+                } else {
+                    "unpaired"
+                }
+            };
+            error!(
+                "{} holds {} records, but {} holds {} records; cannot proceed",
+                in_file.display(),
+                paired(&new_prelude),
+                fname.display(),
+                paired(&first_prelude)
+            );
+            bail!("Input RAD files mix paired and unpaired records.");
+        }
+
+        let compatible = if cat_opts.remap_refs {
+            // the references are checked when building the ID map
+            first_prelude.hdr.is_paired == new_prelude.hdr.is_paired
+                && tag_sections_equivalent(&first_prelude.file_tags, &new_prelude.file_tags)
+                && first_prelude.read_tags == new_prelude.read_tags
+                && first_prelude.aln_tags == new_prelude.aln_tags
+                && tag_values_match(&first_tag_map, &new_tag_map)
+        } else if cat_opts.force {
+            preludes_compatible(&first_prelude, &new_prelude)
+                && tag_values_match(&first_tag_map, &new_tag_map)
+        } else if sort_tag.is_some() {
+            (new_prelude == first_prelude) && tag_values_match(&first_tag_map, &new_tag_map)
+        } else {
+            (new_prelude == first_prelude) && (first_tag_map == new_tag_map)
+        };
+
+        if compatible {
             total_chunks += new_prelude.hdr.num_chunks;
+            let key = sort_key(&new_tag_map, in_file)?;
+            other_inputs.push((key, in_file.as_path(), ifile, Some(new_prelude)));
         } else {
             error!(
                 "The prelude or file-level tags for ({}) is incompatible with the prelude or file-level tags for ({}); cannot proceed",
-                cat_opts.inputs.first().unwrap().display(),
+                fname.display(),
                 in_file.display()
             );
             bail!("Incompatible input RAD files.");
         }
     }
 
-    info!("All inputs had compatible preludes; continuing with merge!");
-    info!("total chunks = {}", total_chunks);
+    let rad_type = if cat_opts.remap_refs {
+        resolve_rad_type(
+            cat_opts.rad_type.as_ref(),
+            &first_prelude,
+            &first_tag_map,
+            fname,
+        )?
+    } else {
+        RadFileType::Unknown
+    };
+    let canonical_ids: HashMap<&str, u32> = first_prelude
+        .hdr
+        .ref_names
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.as_str(), i as u32))
+        .collect();
+
+    if !cat_opts.quiet {
+        info!("All inputs had compatible preludes; continuing with merge!");
+        info!("total chunks = {}", total_chunks);
+    }
+
+    // a compressed output can't be seeked, so if the chunks kept are only
+    // known once they've all been copied, the number is left unknown.
+    // an output that is appended to is known to be uncompressed, and its
+    // number of chunks is always rewritten once the new chunks are copied.
+    let compression = Compression::from_path(output);
+    let recount_chunks =
+        (cat_opts.prune_empty || cat_opts.append) && compression == Compression::None;
+    if cat_opts.prune_empty && compression != Compression::None {
+        warn!(
+            "{} is compressed, so the number of chunks kept with --prune-empty can't be recorded in its prelude",
+            output.display()
+        );
+        first_prelude.hdr.num_chunks = 0;
+    } else {
+        first_prelude.hdr.num_chunks = total_chunks;
+    }
+
+    let mut owriter = if cat_opts.append {
+        // the new chunks follow the existing ones
+        let ofile = std::fs::OpenOptions::new().append(true).open(output)?;
+        compressed_writer(buffered_writer(ofile), compression)?
+    } else {
+        let ofile = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(output)?;
+        let mut owriter = compressed_writer(buffered_writer(ofile), compression)?;
 
-    first_prelude.hdr.num_chunks = total_chunks;
+        // write the output prelude with the correct number of chunks.
+        first_prelude
+            .write(&mut owriter)
+            .expect("cannot write output prelude to file");
 
-    let ofile = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&cat_opts.output)?;
-    let mut owriter = BufWriter::new(ofile);
+        first_tag_map
+            .write_values(&mut owriter)
+            .expect("cannot write values of file-level tagl map to output file");
+        owriter
+    };
 
-    // write the output prelude with the correct number of chunks.
-    first_prelude
-        .write(&mut owriter)
-        .expect("cannot write output prelude to file");
+    // the first input is copied like the others, but as it defines the
+    // canonical reference order, its records never need to be remapped.
+    // When appending, the existing output plays its part, and isn't copied.
+    let mut all_inputs = Vec::with_capacity(inputs.len());
+    if !cat_opts.append {
+        all_inputs.push((first_key, fname, first_ifile, None));
+    }
+    all_inputs.extend(other_inputs);
+    if sort_tag.is_some() {
+        // every chunk of an input has the same key, so merging the chunks
+        // of the inputs by key amounts to (stably) ordering the inputs.
+        all_inputs.sort_by(|a, b| a.0.cmp(&b.0));
+    }
 
-    first_tag_map
-        .write_values(&mut owriter)
-        .expect("cannot write values of file-level tagl map to output file");
+    let mut summaries = Vec::with_capacity(inputs.len());
+    let mut report_copy =
+        |in_file: &std::path::Path, copy_res: anyhow::Result<CopyCounts>| -> anyhow::Result<()> {
+            if let Ok(copied) = copy_res {
+                summaries.push(InputSummary {
+                    file: in_file.to_path_buf(),
+                    output: output.to_path_buf(),
+                    copied,
+                });
+                if !cat_opts.quiet {
+                    info!(
+                        "copied {} bytes of record chunks from {} into {}.",
+                        copied.num_bytes,
+                        in_file.display(),
+                        output.display()
+                    );
+                }
+                Ok(())
+            } else {
+                bail!(
+                    "Failed to copy record chunks from {} to {}; error {:?}",
+                    in_file.display(),
+                    output.display(),
+                    copy_res
+                );
+            }
+        };
 
-    for in_file in cat_opts.inputs.iter() {
-        let f = std::fs::File::open(in_file)?;
-        let mut ifile = BufReader::new(f);
-        let prelude = libradicl::header::RadPrelude::from_bytes(&mut ifile)?;
-        let _tag_map = prelude.file_tags.try_parse_tags_from_bytes(&mut ifile)?;
-        let copy_res = std::io::copy(&mut ifile, &mut owriter);
-        if let Ok(copied_bytes) = copy_res {
-            info!(
-                "copied {} bytes of record chunks from {} into {}.",
-                copied_bytes,
-                in_file.display(),
-                &cat_opts.output.display()
-            );
+    for (_, in_file, mut ifile, prelude) in all_inputs {
+        let copy_res = if let (true, Some(prelude)) = (cat_opts.remap_refs, prelude) {
+            let id_map = build_ref_id_map(&canonical_ids, &prelude, in_file)?;
+            with_record_type(
+                &rad_type,
+                CopyRemappedChunks {
+                    path: in_file,
+                    prelude: &prelude,
+                    id_map: &id_map,
+                    ifile: &mut ifile,
+                    owriter: &mut owriter,
+                    prune_empty: cat_opts.prune_empty,
+                },
+            )
         } else {
-            bail!(
-                "Failed to copy record chunks from {} to {}; error {:?}",
-                in_file.display(),
-                &cat_opts.output.display(),
-                copy_res
-            );
-        }
+            copy_chunks(&mut ifile, &mut owriter, cat_opts.prune_empty)
+        };
+        report_copy(in_file, copy_res)?;
+    }
+    pbar.finish();
+    owriter.finish()?;
+    drop(owriter);
+    if cat_opts.prune_empty || cat_opts.append {
+        total_chunks = existing_chunks + summaries.iter().map(|s| s.copied.num_chunks).sum::<u64>();
+    }
+    if recount_chunks {
+        // the chunks kept are only known once they've all been copied.
+        let mut ofile = std::fs::OpenOptions::new().write(true).open(output)?;
+        rewrite_num_chunks(&mut ofile, &first_prelude, total_chunks)?;
     }
 
-    Ok(())
+    let (verb, prep) = if cat_opts.append {
+        ("appended", "onto")
+    } else {
+        ("concatenated", "into")
+    };
+    info!(
+        "{} {} input files {} {}: {} chunks, {} bytes of record chunks",
+        verb,
+        inputs.len(),
+        prep,
+        output.display(),
+        total_chunks,
+        summaries.iter().map(|s| s.copied.num_bytes).sum::<u64>()
+    );
+    Ok(summaries)
 }
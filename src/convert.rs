@@ -0,0 +1,151 @@
+use anyhow::bail;
+use clap::Parser;
+use libradicl::rad_types::{TagMap, TagValue};
+use libradicl::record::AlevinFryReadRecord;
+use std::io::{BufWriter, Write};
+use tracing::info;
+
+use crate::record::{ChunkBuffer, RecordDecoder, RecordEncoder};
+use crate::utils::{open_and_parse, rewrite_num_chunks, ParsedRad};
+use crate::view::RadFileType;
+
+/// options relevant to converting the barcode length of a single-cell RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ConvertOpts {
+    /// the input (single-cell) RAD file to convert
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the output RAD file in which the converted records will be written
+    #[arg(short, long, required = true)]
+    pub output: std::path::PathBuf,
+
+    /// the new barcode length (in bases); shorter barcodes are left-padded
+    /// with `A`, and longer barcodes have their leading bases removed
+    #[arg(long, required = true)]
+    pub cblen: usize,
+
+    /// allow truncating barcodes even when the removed bases are not all
+    /// `A` (i.e. when barcode information would be lost)
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// The value of the integer tag `tv` replaced by `v`, keeping its type
+fn with_int_value(name: &str, tv: &TagValue, v: u64) -> anyhow::Result<TagValue> {
+    Ok(match tv {
+        TagValue::U8(_) => TagValue::U8(u8::try_from(v)?),
+        TagValue::U16(_) => TagValue::U16(u16::try_from(v)?),
+        TagValue::U32(_) => TagValue::U32(u32::try_from(v)?),
+        TagValue::U64(_) => TagValue::U64(v),
+        _ => bail!("file-level tag {} is not an integer ({:?})", name, tv),
+    })
+}
+
+pub fn convert(convert_opts: &ConvertOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader: mut ifile,
+        mut prelude,
+        tag_map,
+        rad_type,
+        ..
+    } = open_and_parse(&convert_opts.input)?;
+    if rad_type != RadFileType::SingleCell {
+        bail!(
+            "{} is not a single-cell RAD file; only single-cell barcodes can be converted",
+            convert_opts.input.display()
+        );
+    }
+
+    let old_cblen: u64 = tag_map
+        .get("cblen")
+        .expect("tag map must contain \"cblen\" value")
+        .try_into()?;
+    let old_cblen = old_cblen as usize;
+    let new_cblen = convert_opts.cblen;
+
+    // barcodes are 2-bit encoded, so they must fit in the width of the
+    // barcode field of the records.
+    let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(&prelude)?;
+    let bc_bytes = encoder.context().bc_bytes;
+    if new_cblen == 0 || 2 * new_cblen > 8 * bc_bytes {
+        bail!(
+            "a barcode of length {} can't be stored in the {}-byte barcode field of {}",
+            new_cblen,
+            bc_bytes,
+            convert_opts.input.display()
+        );
+    }
+
+    // the file-level tags are copied, except for the new barcode length
+    let mut new_tag_map = TagMap::with_keyset(&prelude.file_tags.tags);
+    for td in prelude.file_tags.tags.iter() {
+        let tv = tag_map
+            .get(&td.name)
+            .expect("tag map must contain a value for each file-level tag");
+        let new_tv = if td.name == "cblen" {
+            with_int_value(&td.name, tv, new_cblen as u64)?
+        } else {
+            tv.clone()
+        };
+        new_tag_map.add(new_tv);
+    }
+
+    // as with `needletail`'s BitKmers, the first base of the barcode is in
+    // the most significant bits, so padding with `A` (0) leaves the encoded
+    // value unchanged, and truncation keeps the low-order bits.
+    let keep_mask = if new_cblen >= 32 {
+        u64::MAX
+    } else {
+        (1_u64 << (2 * new_cblen)) - 1
+    };
+    let truncating = new_cblen < old_cblen;
+
+    let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(&prelude)?;
+    prelude.hdr.num_chunks = 0;
+    let mut owriter = BufWriter::new(std::fs::File::create(&convert_opts.output)?);
+    prelude.write(&mut owriter)?;
+    new_tag_map.write_values(&mut owriter)?;
+
+    let mut chunk_buf = ChunkBuffer::new();
+    let mut num_chunks = 0_u64;
+    let mut num_lossy = 0_u64;
+    while libradicl::utils::has_data_left(&mut ifile)? {
+        let mut chunk = decoder.read_chunk(&mut ifile)?;
+        for (i, r) in chunk.reads.iter_mut().enumerate() {
+            if truncating && (r.bc & !keep_mask) != 0 {
+                if !convert_opts.force {
+                    // don't leave a truncated output behind
+                    drop(owriter);
+                    std::fs::remove_file(&convert_opts.output)?;
+                    bail!(
+                        "truncating barcodes from {} to {} bases would lose barcode information; pass --force to allow this",
+                        old_cblen,
+                        new_cblen
+                    );
+                }
+                num_lossy += 1;
+            }
+            r.bc &= keep_mask;
+            chunk_buf.push(r, chunk.tags.get(i), &encoder)?;
+        }
+        // each input chunk produces exactly one output chunk
+        chunk_buf.write_chunk(&mut owriter)?;
+        num_chunks += 1;
+    }
+
+    rewrite_num_chunks(&mut owriter, &prelude, num_chunks)?;
+    owriter.flush()?;
+    if num_lossy > 0 {
+        info!(
+            "{} barcodes lost information when truncated to {} bases",
+            num_lossy, new_cblen
+        );
+    }
+    info!(
+        "converted barcodes from {} to {} bases in {} chunks",
+        old_cblen, new_cblen, num_chunks
+    );
+    Ok(())
+}
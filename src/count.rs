@@ -0,0 +1,55 @@
+use clap::Parser;
+use tracing::warn;
+
+use crate::utils::{open_and_parse, walk_chunk_headers, CountingReader, ParsedRad};
+
+/// options relevant to counting the records in a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct CountOpts {
+    /// the input RAD file whose records should be counted
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// print the number of records in each chunk in addition to the total
+    #[arg(long)]
+    pub per_chunk: bool,
+}
+
+pub fn count(count_opts: &CountOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader,
+        prelude,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(&count_opts.input)?;
+    let mut ifile = CountingReader::with_position(reader, first_chunk_offset);
+
+    // walk the chunk headers only, without decoding the records.
+    let mut total_records = 0_u64;
+    let mut chunk_num = 0_u64;
+    let num_chunks = walk_chunk_headers(&mut ifile, |_, _, nrec| {
+        if count_opts.per_chunk {
+            println!("chunk {}\t{}", chunk_num, nrec);
+        }
+        chunk_num += 1;
+        total_records += nrec as u64;
+        true
+    })?;
+
+    if prelude.hdr.num_chunks > 0 && prelude.hdr.num_chunks != num_chunks {
+        warn!(
+            "the header of {} reports {} chunks, but {} were found",
+            count_opts.input.display(),
+            prelude.hdr.num_chunks,
+            num_chunks
+        );
+    }
+
+    if count_opts.per_chunk {
+        println!("total\t{}", total_records);
+    } else {
+        println!("{}", total_records);
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use anyhow::bail;
+use clap::Parser;
+use std::io::{BufRead, Write};
+
+use crate::record::{with_record_type, RecordDecoder, RecordTypeVisitor, SupportedRecord};
+use crate::utils::{open_and_parse, ParsedRad};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to computing the per-reference alignment counts of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct CoverageOpts {
+    /// the input RAD file whose alignments should be counted; if not
+    /// provided (or `-`), the RAD file is read from the standard input
+    #[arg(short, long, default_value = "-")]
+    pub input: std::path::PathBuf,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// print the name of each reference rather than its numeric ID
+    #[arg(long)]
+    pub use_ref_name: bool,
+}
+
+/// Count, for each reference, the number of alignments of the records of
+/// `ifile` to that reference. Every alignment of a multi-mapping record
+/// is counted.
+pub fn count_alignments<T: SupportedRecord, R: BufRead>(
+    prelude: &libradicl::header::RadPrelude,
+    ifile: &mut R,
+) -> anyhow::Result<Vec<u64>> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    let mut counts = vec![0_u64; prelude.hdr.ref_count as usize];
+    while libradicl::utils::has_data_left(ifile)? {
+        let chunk = decoder.read_chunk(ifile)?;
+        for r in chunk.reads.iter() {
+            for &ref_id in r.ref_ids() {
+                match counts.get_mut(ref_id as usize) {
+                    Some(c) => *c += 1,
+                    None => bail!(
+                        "encountered an alignment to reference {}, but the header lists only {} references",
+                        ref_id,
+                        counts.len()
+                    ),
+                }
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Counts the alignments of the records of a RAD file to each reference
+struct CountAlignments<'a>(&'a mut ParsedRad);
+
+impl RecordTypeVisitor for CountAlignments<'_> {
+    type Output = Vec<u64>;
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<Self::Output> {
+        count_alignments::<T, _>(&self.0.prelude, &mut self.0.reader)
+    }
+}
+
+pub fn coverage(coverage_opts: &CoverageOpts) -> anyhow::Result<()> {
+    let mut input = open_and_parse(&coverage_opts.input)?;
+    let rad_type = resolve_rad_type(
+        coverage_opts.rad_type.as_ref(),
+        &input.prelude,
+        &input.tag_map,
+        &coverage_opts.input,
+    )?;
+    let counts = with_record_type(&rad_type, CountAlignments(&mut input))?;
+    let prelude = input.prelude;
+
+    // most covered references first; ties are kept in reference order.
+    let mut order: Vec<usize> = (0..counts.len()).collect();
+    order.sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    for i in order {
+        if coverage_opts.use_ref_name {
+            writeln!(out, "{}\t{}", prelude.hdr.ref_names[i], counts[i])?;
+        } else {
+            writeln!(out, "{}\t{}", i, counts[i])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
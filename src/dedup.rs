@@ -0,0 +1,119 @@
+use anyhow::bail;
+use clap::Parser;
+use libradicl::record::AlevinFryReadRecord;
+use std::collections::HashSet;
+use std::io::{BufWriter, Write};
+use tracing::{info, warn};
+
+use crate::record::{ChunkBuffer, RecordDecoder, RecordEncoder};
+use crate::utils::{open_and_parse, rewrite_num_chunks, ParsedRad};
+use crate::view::RadFileType;
+
+/// options relevant to removing duplicate records from a single-cell RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct DedupOpts {
+    /// the input (single-cell) RAD file to deduplicate
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the output RAD file in which the deduplicated records will be written
+    #[arg(short, long, required = true)]
+    pub output: std::path::PathBuf,
+
+    /// consider records to be duplicates only if, in addition to their
+    /// barcode and UMI, they also align to the same set of references
+    #[arg(long)]
+    pub include_refs: bool,
+
+    /// warn when the number of distinct keys held in memory exceeds this
+    /// number (and each time it doubles after that)
+    #[arg(long, default_value_t = 50_000_000)]
+    pub warn_keys: usize,
+}
+
+/// The key identifying duplicate records; the reference set is empty
+/// unless `--include-refs` is given.
+type DedupKey = (u64, u64, Vec<u32>);
+
+fn dedup_key(r: &AlevinFryReadRecord, include_refs: bool) -> DedupKey {
+    let refs = if include_refs {
+        let mut refs = r.refs.clone();
+        refs.sort_unstable();
+        refs.dedup();
+        refs
+    } else {
+        Vec::new()
+    };
+    (r.bc, r.umi, refs)
+}
+
+pub fn dedup(dedup_opts: &DedupOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader: mut ifile,
+        mut prelude,
+        tag_map,
+        rad_type,
+        ..
+    } = open_and_parse(&dedup_opts.input)?;
+    if rad_type != RadFileType::SingleCell {
+        bail!(
+            "{} is not a single-cell RAD file; only single-cell records can be deduplicated",
+            dedup_opts.input.display()
+        );
+    }
+
+    let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(&prelude)?;
+    let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(&prelude)?;
+
+    // the true number of chunks is filled in once they've been written
+    prelude.hdr.num_chunks = 0;
+    let mut owriter = BufWriter::new(std::fs::File::create(&dedup_opts.output)?);
+    prelude.write(&mut owriter)?;
+    tag_map.write_values(&mut owriter)?;
+
+    let mut seen = HashSet::<DedupKey>::new();
+    let mut warn_at = dedup_opts.warn_keys;
+    let mut chunk_buf = ChunkBuffer::new();
+    let mut num_chunks = 0_u64;
+    let mut num_in = 0_u64;
+    let mut num_out = 0_u64;
+
+    while libradicl::utils::has_data_left(&mut ifile)? {
+        let chunk = decoder.read_chunk(&mut ifile)?;
+        for (i, r) in chunk.reads.iter().enumerate() {
+            num_in += 1;
+            // keep only the first record having each key
+            if !seen.insert(dedup_key(r, dedup_opts.include_refs)) {
+                continue;
+            }
+            if seen.len() > warn_at {
+                warn!(
+                    "holding more than {} distinct keys in memory while deduplicating",
+                    warn_at
+                );
+                warn_at = warn_at.saturating_mul(2);
+            }
+            chunk_buf.push(r, chunk.tags(i), &encoder)?;
+            num_out += 1;
+        }
+        // the records kept from each input chunk are written as one output
+        // chunk, so that the chunks of a collated file still hold one
+        // barcode each; chunks left with no records are dropped.
+        if !chunk_buf.is_empty() {
+            chunk_buf.write_chunk(&mut owriter)?;
+            num_chunks += 1;
+        }
+    }
+
+    rewrite_num_chunks(&mut owriter, &prelude, num_chunks)?;
+    owriter.flush()?;
+    info!(
+        "kept {} of {} records ({} duplicates removed) in {} chunks",
+        num_out,
+        num_in,
+        num_in - num_out,
+        num_chunks
+    );
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use anyhow::bail;
+use clap::Parser;
+use libradicl::record::{AlevinFryReadRecord, PiscemBulkReadRecord};
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+use tracing::{error, info};
+
+use crate::cat::preludes_compatible;
+use crate::record::{with_record_type, RecordDecoder, RecordTypeVisitor, SupportedRecord};
+use crate::utils::{is_stdin, open_and_parse, ParsedRad};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to comparing the records of two RAD files
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct DiffOpts {
+    /// the first RAD file to compare (`-` reads it from the standard input)
+    pub first: std::path::PathBuf,
+
+    /// the second RAD file to compare
+    pub second: std::path::PathBuf,
+
+    /// the type of the input RAD files; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags of the first input.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// print at most this many differing records (all of the records are
+    /// still compared, so that the number of differing records is exact)
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub max_diffs: usize,
+
+    /// compare the records of single-cell files positionally, rather than
+    /// by their barcodes and UMIs (bulk records are always compared
+    /// positionally)
+    #[arg(long)]
+    pub positional: bool,
+}
+
+/// A record whose fields can be compared to those of another record of
+/// the same type
+pub trait DiffRecord {
+    /// The name, and the values in `self` and `other`, of each field
+    /// that differs between the two records
+    fn field_diffs(&self, other: &Self) -> Vec<(&'static str, String, String)>;
+}
+
+/// Push the difference in the field `name` onto `diffs`, if there is one
+fn diff_field<T: PartialEq + std::fmt::Debug>(
+    diffs: &mut Vec<(&'static str, String, String)>,
+    name: &'static str,
+    a: &T,
+    b: &T,
+) {
+    if a != b {
+        diffs.push((name, format!("{:?}", a), format!("{:?}", b)));
+    }
+}
+
+impl DiffRecord for PiscemBulkReadRecord {
+    fn field_diffs(&self, other: &Self) -> Vec<(&'static str, String, String)> {
+        let mut diffs = Vec::new();
+        diff_field(&mut diffs, "frag_type", &self.frag_type, &other.frag_type);
+        diff_field(&mut diffs, "refs", &self.refs, &other.refs);
+        diff_field(&mut diffs, "dirs", &self.dirs, &other.dirs);
+        diff_field(&mut diffs, "positions", &self.positions, &other.positions);
+        diff_field(
+            &mut diffs,
+            "frag_lengths",
+            &self.frag_lengths,
+            &other.frag_lengths,
+        );
+        diffs
+    }
+}
+
+impl DiffRecord for AlevinFryReadRecord {
+    fn field_diffs(&self, other: &Self) -> Vec<(&'static str, String, String)> {
+        let mut diffs = Vec::new();
+        diff_field(&mut diffs, "bc", &self.bc, &other.bc);
+        diff_field(&mut diffs, "umi", &self.umi, &other.umi);
+        diff_field(&mut diffs, "refs", &self.refs, &other.refs);
+        diff_field(&mut diffs, "dirs", &self.dirs, &other.dirs);
+        diffs
+    }
+}
+
+/// The records of a RAD file, read a chunk at a time, as a single
+/// sequence (i.e. regardless of how they are divided into chunks)
+struct RecordStream<'a, T: SupportedRecord, R: BufRead> {
+    ifile: &'a mut R,
+    decoder: RecordDecoder<T>,
+    chunk: std::vec::IntoIter<T>,
+}
+
+impl<'a, T: SupportedRecord, R: BufRead> RecordStream<'a, T, R> {
+    fn new(prelude: &libradicl::header::RadPrelude, ifile: &'a mut R) -> anyhow::Result<Self> {
+        Ok(Self {
+            ifile,
+            decoder: RecordDecoder::from_prelude(prelude)?,
+            chunk: Vec::new().into_iter(),
+        })
+    }
+
+    /// The next record, or `None` once the input is exhausted
+    fn next_record(&mut self) -> anyhow::Result<Option<T>> {
+        loop {
+            if let Some(r) = self.chunk.next() {
+                return Ok(Some(r));
+            }
+            if !libradicl::utils::has_data_left(&mut *self.ifile)? {
+                return Ok(None);
+            }
+            let chunk = self.decoder.read_chunk(&mut *self.ifile)?;
+            self.chunk = chunk.reads.into_iter();
+        }
+    }
+}
+
+/// Compare the records of `first` and `second` positionally, printing
+/// the fields of (at most `max_diffs` of) the records that differ.
+/// Returns the number of differing records; if one input has more records
+/// than the other, each of its extra records counts as a difference.
+pub fn diff_records<T: SupportedRecord, R: BufRead>(
+    first_prelude: &libradicl::header::RadPrelude,
+    first: &mut R,
+    second_prelude: &libradicl::header::RadPrelude,
+    second: &mut R,
+    max_diffs: usize,
+) -> anyhow::Result<u64> {
+    let mut first_recs = RecordStream::<T, R>::new(first_prelude, first)?;
+    let mut second_recs = RecordStream::<T, R>::new(second_prelude, second)?;
+    let mut num_diffs = 0_u64;
+    let mut record_index = 0_u64;
+    loop {
+        let diffs = match (first_recs.next_record()?, second_recs.next_record()?) {
+            (None, None) => break,
+            (Some(a), Some(b)) => a.field_diffs(&b),
+            (Some(_), None) => vec![("record", "present".to_string(), "missing".to_string())],
+            (None, Some(_)) => vec![("record", "missing".to_string(), "present".to_string())],
+        };
+        if !diffs.is_empty() {
+            if num_diffs < max_diffs as u64 {
+                for (field, a, b) in diffs {
+                    println!("record {}\t{}\t{}\t{}", record_index, field, a, b);
+                }
+            }
+            num_diffs += 1;
+        }
+        record_index += 1;
+    }
+    info!("compared {} records", record_index);
+    Ok(num_diffs)
+}
+
+/// The alignments of a single-cell record, as (reference, orientation)
+/// pairs in sorted order, so that they compare equal regardless of the
+/// order in which they were written
+type SortedAlns = Vec<(u32, bool)>;
+
+fn sorted_alns(r: &AlevinFryReadRecord) -> SortedAlns {
+    let mut alns: SortedAlns = r.refs.iter().copied().zip(r.dirs.iter().copied()).collect();
+    alns.sort_unstable();
+    alns
+}
+
+/// Compare the records of the single-cell files `first` and `second` by
+/// key, i.e. by their barcodes and UMIs, so that neither the order of the
+/// records nor their chunking matters. Records having the same key are
+/// matched when they have the same (sorted) alignments; those left
+/// unmatched are paired up, in the order they were read, and their
+/// alignments printed as differences (at most `max_diffs` of them), and a
+/// record with no counterpart counts as a difference itself. The records
+/// of `first` are held in memory. Returns the number of differing records.
+pub fn diff_records_by_key<R: BufRead>(
+    first_prelude: &libradicl::header::RadPrelude,
+    first: &mut R,
+    second_prelude: &libradicl::header::RadPrelude,
+    second: &mut R,
+    max_diffs: usize,
+) -> anyhow::Result<u64> {
+    let mut first_recs = RecordStream::<AlevinFryReadRecord, R>::new(first_prelude, first)?;
+    let mut second_recs = RecordStream::<AlevinFryReadRecord, R>::new(second_prelude, second)?;
+
+    let mut unmatched_first = HashMap::<(u64, u64), Vec<SortedAlns>>::new();
+    let mut num_records = 0_u64;
+    while let Some(r) = first_recs.next_record()? {
+        unmatched_first
+            .entry((r.bc, r.umi))
+            .or_default()
+            .push(sorted_alns(&r));
+        num_records += 1;
+    }
+    let mut unmatched_second = HashMap::<(u64, u64), Vec<SortedAlns>>::new();
+    while let Some(r) = second_recs.next_record()? {
+        let key = (r.bc, r.umi);
+        let alns = sorted_alns(&r);
+        let matched = unmatched_first.get_mut(&key).and_then(|recs| {
+            recs.iter()
+                .position(|a| *a == alns)
+                .map(|i| recs.swap_remove(i))
+        });
+        if matched.is_none() {
+            unmatched_second.entry(key).or_default().push(alns);
+        }
+        num_records += 1;
+    }
+
+    // report the differences in the order of their keys
+    let keys: BTreeSet<(u64, u64)> = unmatched_first
+        .iter()
+        .chain(unmatched_second.iter())
+        .filter(|(_, recs)| !recs.is_empty())
+        .map(|(key, _)| *key)
+        .collect();
+    let mut num_diffs = 0_u64;
+    for key in keys {
+        let a = unmatched_first.remove(&key).unwrap_or_default();
+        let b = unmatched_second.remove(&key).unwrap_or_default();
+        for i in 0..a.len().max(b.len()) {
+            let diffs = match (a.get(i), b.get(i)) {
+                (Some(a), Some(b)) => {
+                    let mut diffs = Vec::new();
+                    diff_field(&mut diffs, "alns", a, b);
+                    diffs
+                }
+                (Some(_), None) => vec![("record", "present".to_string(), "missing".to_string())],
+                (None, Some(_)) => vec![("record", "missing".to_string(), "present".to_string())],
+                (None, None) => unreachable!("i is less than the length of a or b"),
+            };
+            if num_diffs < max_diffs as u64 {
+                for (field, a, b) in diffs {
+                    println!("bc {} umi {}\t{}\t{}\t{}", key.0, key.1, field, a, b);
+                }
+            }
+            num_diffs += 1;
+        }
+    }
+    info!("compared {} records by barcode and UMI", num_records);
+    Ok(num_diffs)
+}
+
+/// Compares the records of two RAD files
+struct DiffRecords<'a> {
+    first: &'a mut ParsedRad,
+    second: &'a mut ParsedRad,
+    max_diffs: usize,
+}
+
+impl RecordTypeVisitor for DiffRecords<'_> {
+    type Output = u64;
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<u64> {
+        diff_records::<T, _>(
+            &self.first.prelude,
+            &mut self.first.reader,
+            &self.second.prelude,
+            &mut self.second.reader,
+            self.max_diffs,
+        )
+    }
+}
+
+pub fn diff(diff_opts: &DiffOpts) -> anyhow::Result<()> {
+    if is_stdin(&diff_opts.first) && is_stdin(&diff_opts.second) {
+        bail!("at most one of the inputs to diff can be read from stdin");
+    }
+    let mut first = open_and_parse(&diff_opts.first)?;
+    let mut second = open_and_parse(&diff_opts.second)?;
+
+    // records can only be compared if they have the same layout and
+    // refer to the same references.
+    if !preludes_compatible(&first.prelude, &second.prelude) || first.rad_type != second.rad_type {
+        error!(
+            "The prelude of ({}) is incompatible with the prelude of ({}); cannot compare their records",
+            diff_opts.first.display(),
+            diff_opts.second.display()
+        );
+        bail!("Incompatible input RAD files.");
+    }
+
+    let rad_type = resolve_rad_type(
+        diff_opts.rad_type.as_ref(),
+        &first.prelude,
+        &first.tag_map,
+        &diff_opts.first,
+    )?;
+    let num_diffs = if rad_type == RadFileType::SingleCell && !diff_opts.positional {
+        diff_records_by_key(
+            &first.prelude,
+            &mut first.reader,
+            &second.prelude,
+            &mut second.reader,
+            diff_opts.max_diffs,
+        )?
+    } else {
+        with_record_type(
+            &rad_type,
+            DiffRecords {
+                first: &mut first,
+                second: &mut second,
+                max_diffs: diff_opts.max_diffs,
+            },
+        )?
+    };
+
+    if num_diffs > 0 {
+        error!(
+            "{} records differ between {} and {}",
+            num_diffs,
+            diff_opts.first.display(),
+            diff_opts.second.display()
+        );
+        bail!("RAD files differ");
+    }
+    info!("the records of the RAD files are identical");
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use anyhow::bail;
+use clap::Parser;
+use std::io::{BufWriter, Read, Write};
+use tracing::info;
+
+use crate::utils::{open_and_parse, read_chunk_header, ParsedRad};
+use crate::view::seek_to_chunk;
+
+/// options relevant to extracting a single chunk of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ExtractOpts {
+    /// the input RAD file from which the chunk should be extracted
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the (0-based) index of the chunk to extract
+    #[arg(short, long)]
+    pub chunk: usize,
+
+    /// the output RAD file, which will hold the prelude and file-level tags
+    /// of the input followed by only the extracted chunk
+    #[arg(short, long, required = true)]
+    pub output: std::path::PathBuf,
+}
+
+pub fn extract(extract_opts: &ExtractOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader: ifile,
+        mut prelude,
+        tag_map,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(&extract_opts.input)?;
+
+    // the chunk is copied verbatim, so the records never need decoding
+    let (mut ifile, _, chunk_offset) = seek_to_chunk(
+        &extract_opts.input,
+        ifile,
+        first_chunk_offset,
+        extract_opts.chunk,
+    )?;
+    if !libradicl::utils::has_data_left(&mut ifile)? {
+        bail!(
+            "chunk {} is past the end of {}",
+            extract_opts.chunk,
+            extract_opts.input.display()
+        );
+    }
+    let (num_bytes, num_rec) = read_chunk_header(&mut ifile)?;
+    // the size of a chunk includes its own 8-byte header
+    if num_bytes < 8 {
+        bail!(
+            "chunk {} (at byte offset {}) has an invalid size of {} bytes",
+            extract_opts.chunk,
+            chunk_offset,
+            num_bytes
+        );
+    }
+    let mut chunk_buf = vec![0u8; num_bytes as usize - 8];
+    ifile.read_exact(chunk_buf.as_mut_slice())?;
+
+    prelude.hdr.num_chunks = 1;
+    let ofile = std::fs::File::create(&extract_opts.output)?;
+    let mut owriter = BufWriter::new(ofile);
+    prelude.write(&mut owriter)?;
+    tag_map.write_values(&mut owriter)?;
+    owriter.write_all(&num_bytes.to_le_bytes())?;
+    owriter.write_all(&num_rec.to_le_bytes())?;
+    owriter.write_all(&chunk_buf)?;
+    owriter.flush()?;
+
+    info!(
+        "wrote chunk {} ({} records, {} bytes) of {} to {}",
+        extract_opts.chunk,
+        num_rec,
+        num_bytes,
+        extract_opts.input.display(),
+        extract_opts.output.display()
+    );
+    Ok(())
+}
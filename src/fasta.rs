@@ -0,0 +1,260 @@
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// The location of a single sequence within a FASTA file, as recorded by
+/// a `samtools faidx` index
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FaiEntry {
+    /// the length of the sequence (in bases)
+    len: u64,
+    /// the byte offset of the first base of the sequence
+    offset: u64,
+    /// the number of bases on each line
+    line_bases: u64,
+    /// the number of bytes on each line (i.e. including the line ending)
+    line_width: u64,
+}
+
+/// A memory-mapped (uncompressed) FASTA file, along with the index used
+/// to look up subsequences of its records by name. The index is read from
+/// the accompanying `.fai` file if there is one, and is otherwise built
+/// by scanning the file. Like `samtools faidx`, this requires that all of
+/// the lines of a record (except the last) have the same length.
+pub struct IndexedFasta {
+    map: memmap2::Mmap,
+    index: HashMap<String, FaiEntry>,
+}
+
+impl IndexedFasta {
+    /// The path of the `samtools faidx` index that accompanies `path`
+    fn fai_path(path: &Path) -> PathBuf {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".fai");
+        p.into()
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let f = std::fs::File::open(path)
+            .with_context(|| format!("couldn't open reference FASTA {}", path.display()))?;
+        // SAFETY: the map is only ever read; as with the RAD inputs, the
+        // file must not be truncated while it is mapped.
+        let map = unsafe { memmap2::Mmap::map(&f)? };
+        if map.starts_with(&[0x1f, 0x8b]) {
+            bail!(
+                "{} is compressed; the reference FASTA must be uncompressed",
+                path.display()
+            );
+        }
+        let fai = Self::fai_path(path);
+        let index = if fai.exists() {
+            read_fai(&fai)?
+        } else {
+            info!("{} has no .fai index; indexing it", path.display());
+            build_index(&map).with_context(|| format!("couldn't index {}", path.display()))?
+        };
+        Ok(Self { map, index })
+    }
+
+    /// Returns `true` if the FASTA file has a record named `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// The length of the record named `name`, if there is one
+    pub fn seq_len(&self, name: &str) -> Option<u64> {
+        self.index.get(name).map(|e| e.len)
+    }
+
+    /// The bases `[start, end)` of the record named `name`, or an error if
+    /// there is no such record or the interval extends past its end
+    pub fn fetch(&self, name: &str, start: u64, end: u64) -> anyhow::Result<Vec<u8>> {
+        let Some(e) = self.index.get(name) else {
+            bail!("reference {} is not in the reference FASTA", name);
+        };
+        if end > e.len || start > end {
+            bail!(
+                "interval [{}, {}) extends past the end of reference {} (of length {})",
+                start,
+                end,
+                name,
+                e.len
+            );
+        }
+        let mut seq = Vec::with_capacity((end - start) as usize);
+        let mut p = start;
+        while p < end {
+            // copy the rest of the line on which base `p` lies
+            let line_end = ((p / e.line_bases + 1) * e.line_bases).min(end);
+            let o = (e.offset + (p / e.line_bases) * e.line_width + p % e.line_bases) as usize;
+            let n = (line_end - p) as usize;
+            match self.map.get(o..o + n) {
+                Some(bases) => seq.extend_from_slice(bases),
+                None => bail!("the index of the reference FASTA doesn't match the file"),
+            }
+            p = line_end;
+        }
+        Ok(seq)
+    }
+}
+
+/// Read a `samtools faidx` index, whose lines give the name, length,
+/// offset, line bases and line width of each record.
+fn read_fai(path: &Path) -> anyhow::Result<HashMap<String, FaiEntry>> {
+    let f = std::fs::File::open(path)?;
+    let mut index = HashMap::new();
+    for (i, line) in BufReader::new(f).lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            bail!(
+                "line {} of {} doesn't have the 5 fields of a FASTA index",
+                i + 1,
+                path.display()
+            );
+        }
+        let parse = |s: &str| {
+            s.parse::<u64>()
+                .with_context(|| format!("line {} of {}", i + 1, path.display()))
+        };
+        let entry = FaiEntry {
+            len: parse(fields[1])?,
+            offset: parse(fields[2])?,
+            line_bases: parse(fields[3])?,
+            line_width: parse(fields[4])?,
+        };
+        index.insert(fields[0].to_string(), entry);
+    }
+    Ok(index)
+}
+
+/// Build the index of the FASTA file `data` (as `samtools faidx` would)
+fn build_index(data: &[u8]) -> anyhow::Result<HashMap<String, FaiEntry>> {
+    let mut index = HashMap::new();
+    // the name and entry of the record being scanned, and whether a line
+    // shorter than the others (which must be its last) has been seen
+    let mut current: Option<(String, FaiEntry)> = None;
+    let mut short_line = false;
+    let mut pos = 0_usize;
+    while pos < data.len() {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i + 1);
+        let line = &data[pos..line_end];
+        let bases = line.strip_suffix(b"\n").unwrap_or(line);
+        let bases = bases.strip_suffix(b"\r").unwrap_or(bases);
+        if let Some(header) = line.strip_prefix(b">") {
+            if let Some((name, entry)) = current.take() {
+                index.insert(name, entry);
+            }
+            // the name of a record is the first word of its header
+            let header = String::from_utf8_lossy(header);
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            current = Some((
+                name,
+                FaiEntry {
+                    len: 0,
+                    offset: line_end as u64,
+                    line_bases: 0,
+                    line_width: 0,
+                },
+            ));
+            short_line = false;
+        } else if let Some((ref name, ref mut e)) = current {
+            if !bases.is_empty() {
+                if e.line_bases == 0 {
+                    e.line_bases = bases.len() as u64;
+                    e.line_width = line.len() as u64;
+                } else if short_line || bases.len() as u64 > e.line_bases {
+                    bail!("the lines of record {} have differing lengths", name);
+                }
+                short_line |= (bases.len() as u64) < e.line_bases;
+                e.len += bases.len() as u64;
+            }
+        } else if !bases.is_empty() {
+            bail!("the file doesn't begin with a FASTA header");
+        }
+        pos = line_end;
+    }
+    if let Some((name, entry)) = current {
+        index.insert(name, entry);
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An indexed FASTA file holding `data`, indexed by [build_index]
+    fn indexed_fasta(data: &[u8]) -> IndexedFasta {
+        let mut map = memmap2::MmapMut::map_anon(data.len()).unwrap();
+        map.copy_from_slice(data);
+        let map = map.make_read_only().unwrap();
+        let index = build_index(&map).unwrap();
+        IndexedFasta { map, index }
+    }
+
+    const FASTA: &[u8] = b">chr1 the first\nACGTA\nCGTAC\nGT\n>chr2\nTTTT\nGG\n";
+
+    #[test]
+    fn index_matches_samtools_faidx() {
+        let fa = indexed_fasta(FASTA);
+        assert_eq!(
+            fa.index["chr1"],
+            FaiEntry {
+                len: 12,
+                offset: 16,
+                line_bases: 5,
+                line_width: 6,
+            }
+        );
+        assert_eq!(
+            fa.index["chr2"],
+            FaiEntry {
+                len: 6,
+                offset: 37,
+                line_bases: 4,
+                line_width: 5,
+            }
+        );
+        assert_eq!(fa.seq_len("chr1"), Some(12));
+        assert!(!fa.contains("chr1 the first"));
+    }
+
+    #[test]
+    fn fetch_spans_line_boundaries() {
+        let fa = indexed_fasta(FASTA);
+        assert_eq!(fa.fetch("chr1", 0, 12).unwrap(), b"ACGTACGTACGT");
+        assert_eq!(fa.fetch("chr1", 3, 11).unwrap(), b"TACGTACG");
+        assert_eq!(fa.fetch("chr1", 5, 10).unwrap(), b"CGTAC");
+        assert_eq!(fa.fetch("chr1", 4, 4).unwrap(), b"");
+        assert_eq!(fa.fetch("chr2", 2, 6).unwrap(), b"TTGG");
+    }
+
+    #[test]
+    fn fetch_handles_crlf_line_endings() {
+        let fa = indexed_fasta(b">chr1\r\nACG\r\nTAC\r\nG\r\n");
+        assert_eq!(fa.index["chr1"].line_width, 5);
+        assert_eq!(fa.fetch("chr1", 1, 7).unwrap(), b"CGTACG");
+    }
+
+    #[test]
+    fn fetch_rejects_bad_intervals() {
+        let fa = indexed_fasta(FASTA);
+        assert!(fa.fetch("chr1", 0, 13).is_err());
+        assert!(fa.fetch("chr1", 5, 4).is_err());
+        assert!(fa.fetch("chr3", 0, 1).is_err());
+    }
+
+    #[test]
+    fn index_rejects_ragged_records() {
+        assert!(build_index(b">chr1\nACG\nACGT\n").is_err());
+        assert!(build_index(b">chr1\nACGT\nAC\nACGT\n").is_err());
+        assert!(build_index(b"ACGT\n").is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::bail;
+use clap::{Parser, ValueEnum};
+use libradicl::record::AlevinFryReadRecord;
+use needletail::bitkmer::*;
+use std::collections::HashMap;
+use std::io::Write;
+use tracing::info;
+
+use crate::record::RecordDecoder;
+use crate::utils::{open_and_parse, ParsedRad};
+use crate::view::RadFileType;
+
+/// The field of a single-cell record whose values are counted
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum FreqField {
+    Barcode,
+    Umi,
+}
+
+/// options relevant to tabulating the barcodes or UMIs of a single-cell RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct FreqOpts {
+    /// the input (single-cell) RAD file; if not provided (or `-`), the RAD
+    /// file is read from the standard input
+    #[arg(short, long, default_value = "-")]
+    pub input: std::path::PathBuf,
+
+    /// the field whose values should be counted
+    #[arg(long, value_enum, default_value_t = FreqField::Barcode)]
+    pub field: FreqField,
+
+    /// print only the N most frequent values
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub top: usize,
+}
+
+pub fn freq(freq_opts: &FreqOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader: mut ifile,
+        prelude,
+        tag_map,
+        rad_type,
+        ..
+    } = open_and_parse(&freq_opts.input)?;
+    if rad_type != RadFileType::SingleCell {
+        bail!(
+            "{} is not a single-cell RAD file; only single-cell records have barcodes and UMIs",
+            freq_opts.input.display()
+        );
+    }
+
+    // the values are encoded with 2 bits per base, so their length is
+    // needed to decode them.
+    let len_tag = match freq_opts.field {
+        FreqField::Barcode => "cblen",
+        FreqField::Umi => "ulen",
+    };
+    let len: u64 = tag_map
+        .get(len_tag)
+        .unwrap_or_else(|| panic!("tag map must contain \"{}\" value", len_tag))
+        .try_into()?;
+
+    let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(&prelude)?;
+    let mut counts = HashMap::<u64, u64>::new();
+    let mut num_records = 0_u64;
+    while libradicl::utils::has_data_left(&mut ifile)? {
+        let chunk = decoder.read_chunk(&mut ifile)?;
+        for r in chunk.reads.iter() {
+            let key = match freq_opts.field {
+                FreqField::Barcode => r.bc,
+                FreqField::Umi => r.umi,
+            };
+            *counts.entry(key).or_insert(0) += 1;
+            num_records += 1;
+        }
+    }
+    info!(
+        "found {} distinct {:?} values among {} records",
+        counts.len(),
+        freq_opts.field,
+        num_records
+    );
+
+    // ties are broken by the encoded value, so the output is deterministic
+    let mut counts: Vec<(u64, u64)> = counts.into_iter().collect();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    for (key, count) in counts.into_iter().take(freq_opts.top) {
+        let mer: BitKmer = (key, len as u8);
+        writeln!(
+            out,
+            "{}\t{}",
+            String::from_utf8_lossy(&bitmer_to_bytes(mer)[..]),
+            count
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
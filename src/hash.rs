@@ -0,0 +1,152 @@
+use clap::Parser;
+use libradicl::record::{AlevinFryReadRecord, PiscemBulkReadRecord};
+use std::io::BufRead;
+use tracing::info;
+
+use crate::record::{with_record_type, RecordDecoder, RecordTypeVisitor, SupportedRecord};
+use crate::utils::{open_and_parse, ParsedRad};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to hashing the records of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct HashOpts {
+    /// the input RAD file whose records should be hashed; if not provided
+    /// (or `-`), the RAD file is read from the standard input
+    #[arg(short, long, default_value = "-")]
+    pub input: std::path::PathBuf,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+}
+
+/// A record that can be written in a canonical form, which is the same
+/// for records that differ only in the order of their alignments.
+pub trait CanonicalRecord {
+    /// Append the canonical form of this record to `buf`
+    fn write_canonical(&self, buf: &mut Vec<u8>);
+}
+
+impl CanonicalRecord for PiscemBulkReadRecord {
+    fn write_canonical(&self, buf: &mut Vec<u8>) {
+        let mut alns: Vec<_> = (0..self.refs.len())
+            .map(|i| {
+                (
+                    self.refs[i],
+                    self.positions[i],
+                    self.dirs[i],
+                    self.frag_lengths[i],
+                )
+            })
+            .collect();
+        alns.sort_unstable();
+        buf.push(self.frag_type);
+        buf.extend_from_slice(&(alns.len() as u64).to_le_bytes());
+        for (ref_id, pos, dir, flen) in alns {
+            buf.extend_from_slice(&ref_id.to_le_bytes());
+            buf.extend_from_slice(&pos.to_le_bytes());
+            buf.push(dir as u8);
+            buf.extend_from_slice(&flen.to_le_bytes());
+        }
+    }
+}
+
+impl CanonicalRecord for AlevinFryReadRecord {
+    fn write_canonical(&self, buf: &mut Vec<u8>) {
+        let mut alns: Vec<_> = (0..self.refs.len())
+            .map(|i| (self.refs[i], self.dirs[i]))
+            .collect();
+        alns.sort_unstable();
+        buf.extend_from_slice(&self.bc.to_le_bytes());
+        buf.extend_from_slice(&self.umi.to_le_bytes());
+        buf.extend_from_slice(&(alns.len() as u64).to_le_bytes());
+        for (ref_id, dir) in alns {
+            buf.extend_from_slice(&ref_id.to_le_bytes());
+            buf.push(dir as u8);
+        }
+    }
+}
+
+/// An order-independent digest of a multiset of records. Each record is
+/// hashed on its own, and the digests of the records are summed (as
+/// 256-bit little-endian integers, modulo 2^256), so the sum is the same
+/// whatever the order in which the records are added. The final digest is
+/// that of the number of records and this sum.
+#[derive(Debug, Default)]
+struct MultisetDigest {
+    sum: [u64; 4],
+    count: u64,
+}
+
+impl MultisetDigest {
+    fn add(&mut self, digest: &blake3::Hash) {
+        let mut carry = false;
+        for (i, limb) in digest.as_bytes().chunks_exact(8).enumerate() {
+            let limb = u64::from_le_bytes(limb.try_into().expect("limbs have 8 bytes"));
+            let (s, c1) = self.sum[i].overflowing_add(limb);
+            let (s, c2) = s.overflowing_add(carry as u64);
+            self.sum[i] = s;
+            carry = c1 || c2;
+        }
+        self.count += 1;
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.count.to_le_bytes());
+        for limb in self.sum.iter() {
+            hasher.update(&limb.to_le_bytes());
+        }
+        hasher.finalize()
+    }
+}
+
+/// Hash the canonical forms of the records of `ifile`, returning the
+/// digest and the number of records hashed. The digest depends only on
+/// the multiset of records, and neither on their order nor on how they
+/// are divided into chunks (see [MultisetDigest]).
+pub fn hash_records<T: SupportedRecord, R: BufRead>(
+    prelude: &libradicl::header::RadPrelude,
+    ifile: &mut R,
+) -> anyhow::Result<(blake3::Hash, u64)> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    let mut digest = MultisetDigest::default();
+    let mut rec_buf = Vec::<u8>::new();
+    while libradicl::utils::has_data_left(ifile)? {
+        let chunk = decoder.read_chunk(ifile)?;
+        for r in chunk.reads.iter() {
+            rec_buf.clear();
+            r.write_canonical(&mut rec_buf);
+            digest.add(&blake3::hash(&rec_buf));
+        }
+    }
+    Ok((digest.finalize(), digest.count))
+}
+
+/// Hashes the records of a RAD file
+struct HashRecords<'a>(&'a mut ParsedRad);
+
+impl RecordTypeVisitor for HashRecords<'_> {
+    type Output = (blake3::Hash, u64);
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<Self::Output> {
+        hash_records::<T, _>(&self.0.prelude, &mut self.0.reader)
+    }
+}
+
+pub fn hash(hash_opts: &HashOpts) -> anyhow::Result<()> {
+    let mut input = open_and_parse(&hash_opts.input)?;
+    let rad_type = resolve_rad_type(
+        hash_opts.rad_type.as_ref(),
+        &input.prelude,
+        &input.tag_map,
+        &hash_opts.input,
+    )?;
+    let (digest, num_records) = with_record_type(&rad_type, HashRecords(&mut input))?;
+
+    info!("hashed {} records", num_records);
+    println!("{}", digest.to_hex());
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use clap::Parser;
+use std::io;
+use std::io::Write;
+
+use crate::utils::{open_and_parse, ParsedRad};
+use crate::view::{
+    resolve_rad_type, write_mapped_records, ExtraRecordInfo, OutputFormat, RadFileType,
+    RecordEmitter,
+};
+
+/// options related to printing the first records of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct HeadOpts {
+    /// the input RAD file to print
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the number of records to print
+    #[arg(short, long, default_value_t = 10)]
+    pub num_records: usize,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// use the reference name rather than ID in the mapped records
+    #[arg(long)]
+    pub use_ref_name: bool,
+
+    /// the format in which the records should be written; `json` writes
+    /// an array of records and `ndjson` writes one record per line
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
+}
+
+pub fn head(head_opts: &HeadOpts) -> anyhow::Result<()> {
+    let mut output_stream: Box<dyn Write> = Box::new(io::stdout());
+
+    let ParsedRad {
+        reader: mut ifile,
+        prelude,
+        tag_map: file_tag_map,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(&head_opts.input)?;
+
+    let rad_type = resolve_rad_type(
+        head_opts.rad_type.as_ref(),
+        &prelude,
+        &file_tag_map,
+        &head_opts.input,
+    )?;
+
+    let mut extra_record_info = ExtraRecordInfo::new(&prelude, head_opts.format);
+    extra_record_info.use_ref_name = head_opts.use_ref_name;
+    extra_record_info.max_records = Some(head_opts.num_records);
+
+    if head_opts.format == OutputFormat::Json {
+        writeln!(output_stream, "[")?;
+    }
+    if head_opts.num_records > 0 {
+        let mut emitter = RecordEmitter::new(&extra_record_info);
+        write_mapped_records(
+            &rad_type,
+            &prelude,
+            &file_tag_map,
+            &mut extra_record_info,
+            &mut emitter,
+            &mut ifile,
+            first_chunk_offset,
+            &mut output_stream,
+        )?;
+        emitter.finish(&mut output_stream)?;
+    }
+    if head_opts.format == OutputFormat::Json {
+        writeln!(output_stream, "]")?;
+    }
+    output_stream.flush()?;
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use anyhow::bail;
+use clap::Parser;
+use needletail::bitkmer::bitmer_to_bytes;
+use std::io::{Read, Write};
+
+use crate::record::{RecordEncodingContext, ORIENTATION_MASK};
+use crate::utils::{open_and_parse, read_chunk_header, ParsedRad};
+use crate::view::{resolve_rad_type, seek_to_chunk, RadFileType};
+
+/// options relevant to printing the raw bytes of a single record
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct HexdumpOpts {
+    /// the input RAD file; if not provided (or `-`), the RAD file is read
+    /// from the standard input
+    #[arg(short, long, default_value = "-")]
+    pub input: std::path::PathBuf,
+
+    /// the (0-based) index of the chunk holding the record
+    #[arg(short, long)]
+    pub chunk: usize,
+
+    /// the (0-based) index of the record within its chunk
+    #[arg(short = 'n', long)]
+    pub record: usize,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+}
+
+/// A field of an encoded record: the offset (within the chunk) and length
+/// of its bytes, and a description of its value
+struct Field {
+    offset: usize,
+    len: usize,
+    label: String,
+}
+
+/// Reads the fields of the records of a chunk, by the layout of the
+/// records of its type, keeping track of where each field lies.
+struct FieldReader<'a> {
+    chunk: &'a [u8],
+    offset: usize,
+    fields: Vec<Field>,
+}
+
+impl FieldReader<'_> {
+    /// Read the next `len`-byte (at most 8) little-endian integer,
+    /// describing the field with `label(value)`
+    fn read_uint(&mut self, len: usize, label: impl Fn(u64) -> String) -> anyhow::Result<u64> {
+        let Some(bytes) = self.chunk.get(self.offset..self.offset + len) else {
+            bail!(
+                "a {}-byte field at offset {} runs past the end of the chunk ({} bytes)",
+                len,
+                self.offset,
+                self.chunk.len()
+            );
+        };
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(bytes);
+        let v = u64::from_le_bytes(buf);
+        self.fields.push(Field {
+            offset: self.offset,
+            len,
+            label: label(v),
+        });
+        self.offset += len;
+        Ok(v)
+    }
+
+    /// Read the next record, as a record of `rad_type`
+    fn read_record(
+        &mut self,
+        rad_type: &RadFileType,
+        enc_ctx: &RecordEncodingContext,
+        bc_len: Option<u8>,
+        umi_len: Option<u8>,
+    ) -> anyhow::Result<()> {
+        let num_alns = self.read_uint(4, |v| format!("num_alns = {}", v))?;
+        match rad_type {
+            RadFileType::Bulk => {
+                self.read_uint(1, |v| {
+                    format!(
+                        "frag_type = {} ({:?})",
+                        v,
+                        libradicl::rad_types::MappingType::from_u8(v as u8)
+                    )
+                })?;
+                for i in 0..num_alns {
+                    self.read_uint(4, |v| ref_label(i, v as u32))?;
+                    self.read_uint(4, |v| format!("aln {}: pos = {}", i, v))?;
+                    self.read_uint(2, |v| format!("aln {}: flen = {}", i, v))?;
+                }
+            }
+            RadFileType::SingleCell => {
+                if enc_ctx.bc_bytes == 0 || enc_ctx.umi_bytes == 0 {
+                    bail!("the read-level tags don't give the widths of the barcode and UMI");
+                }
+                self.read_uint(enc_ctx.bc_bytes, |v| bitmer_label("barcode", v, bc_len))?;
+                self.read_uint(enc_ctx.umi_bytes, |v| bitmer_label("umi", v, umi_len))?;
+                for i in 0..num_alns {
+                    self.read_uint(4, |v| ref_label(i, v as u32))?;
+                }
+            }
+            RadFileType::Unknown => bail!("Unknown RadFileType not supported yet"),
+        }
+        Ok(())
+    }
+}
+
+/// The description of the compressed reference ID `v` of alignment `i`
+fn ref_label(i: u64, v: u32) -> String {
+    let dir = if v & ORIENTATION_MASK != 0 {
+        "fw"
+    } else {
+        "rc"
+    };
+    format!("aln {}: ref = {} ({})", i, v & !ORIENTATION_MASK, dir)
+}
+
+/// The description of the 2-bit encoded sequence `v` of length `len`
+fn bitmer_label(name: &str, v: u64, len: Option<u8>) -> String {
+    match len {
+        Some(l) => format!(
+            "{} = {} ({})",
+            name,
+            v,
+            String::from_utf8_lossy(&bitmer_to_bytes((v, l)))
+        ),
+        None => format!("{} = {}", name, v),
+    }
+}
+
+pub fn hexdump(hexdump_opts: &HexdumpOpts) -> anyhow::Result<()> {
+    let ParsedRad {
+        reader: ifile,
+        prelude,
+        tag_map,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(&hexdump_opts.input)?;
+    let rad_type = resolve_rad_type(
+        hexdump_opts.rad_type.as_ref(),
+        &prelude,
+        &tag_map,
+        &hexdump_opts.input,
+    )?;
+    let enc_ctx = RecordEncodingContext::from_prelude(&prelude)?;
+    let seq_len = |name: &str| -> Option<u8> {
+        tag_map
+            .get(name)
+            .and_then(|tv| u64::try_from(tv).ok())
+            .map(|l| l as u8)
+    };
+
+    // the records are laid out by hand, rather than decoded, so that the
+    // bytes of a record that fails to decode can still be shown.
+    let (mut ifile, _, _) = seek_to_chunk(
+        &hexdump_opts.input,
+        ifile,
+        first_chunk_offset,
+        hexdump_opts.chunk,
+    )?;
+    if !libradicl::utils::has_data_left(&mut ifile)? {
+        bail!(
+            "chunk {} is past the end of {}",
+            hexdump_opts.chunk,
+            hexdump_opts.input.display()
+        );
+    }
+    let (nbytes, nrec) = read_chunk_header(&mut ifile)?;
+    if hexdump_opts.record >= nrec as usize {
+        bail!(
+            "chunk {} has {} records, so it has no record {}",
+            hexdump_opts.chunk,
+            nrec,
+            hexdump_opts.record
+        );
+    }
+    let mut chunk = vec![0u8; (nbytes as usize).max(8)];
+    chunk[0..4].copy_from_slice(&nbytes.to_le_bytes());
+    chunk[4..8].copy_from_slice(&nrec.to_le_bytes());
+    ifile.read_exact(&mut chunk[8..])?;
+
+    let mut reader = FieldReader {
+        chunk: &chunk,
+        offset: 8,
+        fields: Vec::new(),
+    };
+    for i in 0..hexdump_opts.record {
+        if let Err(e) = reader.read_record(&rad_type, &enc_ctx, None, None) {
+            bail!("couldn't lay out record {} of the chunk: {}", i, e);
+        }
+    }
+    reader.fields.clear();
+    let start = reader.offset;
+    let res = reader.read_record(&rad_type, &enc_ctx, seq_len("cblen"), seq_len("ulen"));
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    writeln!(
+        out,
+        "chunk {} ({} bytes, {} records), record {} ({:?}), starting at byte {} of the chunk",
+        hexdump_opts.chunk, nbytes, nrec, hexdump_opts.record, rad_type, start
+    )?;
+    for f in reader.fields.iter() {
+        let hex: Vec<String> = chunk[f.offset..f.offset + f.len]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        writeln!(out, "{:#08x}  {:<24}  {}", f.offset, hex.join(" "), f.label)?;
+    }
+    if res.is_err() {
+        // show what remains of the chunk where the next field should be
+        for (i, bytes) in chunk[reader.offset..].chunks(8).take(4).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let offset = reader.offset + 8 * i;
+            writeln!(
+                out,
+                "{:#08x}  {:<24}  (rest of the chunk)",
+                offset,
+                hex.join(" ")
+            )?;
+        }
+    }
+    out.flush()?;
+    if let Err(e) = res {
+        bail!(
+            "record {} of chunk {} can't be laid out: {}",
+            hexdump_opts.record,
+            hexdump_opts.chunk,
+            e
+        );
+    }
+    Ok(())
+}
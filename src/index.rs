@@ -0,0 +1,273 @@
+use anyhow::bail;
+use clap::Parser;
+use std::io::{BufReader, BufWriter, Read, Write};
+use tracing::{info, warn};
+
+use crate::utils::{buffered_reader, is_gzip_compressed, is_stdin, is_url, CountingReader};
+use crate::validate::walk_chunks_with;
+
+/// The magic bytes with which every chunk index file begins
+pub const INDEX_MAGIC: [u8; 4] = *b"RADI";
+/// The version of the chunk index format written by this version of radtk
+pub const INDEX_VERSION: u32 = 2;
+
+/// options relevant to building a chunk index for a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct IndexOpts {
+    /// the input RAD file to index (which must not be compressed)
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the file to which the index is written; defaults to the input path
+    /// with `.radi` appended
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
+}
+
+/// The location and size of a single chunk of a RAD file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkIndexEntry {
+    /// the byte offset at which the chunk (i.e. its header) begins
+    pub offset: u64,
+    pub nbytes: u32,
+    pub nrec: u32,
+}
+
+/// The table of the chunks of a RAD file. On disk, this is the magic
+/// bytes, the format version (`u32`), the length of the indexed RAD file
+/// (`u64`) and the number of chunks (`u64`), followed by the `offset`
+/// (`u64`), `nbytes` (`u32`) and `nrec` (`u32`) of each chunk, all
+/// little-endian.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkIndex {
+    /// the length, in bytes, of the RAD file when it was indexed, so that
+    /// an index left behind by an older version of the file can be detected
+    pub rad_file_len: u64,
+    pub entries: Vec<ChunkIndexEntry>,
+}
+
+impl ChunkIndex {
+    /// The path of the index that accompanies the RAD file at `input`
+    pub fn default_path(input: &std::path::Path) -> std::path::PathBuf {
+        let mut p = input.as_os_str().to_owned();
+        p.push(".radi");
+        p.into()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> anyhow::Result<()> {
+        w.write_all(&INDEX_MAGIC)?;
+        w.write_all(&INDEX_VERSION.to_le_bytes())?;
+        w.write_all(&self.rad_file_len.to_le_bytes())?;
+        w.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for e in self.entries.iter() {
+            w.write_all(&e.offset.to_le_bytes())?;
+            w.write_all(&e.nbytes.to_le_bytes())?;
+            w.write_all(&e.nrec.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn from_bytes<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC {
+            bail!("not a RAD chunk index (bad magic bytes)");
+        }
+        let mut buf4 = [0u8; 4];
+        let mut buf8 = [0u8; 8];
+        r.read_exact(&mut buf4)?;
+        let version = u32::from_le_bytes(buf4);
+        if version != INDEX_VERSION {
+            bail!(
+                "unsupported RAD chunk index version {} (expected {})",
+                version,
+                INDEX_VERSION
+            );
+        }
+        r.read_exact(&mut buf8)?;
+        let rad_file_len = u64::from_le_bytes(buf8);
+        r.read_exact(&mut buf8)?;
+        let num_chunks = u64::from_le_bytes(buf8);
+        let mut entries = Vec::new();
+        for _ in 0..num_chunks {
+            r.read_exact(&mut buf8)?;
+            let offset = u64::from_le_bytes(buf8);
+            r.read_exact(&mut buf4)?;
+            let nbytes = u32::from_le_bytes(buf4);
+            r.read_exact(&mut buf4)?;
+            let nrec = u32::from_le_bytes(buf4);
+            entries.push(ChunkIndexEntry {
+                offset,
+                nbytes,
+                nrec,
+            });
+        }
+        Ok(Self {
+            rad_file_len,
+            entries,
+        })
+    }
+
+    /// Read the index stored in the file at `path`
+    pub fn from_path(path: &std::path::Path) -> anyhow::Result<Self> {
+        let mut r = BufReader::new(std::fs::File::open(path)?);
+        Self::from_bytes(&mut r)
+    }
+
+    /// Read the index that accompanies the RAD file at `input`, if it has
+    /// one that can be used. An index that can't be read, or that was
+    /// built for a file of a different length (i.e. for an older version
+    /// of `input`), is ignored with a warning, so that the chunks are
+    /// located by walking their headers instead.
+    pub fn for_input(input: &std::path::Path) -> anyhow::Result<Option<Self>> {
+        let index_path = Self::default_path(input);
+        if is_stdin(input) || is_url(input) || !index_path.exists() {
+            return Ok(None);
+        }
+        let index = match Self::from_path(&index_path) {
+            Ok(index) => index,
+            Err(e) => {
+                warn!(
+                    "ignoring the chunk index {}, which can't be read ({:#}); rebuild it with `radtk index`",
+                    index_path.display(),
+                    e
+                );
+                return Ok(None);
+            }
+        };
+        let rad_file_len = std::fs::metadata(input)?.len();
+        if index.rad_file_len != rad_file_len {
+            warn!(
+                "ignoring the chunk index {}, which was built for a {}-byte file, but {} has {} bytes; rebuild it with `radtk index`",
+                index_path.display(),
+                index.rad_file_len,
+                input.display(),
+                rad_file_len
+            );
+            return Ok(None);
+        }
+        Ok(Some(index))
+    }
+}
+
+/// Build the chunk index of the (uncompressed) RAD file at `input`
+pub fn build_index(input: &std::path::Path) -> anyhow::Result<ChunkIndex> {
+    // the offsets are only useful if they can be seeked to
+    let mut reader = buffered_reader(std::fs::File::open(input)?);
+    if is_gzip_compressed(&mut reader)? {
+        bail!(
+            "{} is compressed; only uncompressed RAD files can be indexed",
+            input.display()
+        );
+    }
+    let mut reader = CountingReader::new(reader);
+    let prelude = libradicl::header::RadPrelude::from_bytes(&mut reader)?;
+    let _tag_map = prelude.file_tags.try_parse_tags_from_bytes(&mut reader)?;
+
+    let mut index = ChunkIndex {
+        rad_file_len: std::fs::metadata(input)?.len(),
+        ..Default::default()
+    };
+    walk_chunks_with(&mut reader, |offset, nbytes, nrec| {
+        index.entries.push(ChunkIndexEntry {
+            offset,
+            nbytes,
+            nrec,
+        })
+    })?;
+    Ok(index)
+}
+
+pub fn index(index_opts: &IndexOpts) -> anyhow::Result<()> {
+    if is_stdin(&index_opts.input) {
+        bail!("index can't read its input from stdin; please provide a RAD file with --input");
+    }
+    let index = build_index(&index_opts.input)?;
+    let output = index_opts
+        .output
+        .clone()
+        .unwrap_or_else(|| ChunkIndex::default_path(&index_opts.input));
+    let mut w = BufWriter::new(std::fs::File::create(&output)?);
+    index.write(&mut w)?;
+    w.flush()?;
+    info!(
+        "wrote the index of {} chunks of {} to {}",
+        index.entries.len(),
+        index_opts.input.display(),
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> ChunkIndex {
+        ChunkIndex {
+            rad_file_len: 1 << 33,
+            entries: vec![
+                ChunkIndexEntry {
+                    offset: 100,
+                    nbytes: 48,
+                    nrec: 3,
+                },
+                ChunkIndexEntry {
+                    offset: 148,
+                    nbytes: 8,
+                    nrec: 0,
+                },
+                ChunkIndexEntry {
+                    offset: (1 << 33) - 20,
+                    nbytes: 20,
+                    nrec: u32::MAX,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn index_round_trips() {
+        let index = index();
+        let mut buf = Vec::new();
+        index.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4 + 4 + 8 + 8 + 3 * (8 + 4 + 4));
+        assert_eq!(&buf[0..4], &INDEX_MAGIC);
+        let read = ChunkIndex::from_bytes(&mut &buf[..]).unwrap();
+        assert_eq!(read, index);
+    }
+
+    #[test]
+    fn empty_index_round_trips() {
+        let index = ChunkIndex::default();
+        let mut buf = Vec::new();
+        index.write(&mut buf).unwrap();
+        assert_eq!(ChunkIndex::from_bytes(&mut &buf[..]).unwrap(), index);
+    }
+
+    #[test]
+    fn index_with_bad_magic_or_version_is_rejected() {
+        let mut buf = Vec::new();
+        index().write(&mut buf).unwrap();
+
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = b'X';
+        assert!(ChunkIndex::from_bytes(&mut &bad_magic[..]).is_err());
+
+        let mut old_version = buf.clone();
+        old_version[4..8].copy_from_slice(&1_u32.to_le_bytes());
+        let err = ChunkIndex::from_bytes(&mut &old_version[..]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unsupported RAD chunk index version 1"));
+    }
+
+    #[test]
+    fn truncated_index_is_rejected() {
+        let mut buf = Vec::new();
+        index().write(&mut buf).unwrap();
+        buf.truncate(buf.len() - 1);
+        assert!(ChunkIndex::from_bytes(&mut &buf[..]).is_err());
+    }
+}
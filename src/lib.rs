@@ -0,0 +1,33 @@
+//! `radtk` is a toolkit for working with RAD files. Each of the
+//! sub-commands of the `radtk` program is implemented by a module of
+//! this library, exposing an options struct (e.g. [cat::CatOpts]) and an
+//! entry function taking those options (e.g. [cat::cat]), so that the
+//! same functionality can be used from other programs. The records of a
+//! RAD file can also be read directly, with [record::records].
+
+pub mod cat;
+pub mod convert;
+pub mod count;
+pub mod coverage;
+pub mod dedup;
+pub mod diff;
+pub mod extract;
+pub mod fasta;
+pub mod freq;
+pub mod hash;
+pub mod head;
+pub mod hexdump;
+pub mod index;
+pub mod record;
+pub mod sample;
+pub mod split;
+pub mod stats;
+pub mod utils;
+pub mod validate;
+pub mod view;
+
+pub use crate::cat::{cat, CatOpts};
+pub use crate::record::{records, RadRecord, Records};
+pub use crate::split::{split, SplitOpts};
+pub use crate::utils::{open_and_parse, ParsedRad};
+pub use crate::view::{view, ViewOpts};
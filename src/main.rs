@@ -1,12 +1,24 @@
 use clap::{Parser, Subcommand};
 use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
 
-mod cat;
-use crate::cat::CatOpts;
-mod view;
-use crate::view::ViewOpts;
-mod split;
-use crate::split::SplitOpts;
+use radtk::cat::{self, CatOpts};
+use radtk::convert::{self, ConvertOpts};
+use radtk::count::{self, CountOpts};
+use radtk::coverage::{self, CoverageOpts};
+use radtk::dedup::{self, DedupOpts};
+use radtk::diff::{self, DiffOpts};
+use radtk::extract::{self, ExtractOpts};
+use radtk::freq::{self, FreqOpts};
+use radtk::hash::{self, HashOpts};
+use radtk::head::{self, HeadOpts};
+use radtk::hexdump::{self, HexdumpOpts};
+use radtk::index::{self, IndexOpts};
+use radtk::sample::{self, SampleOpts};
+use radtk::split::{self, SplitOpts};
+use radtk::stats::{self, StatsOpts};
+use radtk::utils::{report_throughput, set_io_buffer_size, DEFAULT_IO_BUFFER_SIZE};
+use radtk::validate::{self, ValidateOpts};
+use radtk::view::{self, ViewOpts};
 
 /// testing out minimizer space suffix arrays
 #[derive(Debug, Parser)]
@@ -15,6 +27,16 @@ use crate::split::SplitOpts;
 pub struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// the size, in bytes, of the buffers used to read and write RAD files
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = DEFAULT_IO_BUFFER_SIZE)]
+    io_buffer_size: usize,
+
+    /// once the command has finished, log the time it took and the rates
+    /// at which it read chunks, records and bytes of its input(s) (before
+    /// any decompression) to stderr
+    #[arg(long, global = true)]
+    timing: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -25,6 +47,34 @@ pub enum Commands {
     View(ViewOpts),
     /// split an input RAD file into multiple output files
     Split(SplitOpts),
+    /// print summary statistics about a RAD file
+    Stats(StatsOpts),
+    /// print the first records of a RAD file
+    Head(HeadOpts),
+    /// count the mapped records in a RAD file (without decoding them)
+    Count(CountOpts),
+    /// check the structural integrity of a RAD file
+    Validate(ValidateOpts),
+    /// write a random sample of the records of a RAD file to a new RAD file
+    Sample(SampleOpts),
+    /// count the alignments to each reference in a RAD file
+    Coverage(CoverageOpts),
+    /// remove records with duplicate barcodes and UMIs from a single-cell RAD file
+    Dedup(DedupOpts),
+    /// rewrite a single-cell RAD file with a different barcode length
+    Convert(ConvertOpts),
+    /// write an index of the offsets and sizes of the chunks of a RAD file
+    Index(IndexOpts),
+    /// print a hash of the records of a RAD file that is independent of their chunking
+    Hash(HashOpts),
+    /// compare the records of two RAD files, printing the fields that differ
+    Diff(DiffOpts),
+    /// print the most frequent barcodes (or UMIs) of a single-cell RAD file
+    Freq(FreqOpts),
+    /// write a single chunk of a RAD file to its own RAD file
+    Extract(ExtractOpts),
+    /// print the bytes of a single record of a RAD file, labeled by field
+    Hexdump(HexdumpOpts),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -42,11 +92,33 @@ fn main() -> anyhow::Result<()> {
         .init();
 
     let args = Cli::parse();
+    set_io_buffer_size(args.io_buffer_size);
 
+    let start = std::time::Instant::now();
     match args.command {
         Commands::Cat(cat_opts) => cat::cat(&cat_opts)?,
         Commands::View(view_opts) => view::view(&view_opts)?,
-        Commands::Split(split_opts) => split::split(&split_opts)?,
+        Commands::Split(split_opts) => {
+            split::install_interrupt_handler();
+            split::split(&split_opts)?
+        }
+        Commands::Stats(stats_opts) => stats::stats(&stats_opts)?,
+        Commands::Head(head_opts) => head::head(&head_opts)?,
+        Commands::Count(count_opts) => count::count(&count_opts)?,
+        Commands::Validate(validate_opts) => validate::validate(&validate_opts)?,
+        Commands::Sample(sample_opts) => sample::sample(&sample_opts)?,
+        Commands::Coverage(coverage_opts) => coverage::coverage(&coverage_opts)?,
+        Commands::Dedup(dedup_opts) => dedup::dedup(&dedup_opts)?,
+        Commands::Convert(convert_opts) => convert::convert(&convert_opts)?,
+        Commands::Index(index_opts) => index::index(&index_opts)?,
+        Commands::Hash(hash_opts) => hash::hash(&hash_opts)?,
+        Commands::Diff(diff_opts) => diff::diff(&diff_opts)?,
+        Commands::Freq(freq_opts) => freq::freq(&freq_opts)?,
+        Commands::Extract(extract_opts) => extract::extract(&extract_opts)?,
+        Commands::Hexdump(hexdump_opts) => hexdump::hexdump(&hexdump_opts)?,
+    }
+    if args.timing {
+        report_throughput(start.elapsed());
     }
     Ok(())
 }
@@ -0,0 +1,1183 @@
+use anyhow::{anyhow, bail, Context};
+use libradicl::header::RadPrelude;
+use libradicl::rad_types::{RadAtomicId, RadFloatId, RadIntId, RadType, TagMap, TagValue};
+use libradicl::record::{
+    AlevinFryReadRecord, AlevinFryRecordContext, PiscemBulkReadRecord, PiscemBulkRecordContext,
+};
+use std::io::{BufRead, Write};
+
+use crate::diff::DiffRecord;
+use crate::hash::CanonicalRecord;
+use crate::stats::RecordStats;
+use crate::utils::{count_chunk_read, open_and_parse, read_chunk_header, ParsedRad};
+use crate::view::RadFileType;
+
+/// The bit of a compressed reference ID that records the orientation
+/// (set for forward) of the alignment.
+pub(crate) const ORIENTATION_MASK: u32 = 0x8000_0000;
+
+/// The widths (in bytes) of the record fields whose size is determined
+/// by the read-level tag descriptions of the file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordEncodingContext {
+    pub bc_bytes: usize,
+    pub umi_bytes: usize,
+}
+
+/// The number of bytes used to encode an integer of type `t`
+fn int_width(name: &str, t: &RadType) -> anyhow::Result<usize> {
+    match t {
+        RadType::Int(RadIntId::U8) => Ok(1),
+        RadType::Int(RadIntId::U16) => Ok(2),
+        RadType::Int(RadIntId::U32) => Ok(4),
+        RadType::Int(RadIntId::U64) => Ok(8),
+        _ => bail!("read-level tag {} has unsupported type {:?}", name, t),
+    }
+}
+
+impl RecordEncodingContext {
+    /// Determine the record field widths from the read-level tags of
+    /// `prelude`. Fields that are not described (e.g. the barcode and UMI
+    /// of bulk files) have a width of 0.
+    pub fn from_prelude(prelude: &libradicl::header::RadPrelude) -> anyhow::Result<Self> {
+        let mut ctx = Self::default();
+        for td in prelude.read_tags.tags.iter() {
+            match td.name.as_str() {
+                "b" => ctx.bc_bytes = int_width(&td.name, &td.typeid)?,
+                "u" => ctx.umi_bytes = int_width(&td.name, &td.typeid)?,
+                _ => {}
+            }
+        }
+        Ok(ctx)
+    }
+}
+
+/// Write the low `width` bytes of `v` to `buf` in little-endian order
+fn push_uint(buf: &mut Vec<u8>, v: u64, width: usize) {
+    buf.extend_from_slice(&v.to_le_bytes()[..width]);
+}
+
+/// Access to the references to which a mapped record aligns
+pub trait RecordRefs {
+    /// the IDs of the references to which this record aligns
+    fn ref_ids(&self) -> &[u32];
+    /// mutable access to the IDs of the references of this record
+    fn ref_ids_mut(&mut self) -> &mut [u32];
+}
+
+impl RecordRefs for PiscemBulkReadRecord {
+    fn ref_ids(&self) -> &[u32] {
+        &self.refs
+    }
+    fn ref_ids_mut(&mut self) -> &mut [u32] {
+        &mut self.refs
+    }
+}
+
+impl RecordRefs for AlevinFryReadRecord {
+    fn ref_ids(&self) -> &[u32] {
+        &self.refs
+    }
+    fn ref_ids_mut(&mut self) -> &mut [u32] {
+        &mut self.refs
+    }
+}
+
+/// The ability to encode a mapped record back into its on-disk
+/// representation; the inverse of `MappedRecord::from_bytes_with_context`.
+pub trait EncodeRecord {
+    fn encode(&self, ctx: &RecordEncodingContext, buf: &mut Vec<u8>) -> anyhow::Result<()>;
+}
+
+impl EncodeRecord for PiscemBulkReadRecord {
+    fn encode(&self, _ctx: &RecordEncodingContext, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+        buf.extend_from_slice(&(self.refs.len() as u32).to_le_bytes());
+        buf.push(self.frag_type);
+        for i in 0..self.refs.len() {
+            let ori = if self.dirs[i] { ORIENTATION_MASK } else { 0 };
+            buf.extend_from_slice(&(self.refs[i] | ori).to_le_bytes());
+            buf.extend_from_slice(&self.positions[i].to_le_bytes());
+            buf.extend_from_slice(&self.frag_lengths[i].to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl EncodeRecord for AlevinFryReadRecord {
+    fn encode(&self, ctx: &RecordEncodingContext, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+        if ctx.bc_bytes == 0 || ctx.umi_bytes == 0 {
+            bail!("cannot encode a single-cell record without barcode and UMI widths");
+        }
+        buf.extend_from_slice(&(self.refs.len() as u32).to_le_bytes());
+        push_uint(buf, self.bc, ctx.bc_bytes);
+        push_uint(buf, self.umi, ctx.umi_bytes);
+        for i in 0..self.refs.len() {
+            let ori = if self.dirs[i] { ORIENTATION_MASK } else { 0 };
+            buf.extend_from_slice(&(self.refs[i] | ori).to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// The number of records placed in each chunk of an output file when
+/// records must be re-chunked (rather than copying input chunks).
+pub const RECORDS_PER_CHUNK: u32 = 10_000;
+
+/// Accumulates encoded records until they are written out as a
+/// chunk (with the appropriate `(nbytes, nrec)` header).
+#[derive(Debug, Default)]
+pub struct ChunkBuffer {
+    buf: Vec<u8>,
+    nrec: u32,
+}
+
+impl ChunkBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the number of records currently in the buffer
+    pub fn num_records(&self) -> u32 {
+        self.nrec
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nrec == 0
+    }
+
+    /// Append a record that has already been encoded (by
+    /// [EncodeRecord::encode]) to the buffer
+    pub fn push_encoded(&mut self, encoded: &[u8]) {
+        self.buf.extend_from_slice(encoded);
+        self.nrec += 1;
+    }
+
+    /// Encode `rec`, whose tags beyond its fixed fields (if the file has
+    /// any) are `tags`, with `encoder` and append it to the buffer
+    pub fn push<R: EncodeRecord>(
+        &mut self,
+        rec: &R,
+        tags: Option<&RecordTags>,
+        encoder: &RecordEncoder,
+    ) -> anyhow::Result<()> {
+        encoder.encode(rec, tags, &mut self.buf)?;
+        self.nrec += 1;
+        Ok(())
+    }
+
+    /// Write the buffered records to `w` as a single chunk and clear the
+    /// buffer. Returns the total number of bytes written.
+    pub fn write_chunk<W: Write>(&mut self, w: &mut W) -> anyhow::Result<u64> {
+        let nbytes = u32::try_from(self.buf.len() + 8)?;
+        w.write_all(&nbytes.to_le_bytes())?;
+        w.write_all(&self.nrec.to_le_bytes())?;
+        w.write_all(&self.buf)?;
+        self.buf.clear();
+        self.nrec = 0;
+        Ok(nbytes as u64)
+    }
+}
+
+/// A mapped record of either of the types of RAD file
+#[derive(Debug)]
+pub enum RadRecord {
+    Bulk(PiscemBulkReadRecord),
+    SingleCell(AlevinFryReadRecord),
+}
+
+/// The decoder, and the records of the current chunk, of a [Records]
+/// iterator of either type
+enum RecordChunks {
+    Bulk(
+        RecordDecoder<PiscemBulkReadRecord>,
+        std::vec::IntoIter<PiscemBulkReadRecord>,
+    ),
+    SingleCell(
+        RecordDecoder<AlevinFryReadRecord>,
+        std::vec::IntoIter<AlevinFryReadRecord>,
+    ),
+}
+
+/// An iterator over the records of a RAD file, read a chunk at a time.
+/// Once an error has been returned, the iterator yields nothing more.
+pub struct Records {
+    reader: Box<dyn BufRead>,
+    prelude: RadPrelude,
+    tag_map: TagMap,
+    rad_type: RadFileType,
+    chunks: RecordChunks,
+    done: bool,
+}
+
+impl Records {
+    /// Iterate over the records of `parsed` (whose reader must be
+    /// positioned at its first chunk) as records of type `rad_type`, or of
+    /// its detected type if that is not given (or `unknown`).
+    pub fn new(parsed: ParsedRad, rad_type: Option<&RadFileType>) -> anyhow::Result<Self> {
+        let rad_type = match rad_type {
+            Some(RadFileType::Unknown) | None => parsed.rad_type,
+            Some(t) => t.clone(),
+        };
+        let chunks = match rad_type {
+            RadFileType::Bulk => RecordChunks::Bulk(
+                RecordDecoder::from_prelude(&parsed.prelude)?,
+                Vec::new().into_iter(),
+            ),
+            RadFileType::SingleCell => RecordChunks::SingleCell(
+                RecordDecoder::from_prelude(&parsed.prelude)?,
+                Vec::new().into_iter(),
+            ),
+            RadFileType::Unknown => bail!("Unable to detect RAD file type"),
+        };
+        Ok(Self {
+            reader: parsed.reader,
+            prelude: parsed.prelude,
+            tag_map: parsed.tag_map,
+            rad_type,
+            chunks,
+            done: false,
+        })
+    }
+
+    /// the prelude of the file being read
+    pub fn prelude(&self) -> &RadPrelude {
+        &self.prelude
+    }
+
+    /// the values of the file-level tags
+    pub fn tag_map(&self) -> &TagMap {
+        &self.tag_map
+    }
+
+    /// the type of the records being read
+    pub fn rad_type(&self) -> &RadFileType {
+        &self.rad_type
+    }
+}
+
+impl Iterator for Records {
+    type Item = anyhow::Result<RadRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let next = match self.chunks {
+                RecordChunks::Bulk(_, ref mut recs) => recs.next().map(RadRecord::Bulk),
+                RecordChunks::SingleCell(_, ref mut recs) => recs.next().map(RadRecord::SingleCell),
+            };
+            if let Some(r) = next {
+                return Some(Ok(r));
+            }
+            match libradicl::utils::has_data_left(&mut self.reader) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+            // a truncated or malformed chunk ends the iteration with an error
+            let res = match self.chunks {
+                RecordChunks::Bulk(ref mut decoder, ref mut recs) => decoder
+                    .read_chunk(&mut self.reader)
+                    .map(|chunk| *recs = chunk.reads.into_iter()),
+                RecordChunks::SingleCell(ref mut decoder, ref mut recs) => decoder
+                    .read_chunk(&mut self.reader)
+                    .map(|chunk| *recs = chunk.reads.into_iter()),
+            };
+            if let Err(e) = res {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Iterate over the records of the RAD file at `path` (which may be
+/// compressed, or `-` for the standard input), without formatting them.
+/// The records are of type `rad_type`, or of the type detected from the
+/// file-level tags if that is not given (or `unknown`).
+pub fn records<P: AsRef<std::path::Path>>(
+    path: P,
+    rad_type: Option<&RadFileType>,
+) -> anyhow::Result<Records> {
+    Records::new(open_and_parse(path)?, rad_type)
+}
+
+/// A type of (mapped) record supported by the subcommands, along with the
+/// context with which it is parsed. This gathers the traits the
+/// subcommands rely on, so that their generic functions need just one
+/// type parameter, and can be dispatched on with [with_record_type].
+pub trait SupportedRecord:
+    libradicl::record::MappedRecord<ParsingContext = <Self as SupportedRecord>::Context>
+    + std::fmt::Debug
+    + RecordRefs
+    + EncodeRecord
+    + CanonicalRecord
+    + DiffRecord
+    + RecordStats
+{
+    type Context: libradicl::record::RecordContext + std::fmt::Debug + Clone;
+
+    /// The number of read-level and alignment-level tags that are decoded
+    /// into the fixed fields of this type (i.e. the fragment type of bulk
+    /// records, or the barcode and UMI of single-cell records, and the
+    /// reference and orientation of each alignment, along with the
+    /// position and fragment length of bulk alignments)
+    const NUM_FIXED_TAGS: (usize, usize);
+}
+
+impl SupportedRecord for PiscemBulkReadRecord {
+    type Context = PiscemBulkRecordContext;
+    const NUM_FIXED_TAGS: (usize, usize) = (1, 3);
+}
+
+impl SupportedRecord for AlevinFryReadRecord {
+    type Context = AlevinFryRecordContext;
+    const NUM_FIXED_TAGS: (usize, usize) = (2, 1);
+}
+
+/// An operation that is generic in the type of the records it processes,
+/// and that is run for the type of its input by [with_record_type]
+pub trait RecordTypeVisitor {
+    type Output;
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<Self::Output>;
+}
+
+/// Run `visitor` with the type of record corresponding to `rad_type`.
+/// This is the one place at which the subcommands choose the type of
+/// the records they decode.
+pub fn with_record_type<V: RecordTypeVisitor>(
+    rad_type: &RadFileType,
+    visitor: V,
+) -> anyhow::Result<V::Output> {
+    match rad_type {
+        RadFileType::Bulk => visitor.visit::<PiscemBulkReadRecord>(),
+        RadFileType::SingleCell => visitor.visit::<AlevinFryReadRecord>(),
+        RadFileType::Unknown => bail!("Unknown RadFileType not supported yet"),
+    }
+}
+
+/// The values of the tags of a record, and of each of its alignments,
+/// beyond the fixed fields that are decoded for its type; they are in the
+/// order of their descriptions in the prelude.
+#[derive(Clone, Debug, Default)]
+pub struct RecordTags {
+    pub read: Vec<TagValue>,
+    pub alns: Vec<Vec<TagValue>>,
+}
+
+/// The layout of the records of a file whose records (or alignments)
+/// carry tags beyond the fixed fields that are decoded for their type.
+/// The fixed fields are the first tags of their section, and the decoders
+/// of `libradicl` don't know about the tags that follow them, so
+/// [RecordTagLayout::split_chunk] separates their values out, leaving a
+/// chunk of the fixed fields alone that can be decoded as usual.
+#[derive(Clone, Debug)]
+pub struct RecordTagLayout {
+    read_tags: Vec<(String, RadType)>,
+    aln_tags: Vec<(String, RadType)>,
+    num_fixed_read_tags: usize,
+    num_fixed_aln_tags: usize,
+}
+
+/// The bytes of a chunk, consumed from the front as its records are laid
+/// out
+struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < n {
+            bail!(
+                "a field of {} bytes at offset {} runs past the end of the chunk",
+                n,
+                self.pos
+            );
+        }
+        let b = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    /// read an unsigned integer of `width` bytes, stored little-endian
+    fn uint(&mut self, width: usize) -> anyhow::Result<u64> {
+        let mut buf = [0u8; 8];
+        buf[..width].copy_from_slice(self.take(width)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.uint(2)? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+fn int_id_width(i: &RadIntId) -> usize {
+    match i {
+        RadIntId::U8 => 1,
+        RadIntId::U16 => 2,
+        RadIntId::U32 => 4,
+        RadIntId::U64 => 8,
+    }
+}
+
+/// Append the value `v` of a tag of type `t` to `buf`; the inverse of
+/// [read_tag_value]
+fn write_tag_value(t: &RadType, v: &TagValue, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn push_str(buf: &mut Vec<u8>, s: &str) -> anyhow::Result<()> {
+        buf.extend_from_slice(&u16::try_from(s.len())?.to_le_bytes());
+        buf.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+    match (t, v) {
+        (RadType::Bool, TagValue::Bool(b)) => buf.push(*b as u8),
+        (RadType::Int(RadIntId::U8), TagValue::U8(x)) => buf.push(*x),
+        (RadType::Int(RadIntId::U16), TagValue::U16(x)) => buf.extend_from_slice(&x.to_le_bytes()),
+        (RadType::Int(RadIntId::U32), TagValue::U32(x)) => buf.extend_from_slice(&x.to_le_bytes()),
+        (RadType::Int(RadIntId::U64), TagValue::U64(x)) => buf.extend_from_slice(&x.to_le_bytes()),
+        (RadType::Float(RadFloatId::F32), TagValue::F32(x)) => {
+            buf.extend_from_slice(&x.to_le_bytes())
+        }
+        (RadType::Float(RadFloatId::F64), TagValue::F64(x)) => {
+            buf.extend_from_slice(&x.to_le_bytes())
+        }
+        (RadType::StrType, TagValue::String(s)) => push_str(buf, s)?,
+        (RadType::Array(len_t, elem), v) => {
+            let len = match (elem, v) {
+                (RadAtomicId::Int(RadIntId::U8), TagValue::ArrayU8(a)) => a.len(),
+                (RadAtomicId::Int(RadIntId::U16), TagValue::ArrayU16(a)) => a.len(),
+                (RadAtomicId::Int(RadIntId::U32), TagValue::ArrayU32(a)) => a.len(),
+                (RadAtomicId::Int(RadIntId::U64), TagValue::ArrayU64(a)) => a.len(),
+                (RadAtomicId::Float(RadFloatId::F32), TagValue::ArrayF32(a)) => a.len(),
+                (RadAtomicId::Float(RadFloatId::F64), TagValue::ArrayF64(a)) => a.len(),
+                (RadAtomicId::String, TagValue::ArrayString(a)) => a.len(),
+                _ => bail!("the value {:?} doesn't have the type {:?}", v, t),
+            };
+            let width = int_id_width(len_t);
+            if width < 8 && (len as u64) >> (8 * width) != 0 {
+                bail!(
+                    "an array of {} values is too long for the type {:?}",
+                    len,
+                    t
+                );
+            }
+            push_uint(buf, len as u64, width);
+            match v {
+                TagValue::ArrayU8(a) => buf.extend_from_slice(a),
+                TagValue::ArrayU16(a) => a
+                    .iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+                TagValue::ArrayU32(a) => a
+                    .iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+                TagValue::ArrayU64(a) => a
+                    .iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+                TagValue::ArrayF32(a) => a
+                    .iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+                TagValue::ArrayF64(a) => a
+                    .iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+                TagValue::ArrayString(a) => {
+                    for s in a {
+                        push_str(buf, s)?;
+                    }
+                }
+                _ => unreachable!("the value is an array of the type's elements"),
+            }
+        }
+        _ => bail!("the value {:?} doesn't have the type {:?}", v, t),
+    }
+    Ok(())
+}
+
+/// Read the value of a tag of type `t` from `cur`
+fn read_tag_value(t: &RadType, cur: &mut ByteCursor) -> anyhow::Result<TagValue> {
+    Ok(match t {
+        RadType::Bool => TagValue::Bool(cur.uint(1)? != 0),
+        RadType::Int(RadIntId::U8) => TagValue::U8(cur.uint(1)? as u8),
+        RadType::Int(RadIntId::U16) => TagValue::U16(cur.uint(2)? as u16),
+        RadType::Int(RadIntId::U32) => TagValue::U32(cur.uint(4)? as u32),
+        RadType::Int(RadIntId::U64) => TagValue::U64(cur.uint(8)?),
+        RadType::Float(RadFloatId::F32) => TagValue::F32(f32::from_bits(cur.uint(4)? as u32)),
+        RadType::Float(RadFloatId::F64) => TagValue::F64(f64::from_bits(cur.uint(8)?)),
+        RadType::StrType => TagValue::String(cur.string()?),
+        RadType::Array(len_t, elem) => {
+            let len = cur.uint(int_id_width(len_t))? as usize;
+            match elem {
+                RadAtomicId::Int(RadIntId::U8) => TagValue::ArrayU8(cur.take(len)?.to_vec()),
+                RadAtomicId::Int(RadIntId::U16) => TagValue::ArrayU16(
+                    (0..len)
+                        .map(|_| cur.uint(2).map(|v| v as u16))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+                RadAtomicId::Int(RadIntId::U32) => TagValue::ArrayU32(
+                    (0..len)
+                        .map(|_| cur.uint(4).map(|v| v as u32))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+                RadAtomicId::Int(RadIntId::U64) => TagValue::ArrayU64(
+                    (0..len)
+                        .map(|_| cur.uint(8))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+                RadAtomicId::Float(RadFloatId::F32) => TagValue::ArrayF32(
+                    (0..len)
+                        .map(|_| cur.uint(4).map(|v| f32::from_bits(v as u32)))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+                RadAtomicId::Float(RadFloatId::F64) => TagValue::ArrayF64(
+                    (0..len)
+                        .map(|_| cur.uint(8).map(f64::from_bits))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+                RadAtomicId::String => TagValue::ArrayString(
+                    (0..len)
+                        .map(|_| cur.string())
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+            }
+        }
+    })
+}
+
+impl RecordTagLayout {
+    /// The layout of the records described by `prelude`, of which the
+    /// first `num_fixed_read_tags` read-level tags, and the first
+    /// `num_fixed_aln_tags` alignment-level tags, are decoded as fixed
+    /// fields; or `None` if the records have no other tags, in which case
+    /// their chunks can be decoded as they are.
+    pub fn from_prelude(
+        prelude: &RadPrelude,
+        num_fixed_read_tags: usize,
+        num_fixed_aln_tags: usize,
+    ) -> Option<Self> {
+        if prelude.read_tags.tags.len() <= num_fixed_read_tags
+            && prelude.aln_tags.tags.len() <= num_fixed_aln_tags
+        {
+            return None;
+        }
+        let mut layout = Self::all_tags(prelude);
+        layout.num_fixed_read_tags = num_fixed_read_tags;
+        layout.num_fixed_aln_tags = num_fixed_aln_tags;
+        Some(layout)
+    }
+
+    /// The layout of all of the tags of the records described by
+    /// `prelude`, none of which are fixed fields. This is enough to find
+    /// the sizes of the records with [RecordTagLayout::record_size].
+    pub fn all_tags(prelude: &RadPrelude) -> Self {
+        let descs = |tags: &[libradicl::rad_types::TagDesc]| {
+            tags.iter()
+                .map(|td| (td.name.clone(), td.typeid.clone()))
+                .collect()
+        };
+        Self {
+            read_tags: descs(&prelude.read_tags.tags),
+            aln_tags: descs(&prelude.aln_tags.tags),
+            num_fixed_read_tags: 0,
+            num_fixed_aln_tags: 0,
+        }
+    }
+
+    /// The size (in bytes) of the encoded record that begins at `offset`
+    /// in `chunk`, which is an error if the record runs past the end of
+    /// the chunk.
+    pub fn record_size(&self, chunk: &[u8], offset: usize) -> anyhow::Result<usize> {
+        let mut cur = ByteCursor {
+            bytes: chunk,
+            pos: offset.min(chunk.len()),
+        };
+        let num_alns = cur
+            .uint(4)
+            .context("its number of alignments runs past the end of the chunk")?;
+        for (name, t) in self.read_tags.iter() {
+            read_tag_value(t, &mut cur).with_context(|| format!("its tag {}", name))?;
+        }
+        // alignments without tags take no space, however many there are
+        if !self.aln_tags.is_empty() {
+            for i in 0..num_alns {
+                for (name, t) in self.aln_tags.iter() {
+                    read_tag_value(t, &mut cur).with_context(|| {
+                        format!("tag {} of alignment {} of {}", name, i, num_alns)
+                    })?;
+                }
+            }
+        }
+        Ok(cur.pos - offset)
+    }
+
+    /// the names of the read-level tags beyond the fixed fields, in the
+    /// order of the values of [RecordTags::read]
+    pub fn read_tag_names(&self) -> impl Iterator<Item = &str> {
+        self.read_tags[self.num_fixed_read_tags.min(self.read_tags.len())..]
+            .iter()
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// the names of the alignment-level tags beyond the fixed fields, in
+    /// the order of the values of each entry of [RecordTags::alns]
+    pub fn aln_tag_names(&self) -> impl Iterator<Item = &str> {
+        self.aln_tags[self.num_fixed_aln_tags.min(self.aln_tags.len())..]
+            .iter()
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Lay out the tags of `section` (whose first `num_fixed` are fixed
+    /// fields) from `cur`, copying the bytes of the fixed fields to
+    /// `fixed` and returning the values of the others.
+    fn split_tags(
+        section: &[(String, RadType)],
+        num_fixed: usize,
+        cur: &mut ByteCursor,
+        fixed: &mut Vec<u8>,
+    ) -> anyhow::Result<Vec<TagValue>> {
+        let mut values = Vec::with_capacity(section.len().saturating_sub(num_fixed));
+        for (i, (name, t)) in section.iter().enumerate() {
+            let start = cur.pos;
+            let v = read_tag_value(t, cur).with_context(|| format!("tag {}", name))?;
+            if i < num_fixed {
+                fixed.extend_from_slice(&cur.bytes[start..cur.pos]);
+            } else {
+                values.push(v);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Split the chunk held in `chunk_bytes` (including its header) into
+    /// an equivalent chunk whose records hold only their fixed fields, and
+    /// the values of the other tags of each of its records.
+    pub fn split_chunk(&self, chunk_bytes: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<RecordTags>)> {
+        let mut cur = ByteCursor {
+            bytes: chunk_bytes,
+            pos: 0,
+        };
+        let _nbytes = cur.uint(4)?;
+        let nrec = cur.uint(4)? as u32;
+        let mut fixed = Vec::with_capacity(chunk_bytes.len());
+        fixed.extend_from_slice(&[0u8; 8]);
+        let mut tags = Vec::with_capacity(nrec as usize);
+        for i in 0..nrec {
+            let mut rec_tags = RecordTags::default();
+            let num_alns = cur.uint(4)?;
+            fixed.extend_from_slice(&(num_alns as u32).to_le_bytes());
+            rec_tags.read = Self::split_tags(
+                &self.read_tags,
+                self.num_fixed_read_tags,
+                &mut cur,
+                &mut fixed,
+            )
+            .with_context(|| format!("record {} of the chunk is malformed", i))?;
+            for _ in 0..num_alns {
+                let aln_tags = Self::split_tags(
+                    &self.aln_tags,
+                    self.num_fixed_aln_tags,
+                    &mut cur,
+                    &mut fixed,
+                )
+                .with_context(|| format!("record {} of the chunk is malformed", i))?;
+                rec_tags.alns.push(aln_tags);
+            }
+            tags.push(rec_tags);
+        }
+        if cur.pos != chunk_bytes.len() {
+            bail!(
+                "the chunk has {} bytes beyond the end of its {} records",
+                chunk_bytes.len() - cur.pos,
+                nrec
+            );
+        }
+        let fixed_nbytes = u32::try_from(fixed.len())?;
+        fixed[0..4].copy_from_slice(&fixed_nbytes.to_le_bytes());
+        fixed[4..8].copy_from_slice(&nrec.to_le_bytes());
+        Ok((fixed, tags))
+    }
+
+    /// Write the tags of `section` (whose first `num_fixed` are fixed
+    /// fields) to `buf`, copying the bytes of the fixed fields from `cur`
+    /// and encoding the others from `values`.
+    fn join_tags(
+        section: &[(String, RadType)],
+        num_fixed: usize,
+        cur: &mut ByteCursor,
+        values: &[TagValue],
+        buf: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let num_fixed = num_fixed.min(section.len());
+        if values.len() != section.len() - num_fixed {
+            bail!(
+                "there are {} tags beyond the fixed fields, but {} values for them",
+                section.len() - num_fixed,
+                values.len()
+            );
+        }
+        for (name, t) in section[..num_fixed].iter() {
+            let start = cur.pos;
+            read_tag_value(t, cur).with_context(|| format!("tag {}", name))?;
+            buf.extend_from_slice(&cur.bytes[start..cur.pos]);
+        }
+        for ((name, t), v) in section[num_fixed..].iter().zip(values) {
+            write_tag_value(t, v, buf).with_context(|| format!("tag {}", name))?;
+        }
+        Ok(())
+    }
+
+    /// Append to `buf` the record whose fixed fields alone are encoded in
+    /// `fixed`, and whose other tags have the values `tags`; the inverse
+    /// of [RecordTagLayout::split_chunk] for a single record.
+    fn join_record(
+        &self,
+        fixed: &[u8],
+        tags: &RecordTags,
+        buf: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let mut cur = ByteCursor {
+            bytes: fixed,
+            pos: 0,
+        };
+        let num_alns = cur.uint(4)? as usize;
+        if tags.alns.len() != num_alns {
+            bail!(
+                "the record has {} alignments, but the tags of {}",
+                num_alns,
+                tags.alns.len()
+            );
+        }
+        buf.extend_from_slice(&fixed[..4]);
+        Self::join_tags(
+            &self.read_tags,
+            self.num_fixed_read_tags,
+            &mut cur,
+            &tags.read,
+            buf,
+        )?;
+        for aln_tags in tags.alns.iter() {
+            Self::join_tags(
+                &self.aln_tags,
+                self.num_fixed_aln_tags,
+                &mut cur,
+                aln_tags,
+                buf,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The records of a chunk, as decoded by [RecordDecoder], along with the
+/// values of their tags beyond the fixed fields (if the file has any, in
+/// which case there is an entry for each record).
+#[derive(Debug)]
+pub struct DecodedChunk<T> {
+    pub reads: Vec<T>,
+    pub tags: Vec<RecordTags>,
+}
+
+impl<T> DecodedChunk<T> {
+    /// the tags beyond the fixed fields of record `i`, if the file has any
+    pub fn tags(&self, i: usize) -> Option<&RecordTags> {
+        self.tags.get(i)
+    }
+}
+
+/// Decodes the chunks of a file whose records are of type `T`, laying out
+/// the records by the tag descriptions of its prelude, so that the tags
+/// beyond their fixed fields (if any) are kept rather than misparsed.
+/// This is how the subcommands decode records.
+pub struct RecordDecoder<T: SupportedRecord> {
+    ctx: T::Context,
+    layout: Option<RecordTagLayout>,
+    chunk_buf: Vec<u8>,
+}
+
+impl<T: SupportedRecord> RecordDecoder<T> {
+    pub fn from_prelude(prelude: &RadPrelude) -> anyhow::Result<Self> {
+        let (num_read, num_aln) = T::NUM_FIXED_TAGS;
+        Ok(Self {
+            ctx: prelude.get_record_context::<T::Context>()?,
+            layout: RecordTagLayout::from_prelude(prelude, num_read, num_aln),
+            chunk_buf: Vec::new(),
+        })
+    }
+
+    /// the context with which the fixed fields of the records are decoded
+    pub fn context(&self) -> &T::Context {
+        &self.ctx
+    }
+
+    /// Decode the chunk held in `chunk_bytes` (including its header)
+    pub fn decode_chunk(&self, chunk_bytes: &[u8]) -> anyhow::Result<DecodedChunk<T>> {
+        let Some(ref layout) = self.layout else {
+            let chunk = libradicl::chunk::Chunk::<T>::from_bytes(
+                &mut std::io::Cursor::new(chunk_bytes),
+                &self.ctx,
+            );
+            return Ok(DecodedChunk {
+                reads: chunk.reads,
+                tags: Vec::new(),
+            });
+        };
+        let (fixed_bytes, tags) = layout
+            .split_chunk(chunk_bytes)
+            .context("the chunk can't be laid out by its tag descriptions")?;
+        let chunk = libradicl::chunk::Chunk::<T>::from_bytes(
+            &mut std::io::Cursor::new(&fixed_bytes[..]),
+            &self.ctx,
+        );
+        Ok(DecodedChunk {
+            reads: chunk.reads,
+            tags,
+        })
+    }
+
+    /// Read the next chunk of `reader`, which must be positioned at the
+    /// start of a chunk, and decode it; it is an error if the chunk is
+    /// truncated.
+    pub fn read_chunk<R: BufRead>(&mut self, reader: &mut R) -> anyhow::Result<DecodedChunk<T>> {
+        let (nbytes, nrec) = read_chunk_header(reader).context("truncated chunk header")?;
+        if nbytes < 8 {
+            bail!("a chunk reports an invalid size of {} bytes", nbytes);
+        }
+        let mut chunk_bytes = std::mem::take(&mut self.chunk_buf);
+        chunk_bytes.resize(nbytes as usize, 0);
+        chunk_bytes[0..4].copy_from_slice(&nbytes.to_le_bytes());
+        chunk_bytes[4..8].copy_from_slice(&nrec.to_le_bytes());
+        reader
+            .read_exact(&mut chunk_bytes[8..])
+            .map_err(|_| anyhow!("a chunk of {} bytes is truncated", nbytes))?;
+        let chunk = self.decode_chunk(&chunk_bytes);
+        self.chunk_buf = chunk_bytes;
+        let chunk = chunk?;
+        count_chunk_read(chunk.reads.len() as u64);
+        Ok(chunk)
+    }
+}
+
+/// Encodes records under the tag descriptions of the prelude of the file
+/// they are written to, including the values of their tags beyond the
+/// fixed fields; the inverse of [RecordDecoder].
+#[derive(Clone, Debug)]
+pub struct RecordEncoder {
+    ctx: RecordEncodingContext,
+    layout: Option<RecordTagLayout>,
+}
+
+impl RecordEncoder {
+    /// The encoder of records of type `T` under `prelude`
+    pub fn from_prelude<T: SupportedRecord>(prelude: &RadPrelude) -> anyhow::Result<Self> {
+        let (num_read, num_aln) = T::NUM_FIXED_TAGS;
+        Ok(Self {
+            ctx: RecordEncodingContext::from_prelude(prelude)?,
+            layout: RecordTagLayout::from_prelude(prelude, num_read, num_aln),
+        })
+    }
+
+    /// the widths of the fixed fields of the records
+    pub fn context(&self) -> &RecordEncodingContext {
+        &self.ctx
+    }
+
+    /// Append the encoding of `rec`, whose tags beyond its fixed fields
+    /// are `tags`, to `buf`. The tags must be given if the prelude
+    /// describes any.
+    pub fn encode<R: EncodeRecord>(
+        &self,
+        rec: &R,
+        tags: Option<&RecordTags>,
+        buf: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let Some(ref layout) = self.layout else {
+            return rec.encode(&self.ctx, buf);
+        };
+        let Some(tags) = tags else {
+            bail!("the record has no values for the tags beyond its fixed fields");
+        };
+        let mut fixed = Vec::new();
+        rec.encode(&self.ctx, &mut fixed)?;
+        layout.join_record(&fixed, tags, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::{prelude_bytes, U16, U32, U8};
+    use libradicl::record::MappedRecord;
+    use std::io::Cursor;
+
+    fn parse_prelude(bytes: &[u8]) -> RadPrelude {
+        RadPrelude::from_bytes(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    fn bulk_prelude() -> RadPrelude {
+        parse_prelude(&prelude_bytes(
+            &["r0", "r1", "r2"],
+            &[("frag_map_type", U8)],
+            &[
+                ("compressed_ori_refid", U32),
+                ("pos", U32),
+                ("frag_len", U16),
+            ],
+        ))
+    }
+
+    fn sc_prelude() -> RadPrelude {
+        parse_prelude(&prelude_bytes(
+            &["r0", "r1", "r2"],
+            &[("b", U32), ("u", U16)],
+            &[("compressed_ori_refid", U32)],
+        ))
+    }
+
+    fn bulk_record() -> PiscemBulkReadRecord {
+        PiscemBulkReadRecord {
+            frag_type: 3,
+            refs: vec![0, 2, 1],
+            dirs: vec![true, false, true],
+            positions: vec![7, 0, u32::MAX],
+            frag_lengths: vec![250, 0, u16::MAX],
+        }
+    }
+
+    fn sc_record(bc: u64, umi: u64) -> AlevinFryReadRecord {
+        AlevinFryReadRecord {
+            bc,
+            umi,
+            refs: vec![2, 0],
+            dirs: vec![false, true],
+        }
+    }
+
+    fn assert_bulk_eq(a: &PiscemBulkReadRecord, b: &PiscemBulkReadRecord) {
+        assert_eq!(a.frag_type, b.frag_type);
+        assert_eq!(a.refs, b.refs);
+        assert_eq!(a.dirs, b.dirs);
+        assert_eq!(a.positions, b.positions);
+        assert_eq!(a.frag_lengths, b.frag_lengths);
+    }
+
+    fn assert_sc_eq(a: &AlevinFryReadRecord, b: &AlevinFryReadRecord) {
+        assert_eq!(a.bc, b.bc);
+        assert_eq!(a.umi, b.umi);
+        assert_eq!(a.refs, b.refs);
+        assert_eq!(a.dirs, b.dirs);
+    }
+
+    #[test]
+    fn encoding_context_follows_the_read_tags() {
+        let ctx = RecordEncodingContext::from_prelude(&sc_prelude()).unwrap();
+        assert_eq!((ctx.bc_bytes, ctx.umi_bytes), (4, 2));
+        let ctx = RecordEncodingContext::from_prelude(&bulk_prelude()).unwrap();
+        assert_eq!((ctx.bc_bytes, ctx.umi_bytes), (0, 0));
+    }
+
+    #[test]
+    fn bulk_record_round_trips() {
+        let prelude = bulk_prelude();
+        let ctx = prelude
+            .get_record_context::<PiscemBulkRecordContext>()
+            .unwrap();
+        let enc_ctx = RecordEncodingContext::from_prelude(&prelude).unwrap();
+        let rec = bulk_record();
+
+        let mut buf = Vec::new();
+        rec.encode(&enc_ctx, &mut buf).unwrap();
+        assert_eq!(buf.len(), 4 + 1 + 3 * (4 + 4 + 2));
+        // the orientation is kept in the high bit of the reference ID
+        let first_ref = u32::from_le_bytes(buf[5..9].try_into().unwrap());
+        assert_eq!(first_ref, ORIENTATION_MASK);
+        let second_ref = u32::from_le_bytes(buf[15..19].try_into().unwrap());
+        assert_eq!(second_ref, 2);
+
+        let decoded =
+            PiscemBulkReadRecord::from_bytes_with_context(&mut Cursor::new(&buf[..]), &ctx);
+        assert_bulk_eq(&decoded, &rec);
+    }
+
+    #[test]
+    fn single_cell_record_round_trips() {
+        let prelude = sc_prelude();
+        let ctx = prelude
+            .get_record_context::<AlevinFryRecordContext>()
+            .unwrap();
+        let enc_ctx = RecordEncodingContext::from_prelude(&prelude).unwrap();
+        let rec = sc_record(0xdead_beef, 0xbeef);
+
+        let mut buf = Vec::new();
+        rec.encode(&enc_ctx, &mut buf).unwrap();
+        assert_eq!(buf.len(), 4 + 4 + 2 + 2 * 4);
+        let first_ref = u32::from_le_bytes(buf[10..14].try_into().unwrap());
+        assert_eq!(first_ref, 2);
+        let second_ref = u32::from_le_bytes(buf[14..18].try_into().unwrap());
+        assert_eq!(second_ref, ORIENTATION_MASK);
+
+        let decoded =
+            AlevinFryReadRecord::from_bytes_with_context(&mut Cursor::new(&buf[..]), &ctx);
+        assert_sc_eq(&decoded, &rec);
+    }
+
+    #[test]
+    fn single_cell_record_needs_barcode_and_umi_widths() {
+        let mut buf = Vec::new();
+        let res = sc_record(1, 1).encode(&RecordEncodingContext::default(), &mut buf);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn chunk_buffer_writes_a_decodable_chunk() {
+        let prelude = sc_prelude();
+        let ctx = prelude
+            .get_record_context::<AlevinFryRecordContext>()
+            .unwrap();
+        let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(&prelude).unwrap();
+        let recs = [sc_record(1, 2), sc_record(3, 4)];
+
+        let mut chunk_buf = ChunkBuffer::new();
+        chunk_buf.push(&recs[0], None, &encoder).unwrap();
+        let mut encoded = Vec::new();
+        recs[1].encode(encoder.context(), &mut encoded).unwrap();
+        chunk_buf.push_encoded(&encoded);
+        assert_eq!(chunk_buf.num_records(), 2);
+
+        let mut out = Vec::new();
+        let nbytes = chunk_buf.write_chunk(&mut out).unwrap();
+        assert_eq!(nbytes, out.len() as u64);
+        assert_eq!(
+            u32::from_le_bytes(out[0..4].try_into().unwrap()),
+            nbytes as u32
+        );
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 2);
+        assert!(chunk_buf.is_empty());
+
+        let chunk = libradicl::chunk::Chunk::<AlevinFryReadRecord>::from_bytes(
+            &mut Cursor::new(&out[..]),
+            &ctx,
+        );
+        assert_eq!(chunk.reads.len(), 2);
+        for (decoded, rec) in chunk.reads.iter().zip(recs.iter()) {
+            assert_sc_eq(decoded, rec);
+        }
+    }
+
+    #[test]
+    fn chunk_buffer_round_trips_bulk_records() {
+        let prelude = bulk_prelude();
+        let ctx = prelude
+            .get_record_context::<PiscemBulkRecordContext>()
+            .unwrap();
+        let encoder = RecordEncoder::from_prelude::<PiscemBulkReadRecord>(&prelude).unwrap();
+        let rec = bulk_record();
+
+        let mut chunk_buf = ChunkBuffer::new();
+        for _ in 0..3 {
+            chunk_buf.push(&rec, None, &encoder).unwrap();
+        }
+        let mut out = Vec::new();
+        chunk_buf.write_chunk(&mut out).unwrap();
+
+        let chunk = libradicl::chunk::Chunk::<PiscemBulkReadRecord>::from_bytes(
+            &mut Cursor::new(&out[..]),
+            &ctx,
+        );
+        assert_eq!(chunk.reads.len(), 3);
+        for decoded in chunk.reads.iter() {
+            assert_bulk_eq(decoded, &rec);
+        }
+    }
+
+    #[test]
+    fn records_with_extra_tags_round_trip() {
+        let prelude = parse_prelude(&prelude_bytes(
+            &["r0", "r1", "r2"],
+            &[("b", U32), ("u", U16), ("x", U8)],
+            &[("compressed_ori_refid", U32), ("score", U16)],
+        ));
+        // two records: (bc 1, umi 2, x 9, alns [r2 fw score 5, r0 rc
+        // score 6]) and (bc 3, umi 4, x 0, no alns)
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&2u32.to_le_bytes());
+        chunk.extend_from_slice(&2u32.to_le_bytes());
+        chunk.extend_from_slice(&1u32.to_le_bytes());
+        chunk.extend_from_slice(&2u16.to_le_bytes());
+        chunk.push(9);
+        chunk.extend_from_slice(&(2 | ORIENTATION_MASK).to_le_bytes());
+        chunk.extend_from_slice(&5u16.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&6u16.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&3u32.to_le_bytes());
+        chunk.extend_from_slice(&4u16.to_le_bytes());
+        chunk.push(0);
+        let nbytes = chunk.len() as u32;
+        chunk[0..4].copy_from_slice(&nbytes.to_le_bytes());
+
+        let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(&prelude).unwrap();
+        let decoded = decoder.read_chunk(&mut Cursor::new(&chunk[..])).unwrap();
+        assert_eq!(decoded.reads.len(), 2);
+        assert_eq!((decoded.reads[0].bc, decoded.reads[0].umi), (1, 2));
+        assert_eq!(decoded.reads[0].refs, vec![2, 0]);
+        assert_eq!(decoded.reads[0].dirs, vec![true, false]);
+        assert_eq!((decoded.reads[1].bc, decoded.reads[1].umi), (3, 4));
+        assert!(decoded.reads[1].refs.is_empty());
+        assert_eq!(decoded.tags.len(), 2);
+
+        let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(&prelude).unwrap();
+        let mut chunk_buf = ChunkBuffer::new();
+        for (i, r) in decoded.reads.iter().enumerate() {
+            chunk_buf.push(r, decoded.tags(i), &encoder).unwrap();
+        }
+        let mut out = Vec::new();
+        chunk_buf.write_chunk(&mut out).unwrap();
+        assert_eq!(out, chunk);
+
+        // the tags must be given when the prelude describes them
+        assert!(chunk_buf.push(&decoded.reads[0], None, &encoder).is_err());
+    }
+
+    #[test]
+    fn record_sizes_follow_the_tag_descriptions() {
+        let prelude = parse_prelude(&prelude_bytes(
+            &["r0"],
+            &[("b", U32), ("u", U16), ("x", U8)],
+            &[("compressed_ori_refid", U32), ("score", U16)],
+        ));
+        let layout = RecordTagLayout::all_tags(&prelude);
+        // a record with 2 alignments, followed by a truncated one
+        let mut chunk = vec![0u8; 8];
+        chunk.extend_from_slice(&2u32.to_le_bytes());
+        chunk.extend_from_slice(&[0u8; 4 + 2 + 1 + 2 * (4 + 2)]);
+        chunk.extend_from_slice(&1u32.to_le_bytes());
+        chunk.extend_from_slice(&[0u8; 4 + 2 + 1 + 3]);
+        assert_eq!(
+            layout.record_size(&chunk, 8).unwrap(),
+            4 + 4 + 2 + 1 + 2 * (4 + 2)
+        );
+        assert!(layout.record_size(&chunk, 8 + 23).is_err());
+    }
+
+    #[test]
+    fn truncated_chunks_are_an_error() {
+        let prelude = sc_prelude();
+        let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(&prelude).unwrap();
+        let mut chunk_buf = ChunkBuffer::new();
+        chunk_buf.push(&sc_record(1, 2), None, &encoder).unwrap();
+        let mut out = Vec::new();
+        chunk_buf.write_chunk(&mut out).unwrap();
+        out.pop();
+
+        let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(&prelude).unwrap();
+        assert!(decoder.read_chunk(&mut Cursor::new(&out[..])).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::bail;
+use clap::{ArgGroup, Parser};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{BufRead, BufWriter, Write};
+use tracing::info;
+
+use crate::record::{
+    with_record_type, ChunkBuffer, RecordDecoder, RecordEncoder, RecordTypeVisitor,
+    SupportedRecord, RECORDS_PER_CHUNK,
+};
+use crate::utils::{open_and_parse, rewrite_num_chunks, ParsedRad};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to randomly subsampling the records of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("amount")
+        .required(true)
+        .args(["fraction", "num_records"])
+))]
+pub struct SampleOpts {
+    /// the input RAD file to sample from
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// the output RAD file in which the sampled records will be written
+    #[arg(short, long, required = true)]
+    pub output: std::path::PathBuf,
+
+    /// keep each record independently with this probability (in (0, 1])
+    #[arg(short, long)]
+    pub fraction: Option<f64>,
+
+    /// keep exactly this many records (or all of them, if the input has
+    /// fewer), chosen uniformly at random. The sampled records are held
+    /// in memory until the whole input has been read.
+    #[arg(short, long)]
+    pub num_records: Option<usize>,
+
+    /// the seed for the random number generator; if not provided, a
+    /// random seed is used
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+}
+
+/// The sampled records of a file; these are either written out as they
+/// are sampled, or held in a reservoir until the input is exhausted.
+enum Sampler {
+    Bernoulli {
+        fraction: f64,
+    },
+    Reservoir {
+        capacity: usize,
+        num_seen: usize,
+        // (index in the input, index of its chunk, encoded record)
+        records: Vec<(usize, u64, Vec<u8>)>,
+    },
+}
+
+/// Sample the records of `ifile` according to `sampler`, and write them
+/// to `owriter`. The sampled records of each input chunk are written as
+/// one output chunk (and chunks from which no record is sampled are
+/// dropped), so that the records that were grouped together, e.g. by
+/// the barcodes of a collated file, remain so. Returns the number of
+/// chunks and records written.
+fn sample_records<T: SupportedRecord, R: BufRead, W: Write>(
+    prelude: &libradicl::header::RadPrelude,
+    mut sampler: Sampler,
+    rng: &mut StdRng,
+    ifile: &mut R,
+    owriter: &mut W,
+) -> anyhow::Result<(u64, u64)> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    let encoder = RecordEncoder::from_prelude::<T>(prelude)?;
+    let mut chunk_buf = ChunkBuffer::new();
+    let mut num_chunks = 0_u64;
+    let mut num_records = 0_u64;
+    let mut encoded = Vec::<u8>::new();
+    let mut write_chunk = |chunk_buf: &mut ChunkBuffer, owriter: &mut W| -> anyhow::Result<()> {
+        if !chunk_buf.is_empty() {
+            num_records += chunk_buf.num_records() as u64;
+            chunk_buf.write_chunk(owriter)?;
+            num_chunks += 1;
+        }
+        Ok(())
+    };
+
+    let mut chunk_num = 0_u64;
+    while libradicl::utils::has_data_left(ifile)? {
+        let chunk = decoder.read_chunk(ifile)?;
+        for (i, r) in chunk.reads.iter().enumerate() {
+            match sampler {
+                Sampler::Bernoulli { fraction } => {
+                    if rng.gen_bool(fraction) {
+                        chunk_buf.push(r, chunk.tags(i), &encoder)?;
+                    }
+                }
+                Sampler::Reservoir {
+                    capacity,
+                    ref mut num_seen,
+                    ref mut records,
+                } => {
+                    // the ith record replaces a random member of the
+                    // reservoir with probability capacity / (i + 1).
+                    if records.len() < capacity {
+                        encoded.clear();
+                        encoder.encode(r, chunk.tags(i), &mut encoded)?;
+                        records.push((*num_seen, chunk_num, encoded.clone()));
+                    } else {
+                        let j = rng.gen_range(0..=*num_seen);
+                        if j < capacity {
+                            encoded.clear();
+                            encoder.encode(r, chunk.tags(i), &mut encoded)?;
+                            records[j] = (*num_seen, chunk_num, encoded.clone());
+                        }
+                    }
+                    *num_seen += 1;
+                }
+            }
+        }
+        write_chunk(&mut chunk_buf, owriter)?;
+        chunk_num += 1;
+    }
+
+    if let Sampler::Reservoir { mut records, .. } = sampler {
+        // write the sampled records in the order they appear in the input,
+        // starting a new chunk wherever the input did
+        records.sort_unstable_by_key(|(idx, _, _)| *idx);
+        let mut current_chunk = None;
+        for (_, rec_chunk, rec) in records.iter() {
+            if current_chunk != Some(*rec_chunk) {
+                write_chunk(&mut chunk_buf, owriter)?;
+                current_chunk = Some(*rec_chunk);
+            }
+            chunk_buf.push_encoded(rec);
+        }
+        write_chunk(&mut chunk_buf, owriter)?;
+    }
+    Ok((num_chunks, num_records))
+}
+
+/// Samples the records of a RAD file, writing them to `owriter`
+struct SampleRecords<'a, W: Write> {
+    input: &'a mut ParsedRad,
+    sampler: Sampler,
+    rng: &'a mut StdRng,
+    owriter: &'a mut W,
+}
+
+impl<W: Write> RecordTypeVisitor for SampleRecords<'_, W> {
+    type Output = (u64, u64);
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<Self::Output> {
+        sample_records::<T, _, _>(
+            &self.input.prelude,
+            self.sampler,
+            self.rng,
+            &mut self.input.reader,
+            self.owriter,
+        )
+    }
+}
+
+pub fn sample(sample_opts: &SampleOpts) -> anyhow::Result<()> {
+    let sampler = match (sample_opts.fraction, sample_opts.num_records) {
+        (Some(fraction), _) => {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                bail!(
+                    "--fraction must be in the interval (0, 1], but {} was given",
+                    fraction
+                );
+            }
+            Sampler::Bernoulli { fraction }
+        }
+        (None, Some(capacity)) => Sampler::Reservoir {
+            capacity,
+            num_seen: 0,
+            records: Vec::with_capacity(capacity.min(RECORDS_PER_CHUNK as usize)),
+        },
+        (None, None) => bail!("one of --fraction or --num-records is required"),
+    };
+
+    let seed = sample_opts.seed.unwrap_or_else(rand::random);
+    info!("sampling records with seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut input = open_and_parse(&sample_opts.input)?;
+    let rad_type = resolve_rad_type(
+        sample_opts.rad_type.as_ref(),
+        &input.prelude,
+        &input.tag_map,
+        &sample_opts.input,
+    )?;
+
+    // the true number of chunks is filled in once they've been written
+    input.prelude.hdr.num_chunks = 0;
+    let ofile = std::fs::File::create(&sample_opts.output)?;
+    let mut owriter = BufWriter::new(ofile);
+    input.prelude.write(&mut owriter)?;
+    input.tag_map.write_values(&mut owriter)?;
+
+    let (num_chunks, num_records) = with_record_type(
+        &rad_type,
+        SampleRecords {
+            input: &mut input,
+            sampler,
+            rng: &mut rng,
+            owriter: &mut owriter,
+        },
+    )?;
+
+    rewrite_num_chunks(&mut owriter, &input.prelude, num_chunks)?;
+    owriter.flush()?;
+    info!(
+        "wrote {} sampled records in {} chunks to {}",
+        num_records,
+        num_chunks,
+        sample_opts.output.display()
+    );
+    Ok(())
+}
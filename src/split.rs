@@ -1,135 +1,1293 @@
-use clap::Parser;
-use scroll::Pread;
-use std::io::Write;
-use std::io::{BufReader, BufWriter};
-use tracing::info;
+use anyhow::{anyhow, bail};
+use clap::{ArgGroup, Parser};
+use libradicl::record::AlevinFryReadRecord;
+use needletail::bitkmer::bitmer_to_bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufWriter, Read, Seek, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn};
+
+use crate::record::{
+    with_record_type, ChunkBuffer, RecordDecoder, RecordEncoder, RecordTypeVisitor,
+    SupportedRecord, RECORDS_PER_CHUNK,
+};
+use crate::utils::{
+    buffered_writer, compressed_writer, count_chunk_read, is_fifo, is_gzip_compressed, is_stdin,
+    is_url, rad_reader, read_chunk_header, rewrite_num_chunks, skip_bytes,
+    styled_byte_progress_bar, walk_chunk_headers, CompressedWriter, Compression, CountingReader,
+    MmapReader, ProgressDisplay, ThroughputReader,
+};
+use crate::view::{parse_ref_filter, resolve_rad_type, RadFileType};
 
 /// options relevant to building the minimizer space suffix array
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("mode")
+        .required(true)
+        .args(["num_reads", "max_bytes", "num_files", "by_ref", "by_barcode_prefix"])
+))]
 pub struct SplitOpts {
     /// input RAD file to split
     #[arg(short, long, required = true)]
-    input: std::path::PathBuf,
+    pub input: std::path::PathBuf,
 
     /// approximate number of reads in each sub-RAD file
     /// (Note: This is approximate because file chunks will not be split
     /// and input chunks will reside entirely within a single output file).
-    #[arg(short, long, required = true)]
-    num_reads: usize,
+    #[arg(short, long)]
+    pub num_reads: Option<usize>,
+
+    /// approximate size, in bytes, of the records in each sub-RAD file; a
+    /// new output is started once the chunks written to the current one
+    /// reach this size (as with `--num-reads`, chunks are never split).
+    #[arg(long)]
+    pub max_bytes: Option<u64>,
+
+    /// split into exactly this many output files; whole chunks are assigned
+    /// to the output having the fewest records so far, so the outputs are
+    /// balanced by record count.
+    #[arg(long)]
+    pub num_files: Option<usize>,
+
+    /// rather than splitting by read count, write one output file per
+    /// reference, containing the records whose first (primary) alignment
+    /// is to that reference. This requires decoding and re-chunking the
+    /// records, so it is slower than splitting by read count.
+    #[arg(long)]
+    pub by_ref: bool,
+
+    /// write one output file per sequence of the first K bases of the
+    /// barcodes of a single-cell file (e.g. `<prefix>.ACG.rad`), containing
+    /// the records whose barcodes begin with that sequence; there may be
+    /// up to 4^K outputs, which must not be more than `--max-outputs`, so
+    /// K is at most 8 by default. As with `--by-ref`, this requires decoding and
+    /// re-chunking the records.
+    #[arg(long, value_name = "K")]
+    pub by_barcode_prefix: Option<usize>,
+
+    /// with `--by-ref` (or `--by-barcode-prefix`), the most outputs that
+    /// are kept open at once; when another must be written to, the output
+    /// written to least recently is closed, and reopened to append to if
+    /// more records are written to it
+    #[arg(long, value_name = "N", default_value_t = 256)]
+    pub max_open_outputs: usize,
+
+    /// with `--by-ref` (or `--by-barcode-prefix`), the most outputs that
+    /// may be written; the split fails if the records would be written to
+    /// more outputs than this
+    #[arg(long, value_name = "N", default_value_t = 65_536)]
+    pub max_outputs: usize,
 
-    /// output prefix
+    /// a file listing reference names (or IDs), one per line; only the
+    /// records having an alignment to one of these references are written,
+    /// and the rest are dropped. The records kept are written whole (with
+    /// all of their alignments), and with `--by-ref`, each is written to the
+    /// output of the first listed reference to which it aligns. This can
+    /// only be used with `--by-ref` or `--by-barcode-prefix`, which decode
+    /// the records.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["num_reads", "max_bytes", "num_files"])]
+    pub keep_refs: Option<std::path::PathBuf>,
+
+    /// the type of input RAD file (needed for `--by-ref` and
+    /// `--by-barcode-prefix`); if not provided (or `unknown`), the type
+    /// will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// output prefix; outputs that already exist as FIFOs (named pipes)
+    /// are written to as streams, so their preludes record 0 chunks. As
+    /// the outputs of `--num-files` are all known (and opened) up front,
+    /// that is the mode to use with FIFOs, and the consumer must read from
+    /// all of them at once.
     #[arg(short, long, required = true)]
-    output_prefix: std::path::PathBuf,
+    pub output_prefix: std::path::PathBuf,
 
     /// be quiet (no progress bar or standard output messages)
     #[arg(short, long)]
-    quiet: bool,
+    pub quiet: bool,
+
+    /// how the progress through the input is shown (`--quiet` implies
+    /// `none`)
+    #[arg(long, value_enum, default_value_t = ProgressDisplay::Bar)]
+    pub progress_style: ProgressDisplay,
+
+    /// memory-map the input file rather than using buffered reads; this
+    /// falls back to buffered reads if the input is compressed or can't
+    /// be mapped.
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// don't write any outputs; instead, print the name of each output
+    /// that would be written, with the number of records and chunks it
+    /// would hold. Only the chunk headers of the input are read, so this
+    /// can't be combined with `--by-ref`.
+    #[arg(long, conflicts_with_all = ["by_ref", "by_barcode_prefix"])]
+    pub dry_run: bool,
+
+    /// rather than overwriting any existing outputs having the output
+    /// prefix (i.e. `<prefix>.0.rad`, `<prefix>.1.rad`, ...), number the
+    /// outputs starting from the index after the highest existing one
+    #[arg(long, conflicts_with_all = ["by_ref", "by_barcode_prefix"])]
+    pub append_index: bool,
+
+    /// compress each output with this format, adding the corresponding
+    /// extension (e.g. `<prefix>.0.rad.zst`). Since compressed outputs
+    /// can't be seeked, their preludes always record 0 chunks.
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    pub compress: Compression,
+
+    /// drop the chunks of the input that contain no records, rather than
+    /// copying them to the outputs (with `--by-ref`, the records are
+    /// re-chunked, so empty chunks are always dropped)
+    #[arg(long)]
+    pub prune_empty: bool,
+
+    /// write a JSON summary of the split to this file, listing each output
+    /// with the number of records, chunks and bytes of chunks written to it
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    pub report: Option<std::path::PathBuf>,
+
+    /// resume a split by `--num-reads` or `--max-bytes` that stopped
+    /// partway, starting from this byte offset of the (uncompressed)
+    /// input. This must be the offset of the first chunk of an output, as
+    /// logged (along with the index of that output) as each output is
+    /// finished.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        requires = "resume_file_index",
+        conflicts_with_all = ["num_files", "by_ref", "by_barcode_prefix", "append_index"]
+    )]
+    pub resume_from_offset: Option<u64>,
+
+    /// the index of the first output to write when resuming a split with
+    /// `--resume-from-offset`
+    #[arg(long, value_name = "N", requires = "resume_from_offset")]
+    pub resume_file_index: Option<usize>,
 }
 
-// TODO: There should be a "chunk-type-agnostic" read header function in `libradicl`
-// add this.
-fn read_chunk_header<F: std::io::BufRead>(f: &mut F) -> anyhow::Result<(u32, u32)> {
-    let mut buf = [0u8; 8];
-    f.read_exact(&mut buf)?;
-    let nbytes = buf.pread::<u32>(0)?;
-    let nrec = buf.pread::<u32>(4)?;
-    Ok((nbytes, nrec))
+/// The records, chunks and bytes of chunks written to one output of a split
+#[derive(Serialize, Debug)]
+pub struct OutputSummary {
+    pub file: std::path::PathBuf,
+    pub num_records: u64,
+    pub num_chunks: u64,
+    pub num_bytes: u64,
 }
 
-fn process_file<F: std::io::BufRead + std::io::Seek>(
-    f: &mut F,
-    total_size: u64,
-    in_prelude: &mut libradicl::header::RadPrelude,
-    split_opts: &SplitOpts,
-) -> anyhow::Result<()> {
-    let mut file_ctr = 0_usize;
-    let mut rec_in_current_output = 0_usize;
-    let tag_map = in_prelude.file_tags.try_parse_tags_from_bytes(f)?;
-    in_prelude.hdr.num_chunks = 0;
+/// The JSON summary of a split written with `--report`
+#[derive(Serialize, Debug)]
+pub struct SplitReport<'a> {
+    pub input: &'a std::path::Path,
+    pub outputs: &'a [OutputSummary],
+    pub num_chunks: u64,
+    pub num_records: u64,
+    pub bytes_copied: u64,
+}
+
+/// Write the `--report` of a split that wrote `outputs`, if one was requested
+fn write_report(split_opts: &SplitOpts, outputs: &[OutputSummary]) -> anyhow::Result<()> {
+    let Some(ref path) = split_opts.report else {
+        return Ok(());
+    };
+    let report = SplitReport {
+        input: &split_opts.input,
+        outputs,
+        num_chunks: outputs.iter().map(|o| o.num_chunks).sum(),
+        num_records: outputs.iter().map(|o| o.num_records).sum(),
+        bytes_copied: outputs.iter().map(|o| o.num_bytes).sum(),
+    };
+    let mut w = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(&mut w, &report)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+/// The name of the output of `split` with the given label (i.e. its index,
+/// or reference), including the extension of the output compression
+fn output_name(split_opts: &SplitOpts, label: impl std::fmt::Display) -> std::path::PathBuf {
+    let mut out_name = split_opts.output_prefix.clone();
+    match split_opts.compress.extension() {
+        Some(ext) => out_name.set_extension(format!("{}.rad.{}", label, ext)),
+        None => out_name.set_extension(format!("{}.rad", label)),
+    };
+    out_name
+}
 
-    let out_name_base = split_opts.output_prefix.clone();
-    let mut out_name = out_name_base.clone();
-    out_name.set_extension(format!("{}.rad", file_ctr));
-    if out_name.exists() {
-        std::fs::remove_file(&out_name)?;
+/// The writer of an output of a split. Unless it is compressed (or is
+/// written to a FIFO), the output can be seeked to fill in its number of
+/// chunks once it is known.
+enum OutputWriter {
+    Plain(BufWriter<std::fs::File>),
+    Compressed(CompressedWriter<BufWriter<std::fs::File>>),
+}
+
+impl OutputWriter {
+    /// Reopen the output at `path`, which was written (and closed) by an
+    /// earlier writer, to write after what it already holds. A compressed
+    /// output is continued by a new gzip member (or zstd frame), which is
+    /// read as a continuation of the same stream. A plain output is opened
+    /// for writing (rather than appending) and positioned at its end, so
+    /// that its prelude can still be rewritten when it is finished.
+    fn reopen(path: &std::path::Path, compression: Compression) -> anyhow::Result<Self> {
+        if is_fifo(path) {
+            bail!(
+                "the output {} is a FIFO, so it can't be reopened once it has been closed; raise --max-open-outputs",
+                path.display()
+            );
+        }
+        match compression {
+            Compression::None => {
+                let mut f = std::fs::OpenOptions::new().write(true).open(path)?;
+                f.seek(std::io::SeekFrom::End(0))?;
+                Ok(Self::Plain(buffered_writer(f)))
+            }
+            _ => {
+                let f = std::fs::OpenOptions::new().append(true).open(path)?;
+                Ok(Self::Compressed(compressed_writer(
+                    buffered_writer(f),
+                    compression,
+                )?))
+            }
+        }
     }
 
-    let mut out_writer = BufWriter::new(std::fs::File::create(out_name.clone())?);
-    let mut chunk_buf = Vec::<u8>::new();
+    /// Create (or replace) the output at `path`, compressed as requested.
+    /// If `path` is an existing FIFO, it is opened for writing as it is.
+    fn create(path: &std::path::Path, compression: Compression) -> anyhow::Result<Self> {
+        if is_fifo(path) {
+            let writer = buffered_writer(std::fs::OpenOptions::new().write(true).open(path)?);
+            return Ok(Self::Compressed(compressed_writer(writer, compression)?));
+        }
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let writer = buffered_writer(std::fs::File::create(path)?);
+        match compression {
+            Compression::None => Ok(Self::Plain(writer)),
+            _ => Ok(Self::Compressed(compressed_writer(writer, compression)?)),
+        }
+    }
 
-    let current_offset = f.stream_position().expect("should be able to seek");
-    let remaining = total_size.saturating_sub(current_offset);
+    /// Fill in the true number of chunks in the prelude of this output,
+    /// if it can be seeked, and close it.
+    fn finish(
+        &mut self,
+        prelude: &libradicl::header::RadPrelude,
+        num_chunks: u64,
+    ) -> anyhow::Result<()> {
+        if let Self::Plain(writer) = self {
+            rewrite_num_chunks(writer, prelude, num_chunks)?;
+        }
+        self.close()?;
+        Ok(())
+    }
 
-    let pbar = indicatif::ProgressBar::new(remaining);
-    pbar.set_draw_target(indicatif::ProgressDrawTarget::stderr_with_hz(5));
-    if split_opts.quiet {
-        pbar.set_draw_target(indicatif::ProgressDrawTarget::hidden());
-    } else {
-        pbar.set_style(
-            indicatif::ProgressStyle::with_template(
-                "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}",
-            )
-                .unwrap()
-                .progress_chars("#>-"),
+    /// Flush this output, finishing its compressed stream if it has one
+    /// (so that it must be reopened to write more).
+    fn close(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Compressed(w) => w.finish(),
+        }
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(w) => w.write(buf),
+            Self::Compressed(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Compressed(w) => w.flush(),
+        }
+    }
+}
+
+/// The index after the highest index of the existing outputs of `split`
+/// (or 0 if there are none), found by scanning the output directory for
+/// files named as by [output_name].
+fn next_unused_index(split_opts: &SplitOpts) -> anyhow::Result<usize> {
+    let base = split_opts.output_prefix.with_extension("");
+    let Some(base_name) = base.file_name().and_then(|n| n.to_str()) else {
+        bail!(
+            "the output prefix {} is not a valid file name",
+            split_opts.output_prefix.display()
+        );
+    };
+    let out_dir = match base.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+    if !out_dir.exists() {
+        return Ok(0);
+    }
+    let rad_suffix = match split_opts.compress.extension() {
+        Some(ext) => format!(".rad.{}", ext),
+        None => ".rad".to_string(),
+    };
+    let mut next_index = 0;
+    for entry in std::fs::read_dir(&out_dir)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let index = name
+            .strip_suffix(rad_suffix.as_str())
+            .and_then(|n| n.rsplit_once('.'))
+            .filter(|(stem, _)| *stem == base_name)
+            .and_then(|(_, idx)| idx.parse::<usize>().ok());
+        if let Some(idx) = index {
+            next_index = next_index.max(idx + 1);
+        }
+    }
+    Ok(next_index)
+}
+
+/// Returns `true` if a chunk of `num_new_rec` records should start a new
+/// output of a sequential split (i.e. with `--num-reads` or
+/// `--max-bytes`), rather than being added to the current output, which
+/// holds `rec_in_current_output` records in `bytes_in_current_output`
+/// bytes of chunks.
+fn starts_new_output(
+    split_opts: &SplitOpts,
+    rec_in_current_output: usize,
+    bytes_in_current_output: u64,
+    num_new_rec: usize,
+) -> bool {
+    let reads_full = split_opts
+        .num_reads
+        .is_some_and(|n| rec_in_current_output + num_new_rec >= n);
+    let bytes_full = split_opts
+        .max_bytes
+        .is_some_and(|n| bytes_in_current_output >= n);
+    rec_in_current_output > 0 && (reads_full || bytes_full)
+}
+
+/// The planned contents of one output of a split
+#[derive(Debug, Default, Clone)]
+struct PlannedOutput {
+    num_records: u64,
+    num_chunks: u64,
+}
+
+/// Walk the chunk headers of `f` (skipping the chunk bodies), and plan
+/// the outputs that the split described by `split_opts` would write,
+/// using the same rules for assigning chunks to outputs as the split
+/// itself.
+fn plan_split<F: std::io::BufRead>(
+    f: &mut CountingReader<F>,
+    split_opts: &SplitOpts,
+) -> anyhow::Result<Vec<PlannedOutput>> {
+    let mut outputs = match split_opts.num_files {
+        Some(0) => bail!("--num-files must be at least 1"),
+        Some(n) => vec![PlannedOutput::default(); n],
+        None => vec![PlannedOutput::default()],
+    };
+    let mut bytes_in_current_output = 0_u64;
+    walk_chunk_headers(f, |_, num_bytes, num_rec| {
+        if split_opts.prune_empty && num_rec == 0 {
+            return true;
+        }
+
+        let out = if split_opts.num_files.is_some() {
+            outputs
+                .iter_mut()
+                .min_by_key(|o| o.num_records)
+                .expect("there is at least one output")
+        } else {
+            let current = outputs.last().expect("there is at least one output");
+            if starts_new_output(
+                split_opts,
+                current.num_records as usize,
+                bytes_in_current_output,
+                num_rec as usize,
+            ) {
+                outputs.push(PlannedOutput::default());
+                bytes_in_current_output = 0;
+            }
+            bytes_in_current_output += num_bytes as u64;
+            outputs.last_mut().expect("there is at least one output")
+        };
+        out.num_records += num_rec as u64;
+        out.num_chunks += 1;
+        true
+    })?;
+    Ok(outputs)
+}
+
+/// Read the references listed (by name or ID, one per line) in `path`,
+/// as the set of their IDs in the file described by `prelude`.
+fn read_ref_allowlist(
+    path: &std::path::Path,
+    prelude: &libradicl::header::RadPrelude,
+) -> anyhow::Result<HashSet<u32>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut refs = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let r = line.trim();
+        if !r.is_empty() {
+            refs.push(r.to_string());
+        }
+    }
+    let ids = parse_ref_filter(&refs, prelude)?;
+    if ids.is_empty() {
+        warn!(
+            "{} lists no references, so no records will be kept",
+            path.display()
+        );
+    }
+    Ok(ids)
+}
+
+/// Set (by the SIGINT handler) when a split should stop
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGINT by asking the running split to stop, so that it can
+/// finish the outputs being written; a second SIGINT exits at once. This
+/// should be installed once, before [split] is called.
+pub fn install_interrupt_handler() {
+    let res = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    });
+    if let Err(e) = res {
+        warn!(
+            "couldn't install the interrupt handler ({}); an interrupted split may leave its last output incomplete",
+            e
         );
     }
+}
+
+fn process_file<F: std::io::BufRead>(
+    f: &mut CountingReader<F>,
+    pbar: &indicatif::ProgressBar,
+    in_prelude: &mut libradicl::header::RadPrelude,
+    tag_map: &libradicl::rad_types::TagMap,
+    split_opts: &SplitOpts,
+    first_index: usize,
+) -> anyhow::Result<Vec<OutputSummary>> {
+    let mut file_ctr = first_index;
+    let mut rec_in_current_output = 0_usize;
+    let mut bytes_in_current_output = 0_u64;
+    let mut chunks_in_current_output = 0_u64;
+    if split_opts.num_reads.is_none() && split_opts.max_bytes.is_none() {
+        bail!("one of --num-reads or --max-bytes is required for this split mode");
+    }
+    in_prelude.hdr.num_chunks = 0;
+
+    let mut out_name = output_name(split_opts, file_ctr);
+    let mut out_writer = OutputWriter::create(&out_name, split_opts.compress)?;
+    let mut chunk_buf = Vec::<u8>::new();
+    let mut summaries = Vec::new();
+
     // write the header
     in_prelude.write(&mut out_writer)?;
     tag_map.write_values(&mut out_writer)?;
 
+    // on an interrupt, the chunks copied so far are kept, and the current
+    // output is finished (so that it is a valid RAD file) before stopping.
+    let mut interrupted_at = None;
+    let mut chunk_num = 0_u64;
     while libradicl::utils::has_data_left(f).expect("encountered error reading input file") {
+        let chunk_offset = f.position();
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            interrupted_at = Some(chunk_offset);
+            break;
+        }
         let (num_bytes, num_rec) = read_chunk_header(f)?;
+        count_chunk_read(num_rec as u64);
+        let body_len = chunk_body_len(num_bytes, chunk_num, chunk_offset)?;
+        chunk_num += 1;
+        if split_opts.prune_empty && num_rec == 0 {
+            skip_bytes(f, body_len as u64)?;
+            continue;
+        }
 
         let num_new_rec = num_rec as usize;
-        if rec_in_current_output > 0
-            && (rec_in_current_output + num_new_rec >= split_opts.num_reads)
-        {
-            // finish writing the old file.
-            out_writer.flush()?;
+        if starts_new_output(
+            split_opts,
+            rec_in_current_output,
+            bytes_in_current_output,
+            num_new_rec,
+        ) {
+            // finish writing the old file, now that its number of
+            // chunks is known.
+            out_writer.finish(in_prelude, chunks_in_current_output)?;
+            summaries.push(OutputSummary {
+                file: out_name.clone(),
+                num_records: rec_in_current_output as u64,
+                num_chunks: chunks_in_current_output,
+                num_bytes: bytes_in_current_output,
+            });
 
             // create the new file
             file_ctr += 1;
-            out_name.clone_from(&out_name_base);
-            out_name.set_extension(format!("{}.rad", file_ctr));
-            if out_name.exists() {
-                std::fs::remove_file(&out_name)?;
+            if !split_opts.quiet {
+                info!(
+                    "finished {}; to resume from the next output, use --resume-from-offset {} --resume-file-index {}",
+                    out_name.display(),
+                    chunk_offset,
+                    file_ctr
+                );
             }
-            out_writer = BufWriter::new(std::fs::File::create(out_name.clone())?);
+            out_name = output_name(split_opts, file_ctr);
+            out_writer = OutputWriter::create(&out_name, split_opts.compress)?;
 
             // write the header
             in_prelude.write(&mut out_writer)?;
             tag_map.write_values(&mut out_writer)?;
 
-            // reset rec counter
+            // reset rec, byte and chunk counters
             rec_in_current_output = 0;
+            bytes_in_current_output = 0;
+            chunks_in_current_output = 0;
         }
         rec_in_current_output += num_new_rec;
+        bytes_in_current_output += num_bytes as u64;
+        chunks_in_current_output += 1;
         // copy the chunk
         // first write the header
         out_writer.write_all(&num_bytes.to_le_bytes())?;
         out_writer.write_all(&num_rec.to_le_bytes())?;
         // copy the rest of the chunk
-        chunk_buf.resize((num_bytes - 8) as usize, 0);
+        chunk_buf.resize(body_len, 0);
         f.read_exact(chunk_buf.as_mut_slice())?;
         std::io::copy(&mut &chunk_buf[..], &mut out_writer)?;
-        pbar.inc(num_bytes as u64);
     }
-    out_writer.flush()?;
+    out_writer.finish(in_prelude, chunks_in_current_output)?;
+    summaries.push(OutputSummary {
+        file: out_name,
+        num_records: rec_in_current_output as u64,
+        num_chunks: chunks_in_current_output,
+        num_bytes: bytes_in_current_output,
+    });
     pbar.finish();
+    if let Some(offset) = interrupted_at {
+        let complete: Vec<String> = summaries
+            .iter()
+            .map(|s| s.file.display().to_string())
+            .collect();
+        warn!(
+            "interrupted; the outputs written ({}) are complete RAD files, but the last holds only the chunks copied before the interrupt. To continue after it, use --resume-from-offset {} --resume-file-index {}",
+            complete.join(", "),
+            offset,
+            file_ctr + 1
+        );
+        bail!("split was interrupted");
+    }
     if !split_opts.quiet {
-        info!("generated {} output RAD files", file_ctr + 1);
+        info!("generated {} output RAD files", file_ctr + 1 - first_index);
     }
-    Ok(())
+    Ok(summaries)
 }
 
-pub fn split(split_opts: &SplitOpts) -> anyhow::Result<()> {
-    let fname = split_opts.input.clone();
+/// Log that a split writing the outputs of `summaries` (which have been
+/// finished) was interrupted, returning the error to stop with.
+fn interrupted_error(summaries: &[OutputSummary]) -> anyhow::Error {
+    let complete: Vec<String> = summaries
+        .iter()
+        .map(|s| s.file.display().to_string())
+        .collect();
+    warn!(
+        "interrupted; the outputs written ({}) are complete RAD files, but hold only the records read before the interrupt",
+        complete.join(", ")
+    );
+    anyhow!("split was interrupted")
+}
+
+/// An output file of a split that is written to by chunk (rather than
+/// sequentially). Records are either copied as whole input chunks, or
+/// accumulated into chunks and written as each chunk fills.
+struct SplitOutput {
+    path: std::path::PathBuf,
+    compression: Compression,
+    // this is `None` while the output is closed (see [OutputPool])
+    writer: Option<OutputWriter>,
+    chunk: ChunkBuffer,
+    num_chunks: u64,
+    num_records: u64,
+    num_bytes: u64,
+}
+
+impl SplitOutput {
+    fn create(
+        path: &std::path::Path,
+        compression: Compression,
+        prelude: &libradicl::header::RadPrelude,
+        tag_map: &libradicl::rad_types::TagMap,
+    ) -> anyhow::Result<Self> {
+        let mut writer = OutputWriter::create(path, compression)?;
+        prelude.write(&mut writer)?;
+        tag_map.write_values(&mut writer)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            compression,
+            writer: Some(writer),
+            chunk: ChunkBuffer::new(),
+            num_chunks: 0,
+            num_records: 0,
+            num_bytes: 0,
+        })
+    }
+
+    fn is_open(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn writer(&mut self) -> &mut OutputWriter {
+        self.writer
+            .as_mut()
+            .expect("outputs are reopened before they are written to")
+    }
+
+    /// Write any buffered records, and close this output until it is
+    /// reopened by [SplitOutput::reopen]
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush_chunk()?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> anyhow::Result<()> {
+        if self.writer.is_none() {
+            self.writer = Some(OutputWriter::reopen(&self.path, self.compression)?);
+        }
+        Ok(())
+    }
+
+    /// Copy an input chunk with the given header and `body` (the chunk
+    /// bytes following the header) verbatim to this output.
+    fn write_raw_chunk(&mut self, num_bytes: u32, num_rec: u32, body: &[u8]) -> anyhow::Result<()> {
+        let writer = self.writer();
+        writer.write_all(&num_bytes.to_le_bytes())?;
+        writer.write_all(&num_rec.to_le_bytes())?;
+        writer.write_all(body)?;
+        self.num_chunks += 1;
+        self.num_records += num_rec as u64;
+        self.num_bytes += num_bytes as u64;
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> anyhow::Result<()> {
+        if !self.chunk.is_empty() {
+            self.num_records += self.chunk.num_records() as u64;
+            let writer = self
+                .writer
+                .as_mut()
+                .expect("outputs are reopened before they are written to");
+            self.num_bytes += self.chunk.write_chunk(writer)?;
+            self.num_chunks += 1;
+        }
+        Ok(())
+    }
+
+    /// Write any remaining records and fill in the true number of chunks
+    /// in the prelude of this output (if it isn't compressed), returning
+    /// the summary of what was written. The output is closed afterwards.
+    fn finish(&mut self, prelude: &libradicl::header::RadPrelude) -> anyhow::Result<OutputSummary> {
+        self.reopen()?;
+        self.flush_chunk()?;
+        let num_chunks = self.num_chunks;
+        self.writer().finish(prelude, num_chunks)?;
+        self.writer = None;
+        Ok(OutputSummary {
+            file: self.path.clone(),
+            num_records: self.num_records,
+            num_chunks: self.num_chunks,
+            num_bytes: self.num_bytes,
+        })
+    }
+}
+
+/// The outputs of a split that writes each record to the output for its
+/// key (i.e. its reference, or its barcode prefix), of which there may be
+/// as many as there are keys. At most `--max-open-outputs` of them are
+/// open at once: when another must be written to, the open output written
+/// to least recently is closed, and it is reopened if it is written to
+/// again. At most `--max-outputs` outputs may be created.
+struct OutputPool<K> {
+    outputs: HashMap<K, SplitOutput>,
+    // the keys of the open outputs, by when they were last written to
+    open_by_use: BTreeMap<u64, K>,
+    last_use: HashMap<K, u64>,
+    clock: u64,
+    max_open: usize,
+    max_outputs: usize,
+}
+
+impl<K: Copy + Eq + Ord + std::hash::Hash> OutputPool<K> {
+    fn new(split_opts: &SplitOpts) -> anyhow::Result<Self> {
+        if split_opts.max_open_outputs == 0 {
+            bail!("--max-open-outputs must be at least 1");
+        }
+        Ok(Self {
+            outputs: HashMap::new(),
+            open_by_use: BTreeMap::new(),
+            last_use: HashMap::new(),
+            clock: 0,
+            max_open: split_opts.max_open_outputs,
+            max_outputs: split_opts.max_outputs,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// The output for `key`, which is opened if it isn't open, or created
+    /// (at the path given by `path`) if it doesn't exist yet.
+    fn get(
+        &mut self,
+        key: K,
+        path: impl FnOnce() -> std::path::PathBuf,
+        compression: Compression,
+        prelude: &libradicl::header::RadPrelude,
+        tag_map: &libradicl::rad_types::TagMap,
+    ) -> anyhow::Result<&mut SplitOutput> {
+        let is_open = self.outputs.get(&key).map(SplitOutput::is_open);
+        if is_open.is_none() && self.outputs.len() >= self.max_outputs {
+            bail!(
+                "the records would be written to more than {} outputs; raise --max-outputs (or, with --by-ref, keep fewer references with --keep-refs)",
+                self.max_outputs
+            );
+        }
+        self.clock += 1;
+        if let Some(used) = self.last_use.insert(key, self.clock) {
+            self.open_by_use.remove(&used);
+        }
+        if is_open != Some(true) && self.open_by_use.len() >= self.max_open {
+            if let Some((_, lru)) = self.open_by_use.pop_first() {
+                self.last_use.remove(&lru);
+                if let Some(out) = self.outputs.get_mut(&lru) {
+                    out.close()?;
+                }
+            }
+        }
+        self.open_by_use.insert(self.clock, key);
+        let out = match self.outputs.entry(key) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(SplitOutput::create(&path(), compression, prelude, tag_map)?)
+            }
+        };
+        out.reopen()?;
+        Ok(out)
+    }
+
+    /// Finish all of the outputs, returning their summaries in the order
+    /// of their keys. The open outputs are finished first, so that no more
+    /// than `--max-open-outputs` are ever open.
+    fn finish(
+        &mut self,
+        prelude: &libradicl::header::RadPrelude,
+    ) -> anyhow::Result<Vec<OutputSummary>> {
+        let mut summaries = BTreeMap::new();
+        for (_, key) in std::mem::take(&mut self.open_by_use) {
+            if let Some(out) = self.outputs.get_mut(&key) {
+                summaries.insert(key, out.finish(prelude)?);
+            }
+        }
+        self.last_use.clear();
+        for (key, out) in self.outputs.iter_mut() {
+            if !summaries.contains_key(key) {
+                summaries.insert(*key, out.finish(prelude)?);
+            }
+        }
+        Ok(summaries.into_values().collect())
+    }
+}
+
+/// The number of bytes of the body of a chunk (i.e. following its header)
+/// of `num_bytes` bytes, which is chunk `chunk_num` of the input, at byte
+/// offset `offset`; it is an error if the chunk is too short to hold its
+/// header.
+fn chunk_body_len(num_bytes: u32, chunk_num: u64, offset: u64) -> anyhow::Result<usize> {
+    match num_bytes.checked_sub(8) {
+        Some(n) => Ok(n as usize),
+        None => bail!(
+            "chunk {} (at byte offset {}) has an invalid size of {} bytes",
+            chunk_num,
+            offset,
+            num_bytes
+        ),
+    }
+}
+
+/// Split the chunks of `f` into exactly `--num-files` outputs, assigning
+/// each chunk to the output that currently holds the fewest records.
+fn process_file_num_files<F: std::io::BufRead>(
+    f: &mut CountingReader<F>,
+    pbar: &indicatif::ProgressBar,
+    in_prelude: &mut libradicl::header::RadPrelude,
+    tag_map: &libradicl::rad_types::TagMap,
+    split_opts: &SplitOpts,
+    first_index: usize,
+) -> anyhow::Result<Vec<OutputSummary>> {
+    let num_files = split_opts
+        .num_files
+        .expect("--num-files is required for this split mode");
+    if num_files == 0 {
+        bail!("--num-files must be at least 1");
+    }
+    in_prelude.hdr.num_chunks = 0;
+
+    let mut outputs = Vec::with_capacity(num_files);
+    for file_ctr in first_index..first_index + num_files {
+        let out_name = output_name(split_opts, file_ctr);
+        outputs.push(SplitOutput::create(
+            &out_name,
+            split_opts.compress,
+            in_prelude,
+            tag_map,
+        )?);
+    }
+
+    let mut chunk_buf = Vec::<u8>::new();
+    let mut chunk_num = 0_u64;
+    let mut interrupted = false;
+    while libradicl::utils::has_data_left(f).expect("encountered error reading input file") {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            interrupted = true;
+            break;
+        }
+        let chunk_offset = f.position();
+        let (num_bytes, num_rec) = read_chunk_header(f)?;
+        count_chunk_read(num_rec as u64);
+        let body_len = chunk_body_len(num_bytes, chunk_num, chunk_offset)?;
+        chunk_num += 1;
+        if split_opts.prune_empty && num_rec == 0 {
+            skip_bytes(f, body_len as u64)?;
+            continue;
+        }
+        chunk_buf.resize(body_len, 0);
+        f.read_exact(chunk_buf.as_mut_slice())?;
+
+        // ties go to the lowest index, so this is round-robin
+        // when all chunks hold the same number of records.
+        let out = outputs
+            .iter_mut()
+            .min_by_key(|o| o.num_records)
+            .expect("there is at least one output");
+        out.write_raw_chunk(num_bytes, num_rec, &chunk_buf)?;
+    }
+
+    let summaries = outputs
+        .iter_mut()
+        .map(|out| out.finish(in_prelude))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    pbar.finish();
+    if interrupted {
+        return Err(interrupted_error(&summaries));
+    }
+    if !split_opts.quiet {
+        info!("generated {} output RAD files", num_files);
+    }
+    Ok(summaries)
+}
+
+/// Split the records of `f` into one output per reference, keyed by the
+/// primary (first) reference of each record, or, if `keep_refs` is given,
+/// by the first of its references in `keep_refs` (dropping the records
+/// with none). Records are decoded and re-chunked, so each output's
+/// prelude reflects its own chunk count.
+fn process_file_by_ref<T: SupportedRecord, F: std::io::BufRead>(
+    f: &mut F,
+    pbar: &indicatif::ProgressBar,
+    in_prelude: &mut libradicl::header::RadPrelude,
+    tag_map: &libradicl::rad_types::TagMap,
+    split_opts: &SplitOpts,
+    keep_refs: Option<&HashSet<u32>>,
+) -> anyhow::Result<Vec<OutputSummary>> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(in_prelude)?;
+    let encoder = RecordEncoder::from_prelude::<T>(in_prelude)?;
+    in_prelude.hdr.num_chunks = 0;
+
+    let mut outputs = OutputPool::<u32>::new(split_opts)?;
+    let mut num_unmapped = 0_usize;
+    let mut num_dropped = 0_usize;
+    let mut interrupted = false;
+
+    while libradicl::utils::has_data_left(f).expect("encountered error reading input file") {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            interrupted = true;
+            break;
+        }
+        let chunk = decoder.read_chunk(f)?;
+        for (i, r) in chunk.reads.iter().enumerate() {
+            if r.ref_ids().is_empty() {
+                num_unmapped += 1;
+                continue;
+            }
+            let primary = match keep_refs {
+                Some(keep) => r.ref_ids().iter().find(|id| keep.contains(id)),
+                None => r.ref_ids().first(),
+            };
+            let Some(&primary) = primary else {
+                num_dropped += 1;
+                continue;
+            };
+            let out = outputs.get(
+                primary,
+                || output_name(split_opts, format!("ref{}", primary)),
+                split_opts.compress,
+                in_prelude,
+                tag_map,
+            )?;
+            out.chunk.push(r, chunk.tags(i), &encoder)?;
+            if out.chunk.num_records() >= RECORDS_PER_CHUNK {
+                out.flush_chunk()?;
+            }
+        }
+    }
+
+    // the outputs are summarized in the order of their references
+    let summaries = outputs.finish(in_prelude)?;
+    pbar.finish();
+    if interrupted {
+        return Err(interrupted_error(&summaries));
+    }
+
+    if num_unmapped > 0 {
+        warn!(
+            "{} records had no alignments and were not written to any output",
+            num_unmapped
+        );
+    }
+    if num_dropped > 0 && !split_opts.quiet {
+        info!(
+            "dropped {} records having no alignments to the references kept",
+            num_dropped
+        );
+    }
+    if !split_opts.quiet {
+        info!("generated {} output RAD files", outputs.len());
+    }
+    Ok(summaries)
+}
+
+/// Splits an input by reference with [process_file_by_ref]
+struct SplitByRef<'a, F: std::io::BufRead> {
+    f: &'a mut F,
+    pbar: &'a indicatif::ProgressBar,
+    in_prelude: &'a mut libradicl::header::RadPrelude,
+    tag_map: &'a libradicl::rad_types::TagMap,
+    split_opts: &'a SplitOpts,
+    keep_refs: Option<&'a HashSet<u32>>,
+}
+
+impl<F: std::io::BufRead> RecordTypeVisitor for SplitByRef<'_, F> {
+    type Output = Vec<OutputSummary>;
 
-    let md = std::fs::metadata(&fname)?;
-    let f = std::fs::File::open(fname)?;
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<Self::Output> {
+        process_file_by_ref::<T, _>(
+            self.f,
+            self.pbar,
+            self.in_prelude,
+            self.tag_map,
+            self.split_opts,
+            self.keep_refs,
+        )
+    }
+}
+
+/// Write the records of the single-cell RAD file `f` to one output per
+/// sequence of the first `k` bases of their barcodes, dropping those
+/// having no alignment to one of `keep_refs` (if given).
+fn process_file_by_barcode_prefix<F: std::io::BufRead>(
+    f: &mut F,
+    pbar: &indicatif::ProgressBar,
+    in_prelude: &mut libradicl::header::RadPrelude,
+    tag_map: &libradicl::rad_types::TagMap,
+    split_opts: &SplitOpts,
+    k: usize,
+    keep_refs: Option<&HashSet<u32>>,
+) -> anyhow::Result<Vec<OutputSummary>> {
+    let bc_len: u64 = tag_map
+        .get("cblen")
+        .expect("tag map must contain cblen value")
+        .try_into()?;
+    if k == 0 || k as u64 > bc_len {
+        bail!(
+            "--by-barcode-prefix must be between 1 and the barcode length ({}), but is {}",
+            bc_len,
+            k
+        );
+    }
+    // there may be an output for each of the 4^k prefixes
+    let max_prefixes = 4_u64.checked_pow(k as u32).unwrap_or(u64::MAX);
+    if max_prefixes > split_opts.max_outputs as u64 {
+        bail!(
+            "--by-barcode-prefix {} could write up to 4^{} outputs, more than --max-outputs ({}); use a shorter prefix",
+            k,
+            k,
+            split_opts.max_outputs
+        );
+    }
+    let mut decoder = RecordDecoder::<AlevinFryReadRecord>::from_prelude(in_prelude)?;
+    let encoder = RecordEncoder::from_prelude::<AlevinFryReadRecord>(in_prelude)?;
+    in_prelude.hdr.num_chunks = 0;
+
+    // barcodes are encoded with 2 bits per base, the first base in the
+    // highest bits, so the prefix is the (encoded) barcode shifted right.
+    let shift = 2 * (bc_len - k as u64);
+    let mut outputs = OutputPool::<u64>::new(split_opts)?;
+    let mut num_dropped = 0_usize;
+    let mut interrupted = false;
+
+    while libradicl::utils::has_data_left(f).expect("encountered error reading input file") {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            interrupted = true;
+            break;
+        }
+        let chunk = decoder.read_chunk(f)?;
+        for (i, r) in chunk.reads.iter().enumerate() {
+            if keep_refs.is_some_and(|keep| !r.refs.iter().any(|id| keep.contains(id))) {
+                num_dropped += 1;
+                continue;
+            }
+            let prefix = r.bc >> shift;
+            let out = outputs.get(
+                prefix,
+                || {
+                    let seq = bitmer_to_bytes((prefix, k as u8));
+                    output_name(split_opts, String::from_utf8_lossy(&seq))
+                },
+                split_opts.compress,
+                in_prelude,
+                tag_map,
+            )?;
+            out.chunk.push(r, chunk.tags(i), &encoder)?;
+            if out.chunk.num_records() >= RECORDS_PER_CHUNK {
+                out.flush_chunk()?;
+            }
+        }
+    }
+
+    // the order of the encoded prefixes is the lexicographic order of
+    // their sequences
+    let summaries = outputs.finish(in_prelude)?;
+    pbar.finish();
+    if interrupted {
+        return Err(interrupted_error(&summaries));
+    }
+    if num_dropped > 0 && !split_opts.quiet {
+        info!(
+            "dropped {} records having no alignments to the references kept",
+            num_dropped
+        );
+    }
+    if !split_opts.quiet {
+        info!("generated {} output RAD files", outputs.len());
+    }
+    Ok(summaries)
+}
+
+/// Open the input of `split`, memory-mapping it if `--mmap` was requested
+/// and possible, and falling back to buffered reads otherwise.
+fn open_split_input(
+    split_opts: &SplitOpts,
+    pbar: &indicatif::ProgressBar,
+) -> anyhow::Result<Box<dyn std::io::BufRead>> {
+    if split_opts.mmap {
+        match MmapReader::open(&split_opts.input) {
+            Ok(Some(mut reader)) => {
+                if !is_gzip_compressed(&mut reader)? {
+                    return Ok(Box::new(pbar.wrap_read(ThroughputReader::new(reader))));
+                }
+                info!("input is compressed; using buffered reads rather than mmap");
+            }
+            Ok(None) => {
+                info!("input can't be memory-mapped; using buffered reads");
+            }
+            Err(e) => {
+                warn!(
+                    "failed to memory-map {} ({}); using buffered reads",
+                    split_opts.input.display(),
+                    e
+                );
+            }
+        }
+    }
+    let f = std::fs::File::open(&split_opts.input)?;
+    Ok(rad_reader(pbar.wrap_read(f))?)
+}
+
+pub fn split(split_opts: &SplitOpts) -> anyhow::Result<()> {
+    if is_stdin(&split_opts.input) {
+        bail!("split can't read its input from stdin; please provide a RAD file with --input");
+    }
+    if is_url(&split_opts.input) {
+        bail!(
+            "split can't read its input from a URL ({}); please download it first",
+            split_opts.input.display()
+        );
+    }
+    let md = std::fs::metadata(&split_opts.input)?;
     let file_size = md.len();
-    let mut ifile = BufReader::new(f);
+
+    // progress is tracked in terms of the bytes read from the input file
+    // (which, for compressed input, are the compressed bytes).
+    let display = if split_opts.quiet {
+        ProgressDisplay::None
+    } else {
+        split_opts.progress_style
+    };
+    let pbar = styled_byte_progress_bar(file_size, display);
+
+    // the position of the reader is kept so that resumable offsets can
+    // be reported (and resumed from).
+    let mut ifile = CountingReader::new(open_split_input(split_opts, &pbar)?);
     let mut in_prelude = libradicl::header::RadPrelude::from_bytes(&mut ifile)?;
-    process_file(&mut ifile, file_size, &mut in_prelude, split_opts)
+    let tag_map = in_prelude.file_tags.try_parse_tags_from_bytes(&mut ifile)?;
+
+    // rather than writing a single empty output, write nothing at all.
+    if !libradicl::utils::has_data_left(&mut ifile)? {
+        pbar.finish();
+        warn!(
+            "{} contains no chunks; no output RAD files were written",
+            split_opts.input.display()
+        );
+        return write_report(split_opts, &[]);
+    }
+
+    if let Some(offset) = split_opts.resume_from_offset {
+        let Some(to_skip) = offset.checked_sub(ifile.position()) else {
+            bail!(
+                "cannot resume from offset {}, which is within the header of {} (the first chunk is at offset {})",
+                offset,
+                split_opts.input.display(),
+                ifile.position()
+            );
+        };
+        skip_bytes(&mut ifile, to_skip)?;
+        if !libradicl::utils::has_data_left(&mut ifile)? {
+            pbar.finish();
+            info!(
+                "there are no chunks after offset {}; nothing left to split",
+                offset
+            );
+            return write_report(split_opts, &[]);
+        }
+    }
+
+    let first_index = if let Some(idx) = split_opts.resume_file_index {
+        idx
+    } else if split_opts.append_index {
+        next_unused_index(split_opts)?
+    } else {
+        0
+    };
+    if first_index > 0 && !split_opts.quiet {
+        info!("numbering the outputs from {}", first_index);
+    }
+
+    if split_opts.dry_run {
+        if split_opts.num_files.is_none()
+            && split_opts.num_reads.is_none()
+            && split_opts.max_bytes.is_none()
+        {
+            bail!("one of --num-reads, --max-bytes or --num-files is required with --dry-run");
+        }
+        let outputs = plan_split(&mut ifile, split_opts)?;
+        pbar.finish();
+        println!("file\tnum_records\tnum_chunks");
+        for (file_ctr, out) in outputs.iter().enumerate() {
+            println!(
+                "{}\t{}\t{}",
+                output_name(split_opts, first_index + file_ctr).display(),
+                out.num_records,
+                out.num_chunks
+            );
+        }
+        if !split_opts.quiet {
+            info!("would generate {} output RAD files", outputs.len());
+        }
+        return Ok(());
+    }
+
+    if split_opts.num_files.is_none()
+        && !split_opts.by_ref
+        && split_opts.by_barcode_prefix.is_none()
+        && is_fifo(output_name(split_opts, first_index))
+    {
+        warn!(
+            "the outputs are FIFOs, but without --num-files the number of outputs isn't known in advance; any output that isn't an existing FIFO will be written as a regular file"
+        );
+    }
+
+    let keep_refs = split_opts
+        .keep_refs
+        .as_ref()
+        .map(|path| read_ref_allowlist(path, &in_prelude))
+        .transpose()?;
+
+    let summaries = if split_opts.num_files.is_some() {
+        process_file_num_files(
+            &mut ifile,
+            &pbar,
+            &mut in_prelude,
+            &tag_map,
+            split_opts,
+            first_index,
+        )?
+    } else if let Some(k) = split_opts.by_barcode_prefix {
+        let rad_type = resolve_rad_type(
+            split_opts.rad_type.as_ref(),
+            &in_prelude,
+            &tag_map,
+            &split_opts.input,
+        )?;
+        if rad_type != RadFileType::SingleCell {
+            bail!("--by-barcode-prefix can only be used with single-cell RAD files");
+        }
+        process_file_by_barcode_prefix(
+            &mut ifile,
+            &pbar,
+            &mut in_prelude,
+            &tag_map,
+            split_opts,
+            k,
+            keep_refs.as_ref(),
+        )?
+    } else if !split_opts.by_ref {
+        process_file(
+            &mut ifile,
+            &pbar,
+            &mut in_prelude,
+            &tag_map,
+            split_opts,
+            first_index,
+        )?
+    } else {
+        let rad_type = resolve_rad_type(
+            split_opts.rad_type.as_ref(),
+            &in_prelude,
+            &tag_map,
+            &split_opts.input,
+        )?;
+        with_record_type(
+            &rad_type,
+            SplitByRef {
+                f: &mut ifile,
+                pbar: &pbar,
+                in_prelude: &mut in_prelude,
+                tag_map: &tag_map,
+                split_opts,
+                keep_refs: keep_refs.as_ref(),
+            },
+        )?
+    };
+    write_report(split_opts, &summaries)
 }
@@ -0,0 +1,268 @@
+use clap::Parser;
+use libradicl::record::{AlevinFryReadRecord, PiscemBulkReadRecord};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+use crate::record::{with_record_type, RecordDecoder, RecordTypeVisitor, SupportedRecord};
+use crate::utils::{open_and_parse, walk_chunk_headers, CountingReader, ParsedRad};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to summarizing a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct StatsOpts {
+    /// the input RAD file to summarize; if not provided (or `-`), the RAD
+    /// file is read from the standard input
+    #[arg(short, long, default_value = "-")]
+    pub input: std::path::PathBuf,
+
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
+    #[arg(short, long)]
+    pub rad_type: Option<RadFileType>,
+
+    /// write the summary as a JSON object rather than a table
+    #[arg(long)]
+    pub json: bool,
+
+    /// count only the chunks and records, from the chunk headers, without
+    /// decoding the records; this is much faster on large files, but none
+    /// of the alignment-level statistics are computed
+    #[arg(long, alias = "count-only")]
+    pub fast: bool,
+}
+
+/// The per-record information needed to accumulate summary statistics
+pub trait RecordStats {
+    /// the number of alignments in this record
+    fn num_alns(&self) -> usize;
+    /// the mapping type of this record, if the record type has one
+    fn mapping_type(&self) -> Option<String>;
+}
+
+impl RecordStats for PiscemBulkReadRecord {
+    fn num_alns(&self) -> usize {
+        self.refs.len()
+    }
+    fn mapping_type(&self) -> Option<String> {
+        Some(format!(
+            "{:?}",
+            libradicl::rad_types::MappingType::from_u8(self.frag_type)
+        ))
+    }
+}
+
+impl RecordStats for AlevinFryReadRecord {
+    fn num_alns(&self) -> usize {
+        self.refs.len()
+    }
+    fn mapping_type(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Summary statistics of a RAD file
+#[derive(Serialize, Debug, Default)]
+pub struct RadStats {
+    pub num_refs: u64,
+    pub num_chunks: u64,
+    pub total_records: u64,
+    pub total_alns: u64,
+    pub min_alns: Option<usize>,
+    pub max_alns: Option<usize>,
+    pub mean_alns: Option<f64>,
+    /// the number of records having more than one alignment
+    pub multi_aln_records: u64,
+    /// the fraction of records having more than one alignment
+    pub multi_aln_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mapping_types: Option<BTreeMap<String, u64>>,
+}
+
+impl RadStats {
+    fn add_record<R: RecordStats>(&mut self, r: &R) {
+        let na = r.num_alns();
+        self.total_records += 1;
+        self.total_alns += na as u64;
+        self.min_alns = Some(self.min_alns.map_or(na, |m| m.min(na)));
+        self.max_alns = Some(self.max_alns.map_or(na, |m| m.max(na)));
+        if na > 1 {
+            self.multi_aln_records += 1;
+        }
+        if let Some(mt) = r.mapping_type() {
+            *self
+                .mapping_types
+                .get_or_insert_with(BTreeMap::new)
+                .entry(mt)
+                .or_insert(0) += 1;
+        }
+    }
+
+    fn finalize(&mut self) {
+        if self.total_records > 0 {
+            self.mean_alns = Some(self.total_alns as f64 / self.total_records as f64);
+            self.multi_aln_fraction =
+                Some(self.multi_aln_records as f64 / self.total_records as f64);
+        }
+    }
+
+    fn print_table(&self) {
+        let or_na = |v: Option<String>| v.unwrap_or_else(|| "NA".to_string());
+        println!("{:<20}\t{}", "num_refs", self.num_refs);
+        println!("{:<20}\t{}", "num_chunks", self.num_chunks);
+        println!("{:<20}\t{}", "total_records", self.total_records);
+        println!("{:<20}\t{}", "total_alns", self.total_alns);
+        println!(
+            "{:<20}\t{}",
+            "min_alns",
+            or_na(self.min_alns.map(|x| x.to_string()))
+        );
+        println!(
+            "{:<20}\t{}",
+            "max_alns",
+            or_na(self.max_alns.map(|x| x.to_string()))
+        );
+        println!(
+            "{:<20}\t{}",
+            "mean_alns",
+            or_na(self.mean_alns.map(|x| format!("{:.3}", x)))
+        );
+        println!("{:<20}\t{}", "multi_aln_records", self.multi_aln_records);
+        println!(
+            "{:<20}\t{}",
+            "multi_aln_fraction",
+            or_na(self.multi_aln_fraction.map(|x| format!("{:.3}", x)))
+        );
+        if let Some(ref mts) = self.mapping_types {
+            println!("mapping types");
+            for (mt, count) in mts {
+                println!("  {:<18}\t{}", mt, count);
+            }
+        }
+    }
+}
+
+/// The statistics that are not computed by [count_chunks_and_records]
+pub const FAST_UNAVAILABLE_STATS: &[&str] = &[
+    "total_alns",
+    "min_alns",
+    "max_alns",
+    "mean_alns",
+    "multi_aln_records",
+    "multi_aln_fraction",
+    "mapping_types",
+];
+
+/// The summary of a RAD file computed (only) from its chunk headers
+#[derive(Serialize, Debug, Default)]
+pub struct RadCounts {
+    pub num_refs: u64,
+    pub num_chunks: u64,
+    pub total_records: u64,
+    /// the statistics that were not computed
+    pub unavailable: Vec<&'static str>,
+}
+
+impl RadCounts {
+    fn print_table(&self) {
+        println!("{:<20}\t{}", "num_refs", self.num_refs);
+        println!("{:<20}\t{}", "num_chunks", self.num_chunks);
+        println!("{:<20}\t{}", "total_records", self.total_records);
+        for s in self.unavailable.iter() {
+            println!("{:<20}\tNA (not computed with --fast)", s);
+        }
+    }
+}
+
+/// Count the chunks and records of `ifile` by walking the chunk headers,
+/// skipping over the body of each chunk without decoding its records.
+pub fn count_chunks_and_records<R: BufRead>(
+    ifile: &mut CountingReader<R>,
+    counts: &mut RadCounts,
+) -> anyhow::Result<()> {
+    counts.num_chunks += walk_chunk_headers(ifile, |_, _, nrec| {
+        counts.total_records += nrec as u64;
+        true
+    })?;
+    Ok(())
+}
+
+pub fn accumulate_stats<T: SupportedRecord, R: BufRead>(
+    prelude: &libradicl::header::RadPrelude,
+    ifile: &mut R,
+    stats: &mut RadStats,
+) -> anyhow::Result<()> {
+    let mut decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    while libradicl::utils::has_data_left(ifile)? {
+        let chunk = decoder.read_chunk(ifile)?;
+        stats.num_chunks += 1;
+        for r in chunk.reads.iter() {
+            stats.add_record(r);
+        }
+    }
+    Ok(())
+}
+
+/// Accumulates the statistics of the records of `input`
+struct AccumulateStats<'a> {
+    input: &'a mut ParsedRad,
+    stats: &'a mut RadStats,
+}
+
+impl RecordTypeVisitor for AccumulateStats<'_> {
+    type Output = ();
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<()> {
+        accumulate_stats::<T, _>(&self.input.prelude, &mut self.input.reader, self.stats)
+    }
+}
+
+pub fn stats(stats_opts: &StatsOpts) -> anyhow::Result<()> {
+    let mut input = open_and_parse(&stats_opts.input)?;
+
+    if stats_opts.fast {
+        let mut counts = RadCounts {
+            num_refs: input.prelude.hdr.ref_count,
+            unavailable: FAST_UNAVAILABLE_STATS.to_vec(),
+            ..Default::default()
+        };
+        let mut ifile = CountingReader::with_position(input.reader, input.first_chunk_offset);
+        count_chunks_and_records(&mut ifile, &mut counts)?;
+        if stats_opts.json {
+            serde_json::to_writer_pretty(std::io::stdout(), &counts)?;
+            println!();
+        } else {
+            counts.print_table();
+        }
+        return Ok(());
+    }
+
+    let rad_type = resolve_rad_type(
+        stats_opts.rad_type.as_ref(),
+        &input.prelude,
+        &input.tag_map,
+        &stats_opts.input,
+    )?;
+
+    let mut stats = RadStats {
+        num_refs: input.prelude.hdr.ref_count,
+        ..Default::default()
+    };
+    with_record_type(
+        &rad_type,
+        AccumulateStats {
+            input: &mut input,
+            stats: &mut stats,
+        },
+    )?;
+    stats.finalize();
+
+    if stats_opts.json {
+        serde_json::to_writer_pretty(std::io::stdout(), &stats)?;
+        println!();
+    } else {
+        stats.print_table();
+    }
+    Ok(())
+}
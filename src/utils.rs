@@ -0,0 +1,773 @@
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use scroll::Pread;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tracing::info;
+
+use crate::view::{detect_rad_type, RadFileType};
+
+/// The magic bytes with which every gzip stream begins
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Returns `true` if the next bytes available from `reader` are the gzip
+/// magic bytes. This only peeks at the buffered data, so nothing is consumed.
+pub fn is_gzip_compressed<R: BufRead>(reader: &mut R) -> std::io::Result<bool> {
+    let buf = reader.fill_buf()?;
+    Ok(buf.starts_with(&GZIP_MAGIC))
+}
+
+/// The default capacity, in bytes, of the buffers of the readers and
+/// writers of RAD files
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 1 << 20;
+
+static IO_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_IO_BUFFER_SIZE);
+
+/// Set the capacity, in bytes, of the buffers of the RAD file readers and
+/// writers created from now on (e.g. by [rad_reader] and [buffered_writer])
+pub fn set_io_buffer_size(size: usize) {
+    IO_BUFFER_SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+/// The capacity, in bytes, of the buffers of RAD file readers and writers
+pub fn io_buffer_size() -> usize {
+    IO_BUFFER_SIZE.load(Ordering::Relaxed)
+}
+
+/// The number of chunks (and of the records they hold) read, and of bytes
+/// read from the RAD inputs (before any decompression), by the command
+/// being run, from which its throughput is reported with `--timing`
+static CHUNKS_READ: AtomicU64 = AtomicU64::new(0);
+static RECORDS_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Count a chunk of `nrec` records towards the chunks and records read.
+/// This is done by the loops that process the records of each chunk (by
+/// decoding or copying them), not by those that only skip over chunks.
+pub fn count_chunk_read(nrec: u64) {
+    CHUNKS_READ.fetch_add(1, Ordering::Relaxed);
+    RECORDS_READ.fetch_add(nrec, Ordering::Relaxed);
+}
+
+/// A reader that counts the bytes read through it towards the bytes read
+/// from the RAD inputs
+pub struct ThroughputReader<R> {
+    inner: R,
+}
+
+impl<R> ThroughputReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Read> Read for ThroughputReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        BYTES_READ.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for ThroughputReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        BYTES_READ.fetch_add(amt as u64, Ordering::Relaxed);
+        self.inner.consume(amt)
+    }
+}
+
+/// Log the rates at which chunks, records and bytes of input were read
+/// by the command being run, which took `elapsed` to run
+pub fn report_throughput(elapsed: std::time::Duration) {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    let chunks = CHUNKS_READ.load(Ordering::Relaxed);
+    let records = RECORDS_READ.load(Ordering::Relaxed);
+    let mb = BYTES_READ.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    info!(
+        "finished in {:.3}s; read {} chunks ({:.1} chunks/s), {} records ({:.1} records/s) and {:.1} MB of input ({:.1} MB/s)",
+        secs,
+        chunks,
+        chunks as f64 / secs,
+        records,
+        records as f64 / secs,
+        mb,
+        mb / secs
+    );
+}
+
+/// Wrap `writer` in a buffered writer having a buffer of [io_buffer_size] bytes
+pub fn buffered_writer<W: Write>(writer: W) -> std::io::BufWriter<W> {
+    std::io::BufWriter::with_capacity(io_buffer_size(), writer)
+}
+
+/// Wrap `reader` in a buffered reader having a buffer of [io_buffer_size]
+/// bytes, whose bytes are counted towards the reported throughput. Unlike
+/// [rad_reader], the stream is never checked for compression, so this is
+/// what to use for a reader positioned in the middle of a RAD file.
+pub fn buffered_reader<R: Read>(reader: R) -> BufReader<ThroughputReader<R>> {
+    BufReader::with_capacity(io_buffer_size(), ThroughputReader::new(reader))
+}
+
+/// Wrap `reader` in a buffered reader from which the (uncompressed) bytes
+/// of a RAD file can be read. If the underlying stream is gzip-compressed,
+/// it is transparently decompressed, otherwise it is read as-is.
+pub fn rad_reader<R: Read + 'static>(reader: R) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = buffered_reader(reader);
+    if is_gzip_compressed(&mut reader)? {
+        Ok(Box::new(BufReader::with_capacity(
+            io_buffer_size(),
+            MultiGzDecoder::new(reader),
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Returns `true` if `path` is `-`, which denotes the standard input.
+pub fn is_stdin<P: AsRef<std::path::Path>>(path: P) -> bool {
+    path.as_ref().as_os_str() == "-"
+}
+
+/// Returns `true` if `path` is an `http://` or `https://` URL, from which
+/// a RAD file can be streamed (but not seeked).
+pub fn is_url<P: AsRef<std::path::Path>>(path: P) -> bool {
+    path.as_ref()
+        .to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// Open a stream of the body of the response to a GET request for `url`
+#[cfg(feature = "http")]
+fn open_url(url: &str) -> anyhow::Result<Box<dyn BufRead>> {
+    // the whole file is streamed as the response body, so there is no
+    // overall timeout (which would otherwise cut off large files)
+    let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+    let response = client.get(url).send()?.error_for_status()?;
+    Ok(rad_reader(response)?)
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(_url: &str) -> anyhow::Result<Box<dyn BufRead>> {
+    anyhow::bail!("reading RAD files from URLs requires radtk to be built with the `http` feature")
+}
+
+/// Returns `true` if `path` is a named pipe (FIFO), which can only be
+/// written to as a stream, and must not be removed or truncated.
+pub fn is_fifo<P: AsRef<std::path::Path>>(path: P) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata(path).is_ok_and(|md| md.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// Open the RAD file at `path` for reading, transparently decompressing
+/// it if it is gzip-compressed. If `path` is `-`, the standard input is
+/// read instead, and if it is an `http(s)://` URL, the file is streamed
+/// from there.
+pub fn open_rad_input<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Box<dyn BufRead>> {
+    if is_stdin(&path) {
+        return Ok(rad_reader(BufReader::new(std::io::stdin().lock()))?);
+    }
+    if is_url(&path) {
+        let url = path.as_ref().to_string_lossy();
+        return open_url(&url);
+    }
+    let f = std::fs::File::open(path)?;
+    Ok(rad_reader(f)?)
+}
+
+/// A RAD file whose prelude and file-level tags have been parsed, along
+/// with the reader from which its chunks can be read.
+pub struct ParsedRad {
+    /// the reader, positioned at the first chunk
+    pub reader: Box<dyn BufRead>,
+    pub prelude: libradicl::header::RadPrelude,
+    pub tag_map: libradicl::rad_types::TagMap,
+    /// the type detected from the prelude and file-level tags
+    pub rad_type: RadFileType,
+    /// the byte offset of the first chunk (within the uncompressed input),
+    /// i.e. the combined length of the prelude and file-level tags
+    pub first_chunk_offset: u64,
+}
+
+impl ParsedRad {
+    /// Parse the prelude and file-level tags of the RAD file read by `reader`
+    pub fn from_reader(reader: Box<dyn BufRead>) -> anyhow::Result<Self> {
+        Self::from_reader_with(reader, libradicl::header::RadPrelude::from_bytes)
+    }
+
+    /// Parse the prelude of the RAD file read by `reader` with
+    /// `read_prelude`, and then its file-level tags.
+    pub fn from_reader_with<F>(
+        mut reader: Box<dyn BufRead>,
+        read_prelude: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: FnOnce(
+            &mut CountingReader<&mut Box<dyn BufRead>>,
+        ) -> anyhow::Result<libradicl::header::RadPrelude>,
+    {
+        let mut counting = CountingReader::new(&mut reader);
+        let prelude = read_prelude(&mut counting)?;
+        let tag_map = prelude.file_tags.try_parse_tags_from_bytes(&mut counting)?;
+        let first_chunk_offset = counting.position();
+        let rad_type = detect_rad_type(&prelude, &tag_map);
+        Ok(Self {
+            reader,
+            prelude,
+            tag_map,
+            rad_type,
+            first_chunk_offset,
+        })
+    }
+}
+
+/// Open the RAD file at `path` (as with [open_rad_input]), and parse its
+/// prelude and file-level tags.
+pub fn open_and_parse<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<ParsedRad> {
+    ParsedRad::from_reader(open_rad_input(path)?)
+}
+
+/// The compression formats in which output may be written
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Infer the compression that should be used for the output file at
+    /// `path` from its extension (`.gz` or `.zst`).
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// The extension of files compressed in this format, if any
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+/// A writer of output that may be compressed. Once all of the output has
+/// been written, it must be finished by [CompressedWriter::finish], which
+/// writes the trailer of a compressed stream and reports any error in
+/// doing so (which dropping the encoder would ignore).
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Finish the compressed stream, if any, and flush the underlying
+    /// writer. Nothing more should be written afterwards.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Gzip(w) => {
+                w.try_finish()?;
+                w.get_mut().flush()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => {
+                w.do_finish()?;
+                w.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(w) => w.write(buf),
+            Self::Gzip(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Gzip(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// Wrap `writer` in an encoder for the requested `compression`.
+pub fn compressed_writer<W: Write>(
+    writer: W,
+    compression: Compression,
+) -> anyhow::Result<CompressedWriter<W>> {
+    match compression {
+        Compression::None => Ok(CompressedWriter::Plain(writer)),
+        Compression::Gzip => Ok(CompressedWriter::Gzip(flate2::write::GzEncoder::new(
+            writer,
+            flate2::Compression::default(),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(CompressedWriter::Zstd(zstd::stream::write::Encoder::new(
+            writer, 0,
+        )?)),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => {
+            anyhow::bail!("zstd compression requires radtk to be built with the `zstd` feature")
+        }
+    }
+}
+
+/// How the progress through the input of a command is shown
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ProgressDisplay {
+    /// a bar, with the number of bytes processed and an ETA
+    Bar,
+    /// a spinner, with the number of bytes processed and the throughput
+    Spinner,
+    /// no progress is shown
+    None,
+}
+
+/// Create a progress bar, drawn to stderr, tracking the progress through
+/// `total_bytes` bytes of input. If `quiet` is set, the bar is hidden.
+pub fn byte_progress_bar(total_bytes: u64, quiet: bool) -> indicatif::ProgressBar {
+    let display = if quiet {
+        ProgressDisplay::None
+    } else {
+        ProgressDisplay::Bar
+    };
+    styled_byte_progress_bar(total_bytes, display)
+}
+
+/// As [byte_progress_bar], but shown as given by `display`
+pub fn styled_byte_progress_bar(
+    total_bytes: u64,
+    display: ProgressDisplay,
+) -> indicatif::ProgressBar {
+    let template = match display {
+        ProgressDisplay::Bar => {
+            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} (ETA {eta})"
+        }
+        ProgressDisplay::Spinner => {
+            "{spinner:.green} [{elapsed_precise}] {bytes} ({binary_bytes_per_sec})"
+        }
+        ProgressDisplay::None => return indicatif::ProgressBar::hidden(),
+    };
+    let pbar = indicatif::ProgressBar::new(total_bytes);
+    pbar.set_draw_target(indicatif::ProgressDrawTarget::stderr_with_hz(5));
+    pbar.set_style(
+        indicatif::ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pbar
+}
+
+/// Create a progress bar, drawn to stderr, tracking the progress through
+/// the chunks of a RAD file. If the number of chunks isn't known (i.e.
+/// `num_chunks` is 0), a spinner counting the chunks is shown instead. If
+/// `quiet` is set, the bar is hidden.
+pub fn chunk_progress_bar(num_chunks: u64, quiet: bool) -> indicatif::ProgressBar {
+    if quiet {
+        return indicatif::ProgressBar::hidden();
+    }
+    let (pbar, template) = if num_chunks > 0 {
+        (
+            indicatif::ProgressBar::new(num_chunks),
+            "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} chunks",
+        )
+    } else {
+        (
+            indicatif::ProgressBar::new_spinner(),
+            "{spinner:.green} [{elapsed_precise}] {pos} chunks",
+        )
+    };
+    pbar.set_draw_target(indicatif::ProgressDrawTarget::stderr_with_hz(5));
+    pbar.set_style(
+        indicatif::ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pbar
+}
+
+// TODO: There should be a "chunk-type-agnostic" read header function in `libradicl`
+// add this.
+/// Read the header of the next chunk from `f`, returning the number of
+/// bytes in the chunk (including this header) and the number of records
+/// it contains.
+pub fn read_chunk_header<F: BufRead>(f: &mut F) -> anyhow::Result<(u32, u32)> {
+    let mut buf = [0u8; 8];
+    f.read_exact(&mut buf)?;
+    let nbytes = buf.pread::<u32>(0)?;
+    let nrec = buf.pread::<u32>(4)?;
+    Ok((nbytes, nrec))
+}
+
+/// Skip over the next `nbytes` bytes of `f`. This does not require the
+/// input to be seekable, and it is an error if fewer than `nbytes` bytes
+/// remain.
+pub fn skip_bytes<F: BufRead>(f: &mut F, nbytes: u64) -> anyhow::Result<()> {
+    let skipped = std::io::copy(&mut f.by_ref().take(nbytes), &mut std::io::sink())?;
+    if skipped < nbytes {
+        anyhow::bail!(
+            "expected to skip {} bytes, but only {} bytes remained",
+            nbytes,
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// Walk the chunks of `reader`, which should be positioned at the start of
+/// a chunk, reading only the `(nbytes, nrec)` header of each chunk and
+/// advancing exactly `nbytes`, without decoding any records. After each
+/// chunk, `on_chunk(offset, nbytes, nrec)` is called, where `offset` is the
+/// byte offset at which the chunk begins, and the walk stops early if it
+/// returns `false`. Returns the number of chunks walked, or an error giving
+/// the byte offset of the first chunk that is truncated or that reports a
+/// size smaller than its own header.
+pub fn walk_chunk_headers<R: BufRead, F: FnMut(u64, u32, u32) -> bool>(
+    reader: &mut CountingReader<R>,
+    mut on_chunk: F,
+) -> anyhow::Result<u64> {
+    let mut num_chunks = 0_u64;
+    let mut hdr_buf = [0u8; 8];
+    while libradicl::utils::has_data_left(reader)? {
+        let chunk_offset = reader.position();
+
+        let nread = std::io::copy(&mut reader.by_ref().take(8), &mut &mut hdr_buf[..])?;
+        if nread < 8 {
+            anyhow::bail!(
+                "truncated header for chunk {} at byte offset {}",
+                num_chunks,
+                chunk_offset
+            );
+        }
+        let nbytes = u32::from_le_bytes(hdr_buf[0..4].try_into()?);
+        let nrec = u32::from_le_bytes(hdr_buf[4..8].try_into()?);
+        if nbytes < 8 {
+            anyhow::bail!(
+                "chunk {} at byte offset {} reports an invalid size of {} bytes",
+                num_chunks,
+                chunk_offset,
+                nbytes
+            );
+        }
+
+        let body_len = nbytes as u64 - 8;
+        let skipped = std::io::copy(&mut reader.by_ref().take(body_len), &mut std::io::sink())?;
+        if skipped < body_len {
+            anyhow::bail!(
+                "chunk {} at byte offset {} is truncated; expected {} bytes but only {} remain",
+                num_chunks,
+                chunk_offset,
+                nbytes,
+                skipped + 8
+            );
+        }
+
+        num_chunks += 1;
+        if !on_chunk(chunk_offset, nbytes, nrec) {
+            break;
+        }
+    }
+    Ok(num_chunks)
+}
+
+/// The byte offset of the `num_chunks` field within a RAD file having
+/// the given prelude; this field directly follows the reference names.
+pub fn num_chunks_offset(prelude: &libradicl::header::RadPrelude) -> u64 {
+    // is_paired (u8) + ref_count (u64) + each name (u16 length + bytes)
+    let names_len: u64 = prelude
+        .hdr
+        .ref_names
+        .iter()
+        .map(|n| 2 + n.len() as u64)
+        .sum();
+    1 + 8 + names_len
+}
+
+/// Overwrite the `num_chunks` field of the RAD file `f`, which was
+/// written with the given prelude, with `num_chunks`. The stream position
+/// of `f` is restored afterward.
+pub fn rewrite_num_chunks<F: std::io::Write + std::io::Seek>(
+    f: &mut F,
+    prelude: &libradicl::header::RadPrelude,
+    num_chunks: u64,
+) -> anyhow::Result<()> {
+    let pos = f.stream_position()?;
+    f.seek(std::io::SeekFrom::Start(num_chunks_offset(prelude)))?;
+    f.write_all(&num_chunks.to_le_bytes())?;
+    f.seek(std::io::SeekFrom::Start(pos))?;
+    Ok(())
+}
+
+/// A reader that keeps track of the number of bytes that have been
+/// consumed from the underlying reader, so that byte offsets can be
+/// reported for streams that are not seekable.
+pub struct CountingReader<R: BufRead> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: BufRead> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_position(inner, 0)
+    }
+
+    /// Count the bytes consumed from `inner` from `position`, the offset
+    /// at which it is positioned in the underlying stream
+    pub fn with_position(inner: R, position: u64) -> Self {
+        Self { inner, position }
+    }
+
+    /// the number of bytes consumed so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+/// A reader over a memory-mapped file. Since the entire file is directly
+/// addressable, reading and seeking never need to copy through an
+/// intermediate buffer.
+pub struct MmapReader {
+    map: memmap2::Mmap,
+    position: usize,
+}
+
+impl MmapReader {
+    /// Memory-map the file at `path` for reading. Returns `Ok(None)` if the
+    /// file can't sensibly be mapped (i.e. it is empty, or its size changed
+    /// while it was being mapped), in which case the caller should fall back
+    /// to buffered I/O.
+    ///
+    /// Note that the file must not be truncated while the map is live.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Option<Self>> {
+        let f = std::fs::File::open(path)?;
+        let len = f.metadata()?.len();
+        if len == 0 {
+            return Ok(None);
+        }
+        // SAFETY: the map is only ever read, and we check below that the
+        // file was not resized while it was being mapped.
+        let map = unsafe { memmap2::Mmap::map(&f)? };
+        if map.len() as u64 != len || f.metadata()?.len() != len {
+            return Ok(None);
+        }
+        Ok(Some(Self { map, position: 0 }))
+    }
+
+    /// the total length of the mapped file
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for MmapReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(&self.map[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.map.len());
+    }
+}
+
+impl std::io::Seek for MmapReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            std::io::SeekFrom::Start(p) => Some(p),
+            std::io::SeekFrom::End(d) => (self.map.len() as u64).checked_add_signed(d),
+            std::io::SeekFrom::Current(d) => (self.position as u64).checked_add_signed(d),
+        };
+        match new_pos {
+            Some(p) => {
+                // seeking past the end leaves the reader at the end
+                self.position = p.min(self.map.len() as u64) as usize;
+                Ok(self.position as u64)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    /// The type IDs of the integer tag types
+    pub(crate) const U8: u8 = 1;
+    pub(crate) const U16: u8 = 2;
+    pub(crate) const U32: u8 = 3;
+
+    fn push_name(buf: &mut Vec<u8>, name: &str) {
+        buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+    }
+
+    fn push_tag_section(buf: &mut Vec<u8>, tags: &[(&str, u8)]) {
+        buf.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        for (name, typeid) in tags {
+            push_name(buf, name);
+            buf.push(*typeid);
+        }
+    }
+
+    /// The bytes of the prelude of a RAD file having references named
+    /// `ref_names`, no file-level tags, and the given read-level and
+    /// alignment-level tags (as pairs of their names and type IDs)
+    pub(crate) fn prelude_bytes(
+        ref_names: &[&str],
+        read_tags: &[(&str, u8)],
+        aln_tags: &[(&str, u8)],
+    ) -> Vec<u8> {
+        let mut buf = vec![0_u8];
+        buf.extend_from_slice(&(ref_names.len() as u64).to_le_bytes());
+        for name in ref_names {
+            push_name(&mut buf, name);
+        }
+        buf.extend_from_slice(&7_u64.to_le_bytes());
+        push_tag_section(&mut buf, &[]);
+        push_tag_section(&mut buf, read_tags);
+        push_tag_section(&mut buf, aln_tags);
+        buf
+    }
+
+    /// The bytes of a chunk of `nrec` records whose body is `body`
+    fn chunk_bytes(nrec: u32, body: &[u8]) -> Vec<u8> {
+        let mut buf = ((body.len() + 8) as u32).to_le_bytes().to_vec();
+        buf.extend_from_slice(&nrec.to_le_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn rewrite_num_chunks_overwrites_only_the_count() {
+        let prelude_buf = prelude_bytes(&["chr1", "chrM"], &[], &[]);
+        let prelude =
+            libradicl::header::RadPrelude::from_bytes(&mut Cursor::new(&prelude_buf)).unwrap();
+        assert_eq!(prelude.hdr.num_chunks, 7);
+        assert_eq!(num_chunks_offset(&prelude), 1 + 8 + (2 + 4) * 2);
+
+        let mut f = Cursor::new(prelude_buf.clone());
+        f.seek(SeekFrom::End(0)).unwrap();
+        f.write_all(b"chunks").unwrap();
+        let end = f.position();
+        rewrite_num_chunks(&mut f, &prelude, 42).unwrap();
+        assert_eq!(f.position(), end);
+
+        let bytes = f.into_inner();
+        assert_eq!(bytes.len(), prelude_buf.len() + 6);
+        let reparsed = libradicl::header::RadPrelude::from_bytes(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(reparsed.hdr.num_chunks, 42);
+        assert_eq!(reparsed.hdr.ref_names, prelude.hdr.ref_names);
+        assert_eq!(&bytes[prelude_buf.len()..], b"chunks");
+    }
+
+    #[test]
+    fn walk_chunk_headers_reports_each_chunk() {
+        let mut data = chunk_bytes(2, &[0; 12]);
+        data.extend(chunk_bytes(0, &[]));
+        data.extend(chunk_bytes(5, &[1; 3]));
+        let mut reader = CountingReader::with_position(Cursor::new(data), 100);
+        let mut chunks = Vec::new();
+        let num_chunks = walk_chunk_headers(&mut reader, |offset, nbytes, nrec| {
+            chunks.push((offset, nbytes, nrec));
+            true
+        })
+        .unwrap();
+        assert_eq!(num_chunks, 3);
+        assert_eq!(chunks, vec![(100, 20, 2), (120, 8, 0), (128, 11, 5)]);
+        assert_eq!(reader.position(), 139);
+    }
+
+    #[test]
+    fn walk_chunk_headers_stops_when_asked() {
+        let mut data = chunk_bytes(1, &[0; 4]);
+        data.extend(chunk_bytes(1, &[0; 4]));
+        let mut reader = CountingReader::new(Cursor::new(data));
+        let num_chunks = walk_chunk_headers(&mut reader, |_, _, _| false).unwrap();
+        assert_eq!(num_chunks, 1);
+        assert_eq!(reader.position(), 12);
+    }
+
+    #[test]
+    fn walk_chunk_headers_rejects_malformed_chunks() {
+        // a size smaller than the header itself
+        let mut data = chunk_bytes(1, &[0; 4]);
+        data.extend_from_slice(&4_u32.to_le_bytes());
+        data.extend_from_slice(&1_u32.to_le_bytes());
+        let mut reader = CountingReader::new(Cursor::new(data));
+        let err = walk_chunk_headers(&mut reader, |_, _, _| true).unwrap_err();
+        assert!(err.to_string().contains("chunk 1 at byte offset 12"));
+
+        // a body that runs past the end of the input
+        let mut data = chunk_bytes(1, &[0; 4]);
+        data.truncate(10);
+        let mut reader = CountingReader::new(Cursor::new(data));
+        let err = walk_chunk_headers(&mut reader, |_, _, _| true).unwrap_err();
+        assert!(err.to_string().contains("is truncated"));
+    }
+}
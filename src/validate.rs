@@ -0,0 +1,359 @@
+use anyhow::{anyhow, bail, Context};
+use clap::Parser;
+use std::io::{BufRead, BufWriter, Read, Write};
+use tracing::{error, info};
+
+use crate::record::{
+    with_record_type, RecordDecoder, RecordEncodingContext, RecordTagLayout, RecordTypeVisitor,
+    SupportedRecord,
+};
+use crate::utils::{
+    count_chunk_read, is_stdin, open_and_parse, read_chunk_header, walk_chunk_headers,
+    CountingReader, ParsedRad,
+};
+use crate::view::{resolve_rad_type, RadFileType};
+
+/// options relevant to validating the structure of a RAD file
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ValidateOpts {
+    /// the input RAD file to validate
+    #[arg(short, long, required = true)]
+    pub input: std::path::PathBuf,
+
+    /// rather than just validating the input, write a copy of it to
+    /// `--output` whose header records the number of chunks actually
+    /// present (e.g. when `num_chunks` is 0 or stale). The input must not
+    /// be truncated or otherwise malformed.
+    #[arg(long, requires = "output")]
+    pub repair: bool,
+
+    /// the output RAD file written by `--repair`
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
+
+    /// rather than only walking the chunk headers, decode every record of
+    /// every chunk, catching records that are malformed (e.g. that claim
+    /// more alignments than their chunk has bytes for), and report the
+    /// chunk and record at which the first such record is found
+    #[arg(long, conflicts_with = "repair")]
+    pub strict: bool,
+
+    /// the type of the input RAD file, as whose records the records are
+    /// decoded with `--strict`; if not provided (or `unknown`), the type
+    /// will be detected from the file-level tags.
+    #[arg(short, long, requires = "strict")]
+    pub rad_type: Option<RadFileType>,
+}
+
+/// The result of walking over all of the chunks of a RAD file
+#[derive(Debug, Default)]
+pub struct ChunkWalkSummary {
+    /// the byte offset at which the first chunk begins
+    pub data_offset: u64,
+    /// the byte offset at which the last chunk ends
+    pub end_offset: u64,
+    pub num_chunks: u64,
+    pub num_records: u64,
+}
+
+/// Walk over the chunks of `reader`, which should be positioned at the
+/// start of the first chunk, reading only the `(nbytes, nrec)` header of
+/// each chunk and advancing exactly `nbytes`. Returns an error describing
+/// the byte offset of the first problem, if the chunks are malformed or
+/// truncated.
+pub fn walk_chunks<R: BufRead>(reader: &mut CountingReader<R>) -> anyhow::Result<ChunkWalkSummary> {
+    walk_chunks_with(reader, |_, _, _| {})
+}
+
+/// As [walk_chunks], but calls `on_chunk(offset, nbytes, nrec)` for each
+/// (well-formed) chunk, where `offset` is the byte offset at which the
+/// chunk begins.
+pub fn walk_chunks_with<R: BufRead, F: FnMut(u64, u32, u32)>(
+    reader: &mut CountingReader<R>,
+    mut on_chunk: F,
+) -> anyhow::Result<ChunkWalkSummary> {
+    let mut summary = ChunkWalkSummary {
+        data_offset: reader.position(),
+        ..Default::default()
+    };
+    summary.num_chunks = walk_chunk_headers(reader, |offset, nbytes, nrec| {
+        on_chunk(offset, nbytes, nrec);
+        count_chunk_read(nrec as u64);
+        summary.num_records += nrec as u64;
+        true
+    })?;
+    summary.end_offset = reader.position();
+    Ok(summary)
+}
+
+/// Parse the prelude of the RAD file at `input` and walk its chunks,
+/// without checking the chunk count reported by the header.
+pub fn scan_file(
+    input: &std::path::Path,
+) -> anyhow::Result<(libradicl::header::RadPrelude, ChunkWalkSummary)> {
+    let ParsedRad {
+        reader,
+        prelude,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(input)?;
+    let mut reader = CountingReader::with_position(reader, first_chunk_offset);
+    let summary = walk_chunks(&mut reader)?;
+    Ok((prelude, summary))
+}
+
+/// Check that the number of chunks reported by `prelude` (if any) is the
+/// number of chunks found
+fn check_num_chunks(
+    prelude: &libradicl::header::RadPrelude,
+    summary: &ChunkWalkSummary,
+) -> anyhow::Result<()> {
+    if prelude.hdr.num_chunks > 0 && prelude.hdr.num_chunks != summary.num_chunks {
+        bail!(
+            "the header reports {} chunks, but {} chunks were found (stream ends at byte offset {})",
+            prelude.hdr.num_chunks,
+            summary.num_chunks,
+            summary.end_offset
+        );
+    }
+    Ok(())
+}
+
+/// Parse the prelude of the RAD file at `input` and walk its chunks,
+/// checking that the file is structurally intact.
+pub fn check_file(
+    input: &std::path::Path,
+) -> anyhow::Result<(libradicl::header::RadPrelude, ChunkWalkSummary)> {
+    let (prelude, summary) = scan_file(input)?;
+    check_num_chunks(&prelude, &summary)?;
+    Ok((prelude, summary))
+}
+
+/// Decode every record of the chunks of `reader`, which should be
+/// positioned at the start of the first chunk, as records of `rad_type`.
+/// The records of each chunk are first laid out by their sizes, so that a
+/// malformed record is reported (by the index of its chunk and its index
+/// within the chunk) rather than decoded from the bytes that follow it.
+fn decode_chunks<T: SupportedRecord, R: BufRead>(
+    reader: &mut CountingReader<R>,
+    prelude: &libradicl::header::RadPrelude,
+    rad_type: &RadFileType,
+) -> anyhow::Result<ChunkWalkSummary> {
+    let decoder = RecordDecoder::<T>::from_prelude(prelude)?;
+    let layout = RecordTagLayout::all_tags(prelude);
+    let enc_ctx = RecordEncodingContext::from_prelude(prelude)?;
+    if *rad_type == RadFileType::SingleCell && (enc_ctx.bc_bytes == 0 || enc_ctx.umi_bytes == 0) {
+        bail!("the read-level tags don't give the widths of the barcode and UMI");
+    }
+    let mut summary = ChunkWalkSummary {
+        data_offset: reader.position(),
+        ..Default::default()
+    };
+
+    while libradicl::utils::has_data_left(reader)? {
+        let chunk_offset = reader.position();
+        let chunk_num = summary.num_chunks;
+        let truncated = || {
+            anyhow!(
+                "chunk {} at byte offset {} is truncated",
+                chunk_num,
+                chunk_offset
+            )
+        };
+        let (nbytes, nrec) = read_chunk_header(reader).map_err(|_| truncated())?;
+        if nbytes < 8 {
+            bail!(
+                "chunk {} at byte offset {} reports an invalid size of {} bytes",
+                chunk_num,
+                chunk_offset,
+                nbytes
+            );
+        }
+        let mut chunk = vec![0u8; nbytes as usize];
+        chunk[0..4].copy_from_slice(&nbytes.to_le_bytes());
+        chunk[4..8].copy_from_slice(&nrec.to_le_bytes());
+        reader
+            .read_exact(&mut chunk[8..])
+            .map_err(|_| truncated())?;
+
+        let mut offset = 8;
+        for i in 0..nrec {
+            let size = layout.record_size(&chunk, offset).map_err(|e| {
+                anyhow!(
+                    "record {} of chunk {} (at byte offset {}) is malformed: {:#}",
+                    i,
+                    chunk_num,
+                    chunk_offset,
+                    e
+                )
+            })?;
+            offset += size;
+        }
+        if offset != chunk.len() {
+            bail!(
+                "chunk {} at byte offset {} has {} bytes after the last of its {} records",
+                chunk_num,
+                chunk_offset,
+                chunk.len() - offset,
+                nrec
+            );
+        }
+
+        let decoded = decoder
+            .decode_chunk(&chunk)
+            .with_context(|| format!("chunk {} at byte offset {}", chunk_num, chunk_offset))?;
+        for (i, r) in decoded.reads.iter().enumerate() {
+            if let Some(bad) = r
+                .ref_ids()
+                .iter()
+                .find(|&&id| id as u64 >= prelude.hdr.ref_count)
+            {
+                bail!(
+                    "record {} of chunk {} (at byte offset {}) has an alignment to reference {}, but the header lists only {} references",
+                    i,
+                    chunk_num,
+                    chunk_offset,
+                    bad,
+                    prelude.hdr.ref_count
+                );
+            }
+        }
+
+        summary.num_chunks += 1;
+        summary.num_records += nrec as u64;
+    }
+    summary.end_offset = reader.position();
+    Ok(summary)
+}
+
+/// Decodes the chunks of an input with [decode_chunks]
+struct DecodeChunks<'a, R: BufRead> {
+    reader: &'a mut CountingReader<R>,
+    prelude: &'a libradicl::header::RadPrelude,
+    rad_type: &'a RadFileType,
+}
+
+impl<R: BufRead> RecordTypeVisitor for DecodeChunks<'_, R> {
+    type Output = ChunkWalkSummary;
+
+    fn visit<T: SupportedRecord>(self) -> anyhow::Result<ChunkWalkSummary> {
+        decode_chunks::<T, _>(self.reader, self.prelude, self.rad_type)
+    }
+}
+
+/// As [check_file], but also decode every record of the file, as records
+/// of the `requested` type (or, if it isn't given, the detected type).
+pub fn check_file_strict(
+    input: &std::path::Path,
+    requested: Option<&RadFileType>,
+) -> anyhow::Result<(libradicl::header::RadPrelude, ChunkWalkSummary)> {
+    let ParsedRad {
+        reader,
+        prelude,
+        tag_map,
+        first_chunk_offset,
+        ..
+    } = open_and_parse(input)?;
+    let rad_type = resolve_rad_type(requested, &prelude, &tag_map, input)?;
+    let summary = with_record_type(
+        &rad_type,
+        DecodeChunks {
+            reader: &mut CountingReader::with_position(reader, first_chunk_offset),
+            prelude: &prelude,
+            rad_type: &rad_type,
+        },
+    )?;
+    check_num_chunks(&prelude, &summary)?;
+    Ok((prelude, summary))
+}
+
+/// Write a copy of `input` to `output` whose prelude has `num_chunks` set
+/// to the number of chunks actually present; the chunks are copied verbatim.
+fn repair(input: &std::path::Path, output: &std::path::Path) -> anyhow::Result<()> {
+    // the input is read twice, once to scan it and once to copy it
+    if is_stdin(input) {
+        bail!("--repair can't read its input from stdin; please provide a RAD file with --input");
+    }
+    let (_prelude, summary) = match scan_file(input) {
+        Ok(r) => r,
+        Err(e) => {
+            error!(
+                "{} cannot be repaired because its chunks are malformed: {}",
+                input.display(),
+                e
+            );
+            bail!("RAD file could not be repaired");
+        }
+    };
+
+    let ParsedRad {
+        reader: mut ifile,
+        mut prelude,
+        tag_map,
+        ..
+    } = open_and_parse(input)?;
+    if prelude.hdr.num_chunks == summary.num_chunks {
+        info!(
+            "the header of {} already reports the correct number of chunks ({})",
+            input.display(),
+            summary.num_chunks
+        );
+    } else {
+        info!(
+            "rewriting num_chunks from {} to {}",
+            prelude.hdr.num_chunks, summary.num_chunks
+        );
+    }
+    prelude.hdr.num_chunks = summary.num_chunks;
+
+    let ofile = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(output)?;
+    let mut owriter = BufWriter::new(ofile);
+    prelude.write(&mut owriter)?;
+    tag_map.write_values(&mut owriter)?;
+    let copied_bytes = std::io::copy(&mut ifile, &mut owriter)?;
+    owriter.flush()?;
+    info!(
+        "copied {} bytes of record chunks from {} into {}.",
+        copied_bytes,
+        input.display(),
+        output.display()
+    );
+    Ok(())
+}
+
+pub fn validate(validate_opts: &ValidateOpts) -> anyhow::Result<()> {
+    if validate_opts.repair {
+        let output = validate_opts
+            .output
+            .as_ref()
+            .expect("--output is required with --repair");
+        return repair(&validate_opts.input, output);
+    }
+
+    let checked = if validate_opts.strict {
+        check_file_strict(&validate_opts.input, validate_opts.rad_type.as_ref())
+    } else {
+        check_file(&validate_opts.input)
+    };
+    match checked {
+        Ok((_prelude, summary)) => {
+            info!(
+                "{} is valid; found {} chunks containing {} records ({} bytes of chunk data)",
+                validate_opts.input.display(),
+                summary.num_chunks,
+                summary.num_records,
+                summary.end_offset - summary.data_offset
+            );
+            Ok(())
+        }
+        Err(e) => {
+            error!("{} is invalid: {}", validate_opts.input.display(), e);
+            bail!("RAD file failed validation");
+        }
+    }
+}
@@ -1,12 +1,27 @@
-use anyhow::bail;
+use anyhow::{bail, Context};
 use clap::{Parser, ValueEnum};
 use libradicl::record::{
     AlevinFryReadRecord, AlevinFryRecordContext, PiscemBulkReadRecord, PiscemBulkRecordContext,
 };
 use needletail::bitkmer::*;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::io;
-use std::io::{BufReader, Write};
-use tracing::error;
+use std::io::{BufRead, Read, Seek, Write};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use tracing::{error, info, warn};
+
+use crate::cat::{file_tag_values_equal, preludes_compatible};
+use crate::fasta::IndexedFasta;
+use crate::record::{RecordRefs, RecordTagLayout, RecordTags};
+use crate::record::{RecordRefs, RecordTagLayout, RecordTags, SupportedRecord};
+use crate::utils::{
+    buffered_reader, buffered_writer, chunk_progress_bar, compressed_writer, count_chunk_read,
+    is_fifo, is_stdin, is_url, open_and_parse, open_rad_input, read_chunk_header,
+    walk_chunk_headers, Compression, CountingReader, ParsedRad,
+};
 
 /// The types of RAD files supported
 #[derive(Clone, Debug, PartialEq, ValueEnum)]
@@ -16,34 +31,258 @@ pub enum RadFileType {
     Unknown,
 }
 
+/// The textual formats in which a RAD file can be viewed
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// a single JSON object containing the header and an array of records
+    Json,
+    /// newline-delimited JSON, with one mapped record per line
+    Ndjson,
+    /// tab-separated values, with one row per alignment
+    Tsv,
+    /// a minimal SAM-like representation, with one line per alignment
+    /// (bulk RAD files only)
+    Sam,
+}
+
 /// options related to printing a RAD file
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct ViewOpts {
-    /// the input RAD file to print
-    #[arg(short, long, required = true)]
-    input: std::path::PathBuf,
+    /// ',' separated list of input RAD files to print, whose records are
+    /// printed as a single sequence (the header is that of the first); if
+    /// not provided (or `-`), the RAD file is read from the standard input
+    #[arg(short, long, default_value = "-", value_delimiter = ',')]
+    pub input: Vec<std::path::PathBuf>,
 
     /// output file where the JSON format RAD file will be written;
     /// if not provided, the output will be written to standard out.
     #[arg(short, long)]
-    output: Option<std::path::PathBuf>,
+    pub output: Option<std::path::PathBuf>,
 
-    /// the type of input RAD file
+    /// the type of input RAD file; if not provided (or `unknown`), the
+    /// type will be detected from the file-level tags.
     #[arg(short, long)]
-    rad_type: RadFileType,
+    pub rad_type: Option<RadFileType>,
 
     /// use the reference name rather than ID in the mapped records
     #[arg(long)]
-    use_ref_name: bool,
+    pub use_ref_name: bool,
+
+    /// a TSV file mapping reference names (first column) to the names
+    /// that should be printed in their place (second column), in both the
+    /// mapped records and the header; references that aren't listed keep
+    /// their original names
+    #[arg(long, value_name = "FILE", requires = "use_ref_name")]
+    pub rename_refs: Option<std::path::PathBuf>,
+
+    /// decode reference names that are not valid UTF-8 lossily (replacing
+    /// invalid bytes with U+FFFD) rather than failing to parse the header
+    #[arg(long)]
+    pub lossy_names: bool,
 
-    /// skip printing the header and file-level tags (i.e. only print the mapping reacords)
+    /// require the file-level tag NAME of the input to have the value
+    /// VALUE (compared according to the type of the tag, with the elements
+    /// of array values separated by ','), failing before any records are
+    /// printed if it doesn't; this may be given multiple times
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_required_tag)]
+    pub require_tag: Vec<(String, String)>,
+
+    /// skip printing the header and file-level tags (i.e. only print the mapping reacords);
+    /// with `--format tsv`, skip printing the row of column names
     #[arg(long)]
-    no_header: bool,
+    pub no_header: bool,
+
+    /// print only the header and file-level tags, without reading any of
+    /// the chunks; with `--format json`, `mapped_records` is an empty array
+    #[arg(long, conflicts_with_all = ["no_header", "split_output", "chunk_range", "skip_chunks"])]
+    pub header_only: bool,
+
+    /// print only the first N reference names in the header (followed by
+    /// a "..." entry); the true number of references is still given by
+    /// `ref_count`, and records still use the full table of names
+    #[arg(long, value_name = "N")]
+    pub max_refs_in_header: Option<usize>,
 
     /// print the records from at most this many chunks
     #[arg(long)]
-    max_chunks: Option<usize>,
+    pub max_chunks: Option<usize>,
+
+    /// print only the records of the chunks in START-END (0-based and
+    /// half-open); if a chunk index (`<input>.radi`, see the `index`
+    /// command) is present, it is used to seek directly to the first chunk
+    #[arg(long, value_name = "START-END", value_parser = parse_chunk_range)]
+    pub chunk_range: Option<(usize, usize)>,
+
+    /// skip the first N chunks, reading only their headers (or seeking past
+    /// them with a chunk index, as with `--chunk-range`), and print the
+    /// records from the chunks that follow, up to `--max-chunks` of them
+    #[arg(long, value_name = "N", conflicts_with = "chunk_range")]
+    pub skip_chunks: Option<usize>,
+
+    /// print at most this many records (after any filtering); this may
+    /// stop in the middle of a chunk, and can be combined with
+    /// `--max-chunks`, in which case whichever limit is reached first applies
+    #[arg(long)]
+    pub max_records: Option<usize>,
+
+    /// the format in which the output should be written; with `ndjson` the
+    /// header (if printed) is the first line, followed by one line per record,
+    /// `tsv` writes one row per alignment (without the RAD header), and `sam`
+    /// writes a SAM-like line per alignment of a bulk file, after an `@SQ`
+    /// header line for each reference
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
+
+    /// with `--format sam`, fill in the SEQ field of each alignment with
+    /// the bases of the reference it covers, read from this (uncompressed,
+    /// and ideally `samtools faidx` indexed) FASTA file; the records of the
+    /// FASTA file are matched to the references by their (original) names
+    #[arg(long, value_name = "FILE")]
+    pub reference_fasta: Option<std::path::PathBuf>,
+
+    /// ',' separated list of the fields to print (e.g. `ref,pos`), in
+    /// each row with `--format tsv`, or in each record and alignment
+    /// with `--format json` or `ndjson`; the valid fields are the columns
+    /// of the TSV output for the type of the input (`record_index` is
+    /// only valid with `--format tsv`)
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Option<Vec<String>>,
+
+    /// write `--format json` output with no insignificant whitespace,
+    /// rather than pretty-printed (NDJSON output is always compact)
+    #[arg(long, conflicts_with = "pretty")]
+    pub compact: bool,
+
+    /// pretty-print `--format json` output with a stable indentation;
+    /// this is the default
+    #[arg(long)]
+    pub pretty: bool,
+
+    /// the number of threads to use for decoding and formatting records;
+    /// records are always written in the order they appear in the input
+    #[arg(short, long, default_value_t = 1)]
+    pub threads: usize,
+
+    /// list the tag descriptions of each section, and the file-level tag
+    /// values, of the header in the order of their names, rather than in
+    /// the order they are recorded in the file, so that the output doesn't
+    /// depend on the order in which the producer wrote them (e.g. for
+    /// comparing the output of different versions of a mapper)
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// list the references of the header as objects giving the ID, name
+    /// and (if the file records it) length of each reference (e.g.
+    /// `{"id": 0, "name": "chr1", "length": 248956422}`), rather than as
+    /// an array of names; this has no effect with `--format tsv` or `sam`
+    #[arg(long, alias = "refs-as-array-of-objects")]
+    pub refs_objects: bool,
+
+    /// compress the output with this format; by default, output files
+    /// ending in `.gz` (or `.zst`) are compressed and standard out is not
+    #[arg(long, value_enum)]
+    pub compress: Option<Compression>,
+
+    /// ',' separated list of reference names or IDs; only records having
+    /// at least one alignment to one of these references will be printed
+    #[arg(long, value_delimiter = ',')]
+    pub ref_filter: Option<Vec<String>>,
+
+    /// when used with `--ref-filter`, print only the alignments of each
+    /// record that are to one of the selected references
+    #[arg(long, requires = "ref_filter")]
+    pub prune_alns: bool,
+
+    /// a region, given as `ref:start-end` (0-based and half-open), that the
+    /// printed records must overlap; a bulk record overlaps the region if
+    /// the fragment of one of its alignments to `ref` (i.e. `[pos, pos +
+    /// flen)`) overlaps `[start, end)`. This may be given multiple times,
+    /// in which case records overlapping any of the regions are printed.
+    /// Only bulk RAD files have alignment positions.
+    #[arg(long)]
+    pub region: Vec<String>,
+
+    /// a file listing barcodes, one per line; only the records of a
+    /// single-cell file having one of these barcodes will be printed
+    #[arg(long, value_name = "FILE")]
+    pub barcodes: Option<std::path::PathBuf>,
+
+    /// skip records that have no alignments (i.e. unmapped placeholders)
+    #[arg(long)]
+    pub drop_unmapped: bool,
+
+    /// print only records having at least this many alignments
+    #[arg(long, value_name = "N")]
+    pub min_alns: Option<usize>,
+
+    /// print only records having at most this many alignments
+    #[arg(long, value_name = "N")]
+    pub max_alns: Option<usize>,
+
+    /// print at most the first N alignments of each record; JSON records
+    /// that have more are marked with `"truncated_alns": true`, along
+    /// with the number of alignments they have (`num_alns`). This affects
+    /// only what is printed, and is applied after `--sort-alns`
+    #[arg(long, value_name = "N")]
+    pub max_alns_per_record: Option<usize>,
+
+    /// sort the alignments of each bulk record by reference and then by
+    /// position (rather than printing them in the order they were
+    /// recorded), so that the output is deterministic for comparison
+    #[arg(long)]
+    pub sort_alns: bool,
+
+    /// include the index of the chunk containing each record (`_chunk`),
+    /// and the index of the record within that chunk (`_record_in_chunk`),
+    /// in each JSON record, so that records can be located in the input
+    #[arg(long)]
+    pub emit_offsets: bool,
+
+    /// with `--format ndjson`, write a `{"_chunk_start": N, "nrec": M}`
+    /// line before the records of each chunk, giving the index of the
+    /// chunk in the input and its number of records, so that the chunks
+    /// of the input can be reconstructed; this has no effect with other
+    /// formats
+    #[arg(long)]
+    pub include_chunk_boundaries: bool,
+
+    /// print the positions of bulk alignments 1-based (i.e. add 1 to each),
+    /// rather than 0-based as they are recorded, in JSON and TSV output
+    /// (SAM output is always 1-based); this affects only what is printed,
+    /// and not the file, or how `--region` is interpreted
+    #[arg(long)]
+    pub positions_1based: bool,
+
+    /// drop (and count) the alignments of records to reference IDs that
+    /// are out of range for the header, rather than failing on the first
+    /// such record
+    #[arg(long)]
+    pub skip_bad_refs: bool,
+
+    /// truncate the alignments of records whose parallel fields (e.g. the
+    /// references, orientations, positions and fragment lengths of bulk
+    /// records) have different lengths to the shortest of them, rather
+    /// than failing on the first such (malformed) record
+    #[arg(long)]
+    pub lenient: bool,
+
+    /// be quiet (no progress bar)
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// rather than writing a single output, write the records of each chunk
+    /// to their own file in this directory, named by the index of the chunk
+    /// (e.g. `DIR/0.json`). With `--format json` each file holds a JSON
+    /// array of records, and no header is written.
+    #[arg(long, value_name = "DIR", conflicts_with = "output")]
+    pub split_output: Option<std::path::PathBuf>,
+
+    /// rather than printing a RAD file, print the JSON Schema of the header
+    /// and of the bulk and single-cell records of the JSON (and NDJSON)
+    /// output, against which it can be validated; no input is read
+    #[arg(long, conflicts_with_all = ["header_only", "split_output"])]
+    pub emit_schema: bool,
 }
 
 /// **NOTE**: This representation is a hack and we should think of
@@ -52,350 +291,2514 @@ pub struct ExtraRecordInfo<'a> {
     pub bc_len: usize,
     pub umi_len: usize,
     pub use_ref_name: bool,
+    // the names printed in place of those of the header (with `--rename-refs`)
+    pub renamed_refs: Option<Vec<String>>,
+    // the source of the SEQ field of SAM output (with `--reference-fasta`)
+    pub reference: Option<IndexedFasta>,
     pub prelude: &'a libradicl::header::RadPrelude,
     pub max_chunks: Option<usize>,
+    pub max_records: Option<usize>,
+    pub format: OutputFormat,
+    pub compact: bool,
+    pub columns: Option<Vec<String>>,
+    pub ref_filter: Option<HashSet<u32>>,
+    pub prune_alns: bool,
+    pub regions: Option<Vec<Region>>,
+    pub sort_alns: bool,
+    pub drop_unmapped: bool,
+    pub min_alns: Option<usize>,
+    pub max_alns: Option<usize>,
+    pub max_alns_per_record: Option<usize>,
+    pub barcodes: Option<HashSet<u64>>,
+    pub emit_offsets: bool,
+    pub chunk_boundaries: bool,
+    pub positions_1based: bool,
+    pub skip_bad_refs: bool,
+    // the number of alignments dropped because of `skip_bad_refs`; this is
+    // updated while formatting, which may happen on several threads
+    pub num_bad_refs: AtomicUsize,
+    pub lenient: bool,
+    // the number of records truncated because of `lenient`
+    pub num_truncated: AtomicUsize,
+    pub split_output: Option<std::path::PathBuf>,
+    pub print_column_names: bool,
+    pub threads: usize,
+    pub progress: indicatif::ProgressBar,
+    // the layout of records with tags beyond their fixed fields, if they
+    // have any
+    pub tag_layout: Option<RecordTagLayout>,
 }
 
 impl<'a> ExtraRecordInfo<'a> {
+    /// Create the information for writing the records described by
+    /// `prelude` in the given `format`, with no limits or filters.
+    pub fn new(prelude: &'a libradicl::header::RadPrelude, format: OutputFormat) -> Self {
+        Self {
+            bc_len: 0,
+            umi_len: 0,
+            use_ref_name: false,
+            renamed_refs: None,
+            reference: None,
+            prelude,
+            max_chunks: None,
+            max_records: None,
+            format,
+            compact: false,
+            columns: None,
+            ref_filter: None,
+            prune_alns: false,
+            regions: None,
+            sort_alns: false,
+            drop_unmapped: false,
+            min_alns: None,
+            max_alns: None,
+            max_alns_per_record: None,
+            barcodes: None,
+            emit_offsets: false,
+            chunk_boundaries: false,
+            positions_1based: false,
+            skip_bad_refs: false,
+            num_bad_refs: AtomicUsize::new(0),
+            lenient: false,
+            num_truncated: AtomicUsize::new(0),
+            split_output: None,
+            print_column_names: true,
+            threads: 1,
+            progress: indicatif::ProgressBar::hidden(),
+            tag_layout: None,
+        }
+    }
+
+    /// Returns `true` if a record with alignments to the references
+    /// `refs` passes the reference filter (if there is one), has a number
+    /// of alignments within the requested bounds, and is not an unmapped
+    /// record that should be dropped.
+    pub fn keep_record(&self, refs: &[u32]) -> bool {
+        if self.drop_unmapped && refs.is_empty() {
+            return false;
+        }
+        if self.min_alns.is_some_and(|n| refs.len() < n)
+            || self.max_alns.is_some_and(|n| refs.len() > n)
+        {
+            return false;
+        }
+        match self.ref_filter {
+            Some(ref rf) => refs.iter().any(|r| rf.contains(r)),
+            None => true,
+        }
+    }
+
+    /// Returns `true` if a fragment aligned to reference `r` at `pos`, with
+    /// length `flen`, overlaps one of the requested regions (or if no
+    /// regions were requested).
+    pub fn overlaps_region(&self, r: u32, pos: u32, flen: u16) -> bool {
+        match self.regions {
+            Some(ref regions) => {
+                // count a fragment of unknown (0) length as a single base
+                let end = pos.saturating_add((flen as u32).max(1));
+                regions
+                    .iter()
+                    .any(|reg| reg.ref_id == r && pos < reg.end && reg.start < end)
+            }
+            None => true,
+        }
+    }
+
+    /// The number of the alignments to the references `refs` that should
+    /// be written out (ignoring `--max-alns-per-record`)
+    pub fn num_kept_alns(&self, refs: &[u32]) -> usize {
+        refs.iter().filter(|&&r| self.keep_aln(r)).count()
+    }
+
+    /// If `--max-alns-per-record` was given, drop the alignments of `order`
+    /// beyond that number.
+    pub fn truncate_alns(&self, order: &mut Vec<usize>) {
+        if let Some(n) = self.max_alns_per_record {
+            order.truncate(n);
+        }
+    }
+
+    /// Returns `true` if an alignment to reference `r` should be written
+    /// out as part of its record.
+    pub fn keep_aln(&self, r: u32) -> bool {
+        // records with out-of-range references are only written at all
+        // with `--skip-bad-refs`
+        if r as u64 >= self.prelude.hdr.ref_count {
+            return false;
+        }
+        match self.ref_filter {
+            Some(ref rf) if self.prune_alns => rf.contains(&r),
+            _ => true,
+        }
+    }
+
+    /// The printed form of the (0-based) position `pos` of an alignment
+    pub fn display_pos(&self, pos: u32) -> u64 {
+        pos as u64 + self.positions_1based as u64
+    }
+
     /// Provides the ability to use the header to lookup
-    /// the name of a target given its ID.
+    /// the name of a target given its ID (as renamed, if it was).
     pub fn ref_name(&self, i: usize) -> &str {
-        &self.prelude.hdr.ref_names[i]
+        match self.renamed_refs {
+            Some(ref names) => &names[i],
+            None => &self.prelude.hdr.ref_names[i],
+        }
     }
 }
 
-/// The ability to write mapping records of different types
-pub trait WriteMappingRecord {
-    fn write_records(
-        &self,
-        ctx: &ExtraRecordInfo,
-        output_stream: &mut Box<dyn Write>,
-    ) -> anyhow::Result<()>;
+/// An interval `[start, end)` of the reference with ID `ref_id`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub ref_id: u32,
+    pub start: u32,
+    pub end: u32,
 }
 
-impl WriteMappingRecord for libradicl::record::PiscemBulkReadRecord {
-    fn write_records(
-        &self,
-        ctx: &ExtraRecordInfo,
-        output_stream: &mut Box<dyn Write>,
-    ) -> anyhow::Result<()> {
-        writeln!(output_stream, "{{")?;
-        writeln!(
-            output_stream,
-            " \"frag_type\" : \"{:?}\",",
-            libradicl::rad_types::MappingType::from_u8(self.frag_type)
-        )?;
-        writeln!(output_stream, " \"alns\" : [")?;
-
-        for i in 0..(self.refs.len()) {
-            if ctx.use_ref_name {
-                write!(
-                    output_stream,
-                    "  {{\"ref\": {:?}, \"dir\": \"{:?}\", \"pos\": {}, \"flen\": {} }}",
-                    ctx.ref_name(self.refs[i] as usize),
-                    self.dirs[i],
-                    self.positions[i],
-                    self.frag_lengths[i]
-                )?;
-            } else {
-                write!(
-                    output_stream,
-                    "  {{\"ref\": {}, \"dir\": \"{:?}\", \"pos\": {}, \"flen\": {} }}",
-                    self.refs[i], self.dirs[i], self.positions[i], self.frag_lengths[i]
-                )?;
-            }
-
-            if i < self.refs.len() - 1 {
-                writeln!(output_stream, ",")?;
-            } else {
-                writeln!(output_stream)?;
-            }
+impl Region {
+    /// Parse a region given as `ref:start-end`, where `ref` is the name or
+    /// the ID of a reference described by `prelude`.
+    pub fn parse(region: &str, prelude: &libradicl::header::RadPrelude) -> anyhow::Result<Self> {
+        // reference names may themselves contain ':', so split on the last one
+        let Some((ref_str, interval)) = region.rsplit_once(':') else {
+            bail!("region {} is not of the form ref:start-end", region);
+        };
+        let Some((start, end)) = interval.split_once('-') else {
+            bail!("region {} is not of the form ref:start-end", region);
+        };
+        let start: u32 = start
+            .parse()
+            .with_context(|| format!("invalid start of region {}", region))?;
+        let end: u32 = end
+            .parse()
+            .with_context(|| format!("invalid end of region {}", region))?;
+        if start >= end {
+            bail!("the region {} is empty", region);
         }
+        let ids = parse_ref_filter(&[ref_str.to_string()], prelude)?;
+        let ref_id = *ids.iter().next().expect("one reference was parsed");
+        Ok(Self { ref_id, start, end })
+    }
+}
 
-        writeln!(output_stream, " ]")?;
-        write!(output_stream, "}}")?;
-        Ok(())
+/// The ability to decide if a record passes the filters requested in
+/// the [ExtraRecordInfo]; by default, only the reference filter applies.
+pub trait FilterRecord: RecordRefs {
+    fn passes_filters(&self, ctx: &ExtraRecordInfo) -> bool {
+        ctx.keep_record(self.ref_ids())
     }
 }
 
-impl WriteMappingRecord for libradicl::record::AlevinFryReadRecord {
-    fn write_records(
-        &self,
-        ctx: &ExtraRecordInfo,
-        output_stream: &mut Box<dyn Write>,
-    ) -> anyhow::Result<()> {
-        let bc_mer: BitKmer = (self.bc, ctx.bc_len as u8);
-        let umi_mer: BitKmer = (self.umi, ctx.umi_len as u8);
+impl FilterRecord for libradicl::record::PiscemBulkReadRecord {
+    fn passes_filters(&self, ctx: &ExtraRecordInfo) -> bool {
+        ctx.keep_record(self.ref_ids())
+            && (0..self.refs.len())
+                .any(|i| ctx.overlaps_region(self.refs[i], self.positions[i], self.frag_lengths[i]))
+    }
+}
 
-        writeln!(output_stream, "{{")?;
-        writeln!(
-            output_stream,
-            " \"barcode\" : {:?},\n \"umi\" : {:?},",
-            unsafe { std::str::from_utf8_unchecked(&bitmer_to_bytes(bc_mer)[..]) },
-            unsafe { std::str::from_utf8_unchecked(&bitmer_to_bytes(umi_mer)[..]) },
-        )?;
-        writeln!(output_stream, " \"alns\" : [")?;
+impl FilterRecord for libradicl::record::AlevinFryReadRecord {
+    fn passes_filters(&self, ctx: &ExtraRecordInfo) -> bool {
+        ctx.keep_record(self.ref_ids())
+            && ctx
+                .barcodes
+                .as_ref()
+                .map_or(true, |bcs| bcs.contains(&self.bc))
+    }
+}
 
-        for i in 0..(self.refs.len()) {
-            if ctx.use_ref_name {
-                write!(
-                    output_stream,
-                    "  {{\"ref\": {:?}, \"dir\": \"{}\" }}",
-                    ctx.ref_name(self.refs[i] as usize),
-                    if self.dirs[i] { "fw" } else { "rc" }
-                )?;
-            } else {
-                write!(
-                    output_stream,
-                    "  {{\"ref\": {}, \"dir\": \"{}\" }}",
-                    self.refs[i],
-                    if self.dirs[i] { "fw" } else { "rc" }
-                )?;
-            }
+/// The ability to check that the parallel fields of the alignments of a
+/// decoded record are consistent, so that they can be indexed together
+pub trait CheckRecord {
+    /// Check this record, which is at position `pos` in the input, failing
+    /// if its alignment fields have different lengths, or truncating them
+    /// to the shortest if `ctx` is lenient.
+    fn check_fields(&mut self, pos: &RecordPosition, ctx: &ExtraRecordInfo) -> anyhow::Result<()>;
+}
 
-            if i < self.refs.len() - 1 {
-                writeln!(output_stream, ",")?;
-            } else {
-                writeln!(output_stream)?;
-            }
+impl CheckRecord for libradicl::record::PiscemBulkReadRecord {
+    fn check_fields(&mut self, pos: &RecordPosition, ctx: &ExtraRecordInfo) -> anyhow::Result<()> {
+        let lens = [
+            self.refs.len(),
+            self.dirs.len(),
+            self.positions.len(),
+            self.frag_lengths.len(),
+        ];
+        let n = lens.iter().copied().min().unwrap_or(0);
+        if lens.iter().all(|&l| l == n) {
+            return Ok(());
         }
-
-        writeln!(output_stream, " ]")?;
-        write!(output_stream, "}}")?;
+        if !ctx.lenient {
+            bail!(
+                "record {} (record {} of chunk {}) is malformed: it has {} refs, {} dirs, {} positions and {} frag_lengths (use --lenient to truncate them to the shortest)",
+                pos.record_index,
+                pos.record_in_chunk,
+                pos.chunk,
+                lens[0],
+                lens[1],
+                lens[2],
+                lens[3]
+            );
+        }
+        self.refs.truncate(n);
+        self.dirs.truncate(n);
+        self.positions.truncate(n);
+        self.frag_lengths.truncate(n);
+        ctx.num_truncated.fetch_add(1, AtomicOrdering::Relaxed);
         Ok(())
     }
 }
 
-pub fn write_records<
-    RecordContext: std::fmt::Debug + Clone + libradicl::record::RecordContext,
-    RecordType: std::fmt::Debug
-        + libradicl::record::MappedRecord<ParsingContext = RecordContext>
-        + WriteMappingRecord,
-    R: std::io::BufRead,
->(
-    prelude: &libradicl::header::RadPrelude,
-    extra_record_info: &ExtraRecordInfo,
-    ifile: &mut R,
-    output_stream: &mut Box<dyn Write>,
-) -> anyhow::Result<()> {
-    let tag_context = prelude.get_record_context::<RecordContext>()?;
-    let total_chunks = if prelude.hdr.num_chunks > 0 {
-        prelude.hdr.num_chunks as usize
-    } else {
-        usize::MAX - 1
-    };
-    let mut chunk_num = 0;
+impl CheckRecord for libradicl::record::AlevinFryReadRecord {
+    fn check_fields(&mut self, pos: &RecordPosition, ctx: &ExtraRecordInfo) -> anyhow::Result<()> {
+        if self.refs.len() == self.dirs.len() {
+            return Ok(());
+        }
+        if !ctx.lenient {
+            bail!(
+                "record {} (record {} of chunk {}) is malformed: it has {} refs and {} dirs (use --lenient to truncate them to the shortest)",
+                pos.record_index,
+                pos.record_in_chunk,
+                pos.chunk,
+                self.refs.len(),
+                self.dirs.len()
+            );
+        }
+        let n = self.refs.len().min(self.dirs.len());
+        self.refs.truncate(n);
+        self.dirs.truncate(n);
+        ctx.num_truncated.fetch_add(1, AtomicOrdering::Relaxed);
+        Ok(())
+    }
+}
 
-    // output either the requested number of chunks, or all of them if no
-    // request is provided (but never try to print more than the total).
-    let num_chunks = extra_record_info
-        .max_chunks
-        .unwrap_or(total_chunks)
-        .min(total_chunks);
+/// Encode the barcode `bc` (a string of `A`, `C`, `G` and `T`) in the
+/// same 2-bit representation used by the records of a single-cell file,
+/// or return `None` if it contains any other character.
+pub fn encode_barcode(bc: &[u8]) -> Option<u64> {
+    bc.iter().try_fold(0_u64, |acc, &b| {
+        let code = match b {
+            b'A' | b'a' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' => 3,
+            _ => return None,
+        };
+        Some((acc << 2) | code)
+    })
+}
 
-    while chunk_num < num_chunks
-        && libradicl::utils::has_data_left(ifile).expect("encountered error reading input file")
-    {
-        // write out each chunk.
-        let chunk = libradicl::chunk::Chunk::<RecordType>::from_bytes(ifile, &tag_context);
-        let nreads = chunk.reads.len();
-        for (rnum, r) in chunk.reads.iter().enumerate() {
-            r.write_records(extra_record_info, output_stream)?;
-            if (chunk_num == num_chunks - 1) && (rnum == nreads - 1) {
-                writeln!(output_stream)?;
-            } else {
-                writeln!(output_stream, ",")?;
-            }
+/// Read the newline-delimited barcodes in `path`, each of which must have
+/// length `cblen`, and return the set of their encodings.
+pub fn read_barcode_list(path: &std::path::Path, cblen: usize) -> anyhow::Result<HashSet<u64>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut barcodes = HashSet::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        let bc = line.trim();
+        if bc.is_empty() {
+            continue;
         }
-        chunk_num += 1;
+        if bc.len() != cblen {
+            bail!(
+                "barcode {} (line {} of {}) has length {}, but the barcodes of the input have length {}",
+                bc,
+                lineno + 1,
+                path.display(),
+                bc.len(),
+                cblen
+            );
+        }
+        match encode_barcode(bc.as_bytes()) {
+            Some(enc) => barcodes.insert(enc),
+            None => bail!(
+                "barcode {} (line {} of {}) contains characters other than A, C, G and T",
+                bc,
+                lineno + 1,
+                path.display()
+            ),
+        };
     }
-    Ok(())
+    Ok(barcodes)
 }
 
-pub fn write_header(
+/// Read the TSV file at `path`, each line of which gives a reference name
+/// and the name to print in its place, and return the names to print for
+/// the references of `prelude`, in order. References that aren't listed
+/// keep their original names.
+pub fn read_ref_renames(
+    path: &std::path::Path,
     prelude: &libradicl::header::RadPrelude,
-    file_tag_map: &libradicl::rad_types::TagMap,
-    output_stream: &mut Box<dyn Write>,
-) -> anyhow::Result<()> {
-    writeln!(output_stream, "\"rad_header\" : {{")?;
-    writeln!(output_stream, "\"is_paired\" : {},", prelude.hdr.is_paired)?;
-    writeln!(output_stream, "\"ref_count\" : {},", prelude.hdr.ref_count)?;
-
-    writeln!(output_stream, "\"refs\" : [")?;
-    for (i, rn) in prelude.hdr.ref_names.iter().enumerate() {
-        write!(output_stream, "\"{}\"", rn)?;
-        if i < (prelude.hdr.ref_count - 1) as usize {
-            writeln!(output_stream, ",")?;
+) -> anyhow::Result<Vec<String>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut renames = HashMap::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
         }
+        let Some((name, new_name)) = line.split_once('\t') else {
+            bail!(
+                "line {} of {} does not have two tab-separated columns",
+                lineno + 1,
+                path.display()
+            );
+        };
+        renames.insert(name.to_string(), new_name.trim_end().to_string());
     }
-    writeln!(output_stream, "],")?;
 
-    writeln!(output_stream, "\"num_chunks\" : {}", prelude.hdr.num_chunks)?;
-    writeln!(output_stream, "}},")?;
+    let mut num_renamed = 0_usize;
+    let names = prelude
+        .hdr
+        .ref_names
+        .iter()
+        .map(|n| match renames.get(n) {
+            Some(new_name) => {
+                num_renamed += 1;
+                new_name.clone()
+            }
+            None => n.clone(),
+        })
+        .collect();
+    info!(
+        "renamed {} of {} references using {}",
+        num_renamed,
+        prelude.hdr.ref_names.len(),
+        path.display()
+    );
+    Ok(names)
+}
 
-    writeln!(output_stream, "\"tag_descriptions\" : {{")?;
+/// A reference target as it appears in an emitted alignment; either
+/// its numeric ID or its name (when `--use-ref-name` is given).
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum RefLabel<'a> {
+    Id(u32),
+    Name(&'a str),
+}
 
-    writeln!(output_stream, " \"file_tag_desc\" : {{")?;
-    writeln!(
-        output_stream,
-        "  \"label\" : \"{:?}\",",
-        prelude.file_tags.label
-    )?;
+impl<'a> ExtraRecordInfo<'a> {
+    /// Serialize `value` to `output_stream` in the layout appropriate
+    /// for the requested output format (pretty-printed for JSON, unless
+    /// it should be compact, and on a single line for NDJSON).
+    pub fn write_value<T: Serialize>(
+        &self,
+        output_stream: &mut dyn Write,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        if let Some(ref columns) = self.columns {
+            let mut projected = serde_json::to_value(value)?;
+            project_json(&mut projected, columns);
+            return self.write_json(output_stream, &projected);
+        }
+        self.write_json(output_stream, value)
+    }
 
-    writeln!(output_stream, "  \"tag_desc\" : [")?;
-    for (i, td) in prelude.file_tags.tags.iter().enumerate() {
-        writeln!(output_stream, "   {{\n    \"name\" : \"{}\",", td.name)?;
-        write!(output_stream, "    \"desc\" : \"{:?}\"\n   }}", td.typeid)?;
-        if i < (prelude.file_tags.tags.len() - 1) {
-            writeln!(output_stream, ",")?;
-        } else {
-            writeln!(output_stream)?;
+    fn write_json<T: Serialize>(
+        &self,
+        output_stream: &mut dyn Write,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        match self.format {
+            OutputFormat::Json if !self.compact => {
+                serde_json::to_writer_pretty(output_stream, value)?
+            }
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                serde_json::to_writer(output_stream, value)?
+            }
+            OutputFormat::Tsv | OutputFormat::Sam => {
+                bail!("JSON values cannot be written in {:?} format", self.format)
+            }
         }
+        Ok(())
     }
-    writeln!(output_stream, "  ]")?;
-    writeln!(output_stream, " }},")?;
 
-    writeln!(output_stream, " \"read_tag_desc\" : {{")?;
-    writeln!(
-        output_stream,
-        "  \"label\" : \"{:?}\",",
-        prelude.read_tags.label
-    )?;
+    /// Write a row of tabular output holding the `--columns` that were
+    /// requested, whose values are looked up by name in `fields`.
+    fn write_columns(
+        &self,
+        columns: &[String],
+        output_stream: &mut dyn Write,
+        fields: &[(&str, &dyn std::fmt::Display)],
+    ) -> anyhow::Result<()> {
+        for (i, c) in columns.iter().enumerate() {
+            if i > 0 {
+                write!(output_stream, "\t")?;
+            }
+            if let Some((_, v)) = fields.iter().find(|(name, _)| *name == c.as_str()) {
+                write!(output_stream, "{}", v)?;
+            }
+        }
+        writeln!(output_stream)?;
+        Ok(())
+    }
 
-    writeln!(output_stream, "  \"tag_desc\" : [")?;
-    for (i, td) in prelude.read_tags.tags.iter().enumerate() {
-        writeln!(output_stream, "   {{\n    \"name\" : \"{}\",", td.name)?;
-        write!(output_stream, "    \"desc\" : \"{:?}\"\n   }}", td.typeid)?;
-        if i < (prelude.read_tags.tags.len() - 1) {
-            writeln!(output_stream, ",")?;
-        } else {
-            writeln!(output_stream)?;
+    /// The values of the read-level tags of a record, whose tags are
+    /// `tags`, beyond its fixed fields, as a JSON object keyed by their
+    /// names; this is empty if the records have no such tags.
+    pub fn read_tags_json(
+        &self,
+        tags: Option<&RecordTags>,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        match (&self.tag_layout, tags) {
+            (Some(layout), Some(tags)) => layout
+                .read_tag_names()
+                .zip(tags.read.iter())
+                .map(|(name, v)| (name.to_string(), tag_value_to_json(v)))
+                .collect(),
+            _ => serde_json::Map::new(),
         }
     }
-    writeln!(output_stream, "  ]")?;
-    writeln!(output_stream, " }},")?;
 
-    writeln!(output_stream, " \"aln_tag_desc\" : {{")?;
-    writeln!(
-        output_stream,
-        "  \"label\" : \"{:?}\",",
-        prelude.aln_tags.label
-    )?;
+    /// The values of the tags beyond the fixed fields of alignment `i` of
+    /// a record, whose tags are `tags`, as a JSON object keyed by their
+    /// names; this is empty if the alignments have no such tags.
+    pub fn aln_tags_json(
+        &self,
+        tags: Option<&RecordTags>,
+        i: usize,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        match (&self.tag_layout, tags.and_then(|t| t.alns.get(i))) {
+            (Some(layout), Some(values)) => layout
+                .aln_tag_names()
+                .zip(values)
+                .map(|(name, v)| (name.to_string(), tag_value_to_json(v)))
+                .collect(),
+            _ => serde_json::Map::new(),
+        }
+    }
 
-    writeln!(output_stream, "  \"tag_desc\" : [")?;
-    for (i, td) in prelude.aln_tags.tags.iter().enumerate() {
-        writeln!(output_stream, "   {{\n    \"name\" : \"{}\",", td.name)?;
-        write!(output_stream, "    \"desc\" : \"{:?}\"\n   }}", td.typeid)?;
-        if i < (prelude.aln_tags.tags.len() - 1) {
-            writeln!(output_stream, ",")?;
+    /// Produce the label for a reference ID that should be emitted
+    /// in the output, respecting `use_ref_name`.
+    pub fn ref_label(&self, r: u32) -> RefLabel<'_> {
+        if self.use_ref_name {
+            RefLabel::Name(self.ref_name(r as usize))
         } else {
-            writeln!(output_stream)?;
+            RefLabel::Id(r)
         }
     }
-    writeln!(output_stream, "  ]")?;
-    writeln!(output_stream, " }}")?;
-
-    //writeln!(output_stream, "{:?}", prelude.file_tags)?;
-    /*
-    pub file_tags: TagSection,
-    pub read_tags: TagSection,
-    pub aln_tags: TagSection,
-    */
-    writeln!(output_stream, "}},")?;
-
-    // file tags
-    writeln!(output_stream, "\"file_tags\" : [")?;
+}
 
-    let nft = prelude.file_tags.tags.len();
-    for (i, td) in prelude.file_tags.tags.iter().enumerate() {
-        if let Some(tv) = file_tag_map.get(&td.name) {
-            write!(
-                output_stream,
-                "{{ \"name\" : \"{}\", \"val\" : \"{:?}\" }}",
-                &td.name, tv
-            )?;
-            if i == nft - 1 {
-                writeln!(output_stream)?;
-            } else {
-                writeln!(output_stream, ",")?;
+/// Keep only the fields named in `columns` of the JSON record `v`, and of
+/// each of its alignments. The position of the record (with
+/// `--emit-offsets`) is always kept.
+fn project_json(v: &mut serde_json::Value, columns: &[String]) {
+    let selected = |k: &String| columns.iter().any(|c| c == k);
+    if let serde_json::Value::Object(rec) = v {
+        // the truncation markers are kept, since they signal that the
+        // alignments listed are incomplete
+        let always_kept = ["alns", "truncated_alns", "num_alns"];
+        rec.retain(|k, _| always_kept.contains(&k.as_str()) || k.starts_with('_') || selected(k));
+        if let Some(serde_json::Value::Array(alns)) = rec.get_mut("alns") {
+            for aln in alns.iter_mut() {
+                if let serde_json::Value::Object(aln) = aln {
+                    aln.retain(|k, _| selected(k));
+                }
             }
         }
     }
-    writeln!(output_stream, "],")?;
-    Ok(())
 }
 
-pub fn view(view_opts: &ViewOpts) -> anyhow::Result<()> {
-    if view_opts.rad_type == RadFileType::Unknown {
-        error!("Unknown file type not yet supported");
-        bail!("Unknown file type not yet supported");
-    }
-
-    let mut output_stream: Box<dyn Write> = match view_opts.output {
-        Some(ref path) => std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .map(|f| Box::new(std::io::BufWriter::new(f)) as Box<dyn Write>)?,
-        None => Box::new(io::stdout()),
+/// Check that each of the `columns` is a field of the records of the
+/// given type that can be printed in `format`
+fn check_columns(
+    columns: &[String],
+    rad_type: &RadFileType,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let all_columns = match rad_type {
+        RadFileType::Bulk => libradicl::record::PiscemBulkReadRecord::column_names(),
+        RadFileType::SingleCell => libradicl::record::AlevinFryReadRecord::column_names(),
+        RadFileType::Unknown => bail!("Unknown RadFileType not supported yet"),
     };
+    if format == OutputFormat::Sam {
+        bail!("--columns can't be used with --format sam");
+    }
+    let valid: Vec<&str> = all_columns
+        .split('\t')
+        .filter(|c| format == OutputFormat::Tsv || *c != "record_index")
+        .collect();
+    for c in columns.iter() {
+        if !valid.contains(&c.as_str()) {
+            bail!(
+                "unknown column {}; the valid columns for {:?} records with --format {:?} are: {}",
+                c,
+                rad_type,
+                format,
+                valid.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
 
-    let f = std::fs::File::open(&view_opts.input)?;
-    let mut ifile = BufReader::new(f);
-    let prelude = libradicl::header::RadPrelude::from_bytes(&mut ifile)?;
-    let file_tag_map = prelude.file_tags.try_parse_tags_from_bytes(&mut ifile)?;
+/// The position of a record within the input
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy)]
+pub struct RecordPosition {
+    /// the index of the record among all of the records of the input
+    #[serde(skip)]
+    pub record_index: usize,
+    #[serde(rename = "_chunk")]
+    pub chunk: usize,
+    #[serde(rename = "_record_in_chunk")]
+    pub record_in_chunk: usize,
+}
 
-    writeln!(output_stream, "{{")?;
+/// JSON representation of a single alignment of a bulk record
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct BulkAlnJson<'a> {
+    #[serde(rename = "ref")]
+    pub ref_label: RefLabel<'a>,
+    pub dir: String,
+    pub pos: u64,
+    pub flen: u16,
+    /// the values of the alignment-level tags beyond those above
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub tags: serde_json::Map<String, serde_json::Value>,
+}
 
-    if !view_opts.no_header {
-        write_header(&prelude, &file_tag_map, &mut output_stream)?;
-    }
+/// JSON representation of a bulk (piscem) mapped record
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct BulkRecordJson<'a> {
+    #[serde(flatten)]
+    pub position: Option<RecordPosition>,
+    pub frag_type: String,
+    /// the values of the read-level tags beyond the fragment type
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub tags: serde_json::Map<String, serde_json::Value>,
+    pub alns: Vec<BulkAlnJson<'a>>,
+    /// set (along with `num_alns`) if not all of the alignments are listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_alns: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_alns: Option<usize>,
+}
 
-    let mut extra_record_info = ExtraRecordInfo {
-        bc_len: 0,
-        umi_len: 0,
-        use_ref_name: view_opts.use_ref_name,
-        prelude: &prelude,
-        max_chunks: view_opts.max_chunks,
-    };
+/// JSON representation of a single alignment of a single-cell record
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct SingleCellAlnJson<'a> {
+    #[serde(rename = "ref")]
+    pub ref_label: RefLabel<'a>,
+    pub dir: &'static str,
+    /// the values of the alignment-level tags beyond those above
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub tags: serde_json::Map<String, serde_json::Value>,
+}
 
-    writeln!(output_stream, "\"mapped_records\" : [")?;
-    match view_opts.rad_type {
-        RadFileType::Bulk => {
-            write_records::<PiscemBulkRecordContext, PiscemBulkReadRecord, BufReader<std::fs::File>>(
-                &prelude,
-                &extra_record_info,
-                &mut ifile,
-                &mut output_stream,
-            )?;
-        }
-        RadFileType::SingleCell => {
-            let cblen: u64 = file_tag_map
-                .get("cblen")
-                .expect("tag map must contain \"cblen\" value")
-                .try_into()?;
+/// JSON representation of a single-cell (alevin-fry) mapped record
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct SingleCellRecordJson<'a> {
+    #[serde(flatten)]
+    pub position: Option<RecordPosition>,
+    pub barcode: String,
+    pub umi: String,
+    /// the values of the read-level tags beyond the barcode and UMI
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub tags: serde_json::Map<String, serde_json::Value>,
+    pub alns: Vec<SingleCellAlnJson<'a>>,
+    /// set (along with `num_alns`) if not all of the alignments are listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_alns: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_alns: Option<usize>,
+}
 
-            let ulen: u64 = file_tag_map
-                .get("ulen")
-                .expect("tag map must contain \"ulen\" value")
-                .try_into()?;
+/// The ability to write mapping records of different types
+pub trait WriteMappingRecord {
+    /// Write this record, which is at position `pos` in the input, and
+    /// whose tags beyond its fixed fields are `tags`, as JSON
+    fn write_records(
+        &self,
+        pos: &RecordPosition,
+        tags: Option<&RecordTags>,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()>;
+}
 
-            extra_record_info.bc_len = cblen as usize;
-            extra_record_info.umi_len = ulen as usize;
+/// The indices of the alignments of `rec` that should be written, in the
+/// order in which they should be written; this is their order in the
+/// record unless `--sort-alns` was given, in which case they are sorted
+/// by reference and then position. At most `--max-alns-per-record` are
+/// returned.
+fn bulk_aln_order(
+    rec: &libradicl::record::PiscemBulkReadRecord,
+    ctx: &ExtraRecordInfo,
+) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..rec.refs.len())
+        .filter(|&i| ctx.keep_aln(rec.refs[i]))
+        .collect();
+    if ctx.sort_alns {
+        // the alignment fields are parallel vectors, so we sort a
+        // permutation of the indices rather than the fields themselves.
+        order.sort_by_key(|&i| (rec.refs[i], rec.positions[i]));
+    }
+    ctx.truncate_alns(&mut order);
+    order
+}
 
-            write_records::<AlevinFryRecordContext, AlevinFryReadRecord, BufReader<std::fs::File>>(
-                &prelude,
-                &extra_record_info,
-                &mut ifile,
-                &mut output_stream,
-            )?;
-        }
-        RadFileType::Unknown => bail!("Unknown RadFileType not supported yet"),
+/// The indices of the alignments of the single-cell record `rec` to be
+/// written, in order; at most `--max-alns-per-record` are returned.
+fn sc_aln_order(rec: &libradicl::record::AlevinFryReadRecord, ctx: &ExtraRecordInfo) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..rec.refs.len())
+        .filter(|&i| ctx.keep_aln(rec.refs[i]))
+        .collect();
+    ctx.truncate_alns(&mut order);
+    order
+}
+
+impl WriteMappingRecord for libradicl::record::PiscemBulkReadRecord {
+    fn write_records(
+        &self,
+        pos: &RecordPosition,
+        tags: Option<&RecordTags>,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let num_alns = ctx.num_kept_alns(&self.refs);
+        let alns: Vec<BulkAlnJson> = bulk_aln_order(self, ctx)
+            .into_iter()
+            .map(|i| BulkAlnJson {
+                ref_label: ctx.ref_label(self.refs[i]),
+                dir: format!("{:?}", self.dirs[i]),
+                pos: ctx.display_pos(self.positions[i]),
+                flen: self.frag_lengths[i],
+                tags: ctx.aln_tags_json(tags, i),
+            })
+            .collect();
+        let rec = BulkRecordJson {
+            position: ctx.emit_offsets.then_some(*pos),
+            frag_type: format!(
+                "{:?}",
+                libradicl::rad_types::MappingType::from_u8(self.frag_type)
+            ),
+            tags: ctx.read_tags_json(tags),
+            truncated_alns: (alns.len() < num_alns).then_some(true),
+            num_alns: (alns.len() < num_alns).then_some(num_alns),
+            alns,
+        };
+        ctx.write_value(output_stream, &rec)
     }
+}
 
-    writeln!(output_stream, "]")?;
-    writeln!(output_stream, "}}")?;
+impl WriteMappingRecord for libradicl::record::AlevinFryReadRecord {
+    fn write_records(
+        &self,
+        pos: &RecordPosition,
+        tags: Option<&RecordTags>,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let bc_mer: BitKmer = (self.bc, ctx.bc_len as u8);
+        let umi_mer: BitKmer = (self.umi, ctx.umi_len as u8);
+
+        let num_alns = ctx.num_kept_alns(&self.refs);
+        let alns: Vec<SingleCellAlnJson> = sc_aln_order(self, ctx)
+            .into_iter()
+            .map(|i| SingleCellAlnJson {
+                ref_label: ctx.ref_label(self.refs[i]),
+                dir: if self.dirs[i] { "fw" } else { "rc" },
+                tags: ctx.aln_tags_json(tags, i),
+            })
+            .collect();
+        let rec = SingleCellRecordJson {
+            position: ctx.emit_offsets.then_some(*pos),
+            barcode: String::from_utf8_lossy(&bitmer_to_bytes(bc_mer)[..]).into_owned(),
+            umi: String::from_utf8_lossy(&bitmer_to_bytes(umi_mer)[..]).into_owned(),
+            tags: ctx.read_tags_json(tags),
+            truncated_alns: (alns.len() < num_alns).then_some(true),
+            num_alns: (alns.len() < num_alns).then_some(num_alns),
+            alns,
+        };
+        ctx.write_value(output_stream, &rec)
+    }
+}
+
+/// The ability to write mapping records of different types as rows
+/// of a table (one row per alignment)
+pub trait WriteTabularRecord {
+    /// the tab-separated names of the columns written by `write_rows`
+    fn column_names() -> &'static str;
+
+    fn write_rows(
+        &self,
+        record_index: usize,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()>;
+}
+
+// the textual form of a reference label used in tabular output
+impl std::fmt::Display for RefLabel<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefLabel::Id(id) => write!(f, "{}", id),
+            RefLabel::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl WriteTabularRecord for libradicl::record::PiscemBulkReadRecord {
+    fn column_names() -> &'static str {
+        "record_index\tfrag_type\tref\tdir\tpos\tflen"
+    }
+
+    fn write_rows(
+        &self,
+        record_index: usize,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let frag_type = libradicl::rad_types::MappingType::from_u8(self.frag_type);
+        if let Some(ref columns) = ctx.columns {
+            let frag_type = format!("{:?}", frag_type);
+            for i in bulk_aln_order(self, ctx) {
+                ctx.write_columns(
+                    columns,
+                    output_stream,
+                    &[
+                        ("record_index", &record_index),
+                        ("frag_type", &frag_type),
+                        ("ref", &ctx.ref_label(self.refs[i])),
+                        ("dir", &self.dirs[i]),
+                        ("pos", &ctx.display_pos(self.positions[i])),
+                        ("flen", &self.frag_lengths[i]),
+                    ],
+                )?;
+            }
+            return Ok(());
+        }
+        for i in bulk_aln_order(self, ctx) {
+            writeln!(
+                output_stream,
+                "{}\t{:?}\t{}\t{:?}\t{}\t{}",
+                record_index,
+                frag_type,
+                ctx.ref_label(self.refs[i]),
+                self.dirs[i],
+                ctx.display_pos(self.positions[i]),
+                self.frag_lengths[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl WriteTabularRecord for libradicl::record::AlevinFryReadRecord {
+    fn column_names() -> &'static str {
+        "record_index\tbarcode\tumi\tref\tdir"
+    }
+
+    fn write_rows(
+        &self,
+        record_index: usize,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let bc_mer: BitKmer = (self.bc, ctx.bc_len as u8);
+        let umi_mer: BitKmer = (self.umi, ctx.umi_len as u8);
+        let bc = bitmer_to_bytes(bc_mer);
+        let umi = bitmer_to_bytes(umi_mer);
+        if let Some(ref columns) = ctx.columns {
+            let bc = String::from_utf8_lossy(&bc);
+            let umi = String::from_utf8_lossy(&umi);
+            for i in sc_aln_order(self, ctx) {
+                ctx.write_columns(
+                    columns,
+                    output_stream,
+                    &[
+                        ("record_index", &record_index),
+                        ("barcode", &bc),
+                        ("umi", &umi),
+                        ("ref", &ctx.ref_label(self.refs[i])),
+                        ("dir", &if self.dirs[i] { "fw" } else { "rc" }),
+                    ],
+                )?;
+            }
+            return Ok(());
+        }
+        for i in sc_aln_order(self, ctx) {
+            writeln!(
+                output_stream,
+                "{}\t{}\t{}\t{}\t{}",
+                record_index,
+                String::from_utf8_lossy(&bc),
+                String::from_utf8_lossy(&umi),
+                ctx.ref_label(self.refs[i]),
+                if self.dirs[i] { "fw" } else { "rc" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The ability to write mapping records as SAM-like lines (one per
+/// alignment). This is meant for quick inspection rather than fidelity;
+/// the read name is the index of the record, the CIGAR string simply
+/// covers the fragment length, and there is no quality. The sequence is
+/// that of the reference over the fragment, if a reference was provided.
+/// As SAM requires, this is the sequence of the forward strand, even for
+/// reverse-complemented alignments.
+pub trait WriteSamRecord {
+    fn write_sam(
+        &self,
+        record_index: usize,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()>;
+}
+
+impl WriteSamRecord for libradicl::record::PiscemBulkReadRecord {
+    fn write_sam(
+        &self,
+        record_index: usize,
+        ctx: &ExtraRecordInfo,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let mut first = true;
+        for i in bulk_aln_order(self, ctx) {
+            // reverse-complemented alignments are flagged with 0x10, and
+            // all but the first alignment of a record as secondary (0x100).
+            let mut flag = if self.dirs[i] { 0_u16 } else { 0x10 };
+            if !first {
+                flag |= 0x100;
+            }
+            first = false;
+            let flen = self.frag_lengths[i];
+            let cigar = if flen > 0 {
+                format!("{}M", flen)
+            } else {
+                "*".to_string()
+            };
+            // a fragment that runs past the end of its reference (which
+            // SEQ couldn't match the CIGAR string of) has no sequence
+            let start = self.positions[i] as u64;
+            let end = start + flen as u64;
+            let name = &ctx.prelude.hdr.ref_names[self.refs[i] as usize];
+            let seq = match ctx.reference {
+                Some(ref fa) if flen > 0 && fa.seq_len(name).is_some_and(|l| end <= l) => {
+                    let bases = fa
+                        .fetch(name, start, end)
+                        .with_context(|| format!("record {}", record_index))?;
+                    String::from_utf8_lossy(&bases).into_owned()
+                }
+                _ => "*".to_string(),
+            };
+            writeln!(
+                output_stream,
+                "{}\t{}\t{}\t{}\t255\t{}\t*\t0\t0\t{}\t*",
+                record_index,
+                flag,
+                ctx.ref_name(self.refs[i] as usize),
+                self.positions[i] as u64 + 1,
+                cigar,
+                seq
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl WriteSamRecord for libradicl::record::AlevinFryReadRecord {
+    fn write_sam(
+        &self,
+        _record_index: usize,
+        _ctx: &ExtraRecordInfo,
+        _output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        bail!("single-cell records have no positions, so they can't be written as SAM")
+    }
+}
+
+/// Format the record `r` (which is at position `pos` in the input, and
+/// whose tags beyond its fixed fields are `tags`) into `buf`, in the
+/// output format given by `ctx`. Those tags are only written as JSON.
+/// It is an error if `r` has an alignment to a reference that isn't in the
+/// header, unless such alignments should be skipped.
+fn format_record<
+    RecordType: RecordRefs + WriteMappingRecord + WriteTabularRecord + WriteSamRecord,
+>(
+    r: &RecordType,
+    pos: &RecordPosition,
+    tags: Option<&RecordTags>,
+    ctx: &ExtraRecordInfo,
+    buf: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    buf.clear();
+    let ref_count = ctx.prelude.hdr.ref_count;
+    let is_bad = |id: &&u32| **id as u64 >= ref_count;
+    if let Some(bad) = r.ref_ids().iter().find(is_bad) {
+        if !ctx.skip_bad_refs {
+            bail!(
+                "record {} has an alignment to reference {}, but the header lists only {} references (use --skip-bad-refs to drop such alignments)",
+                pos.record_index,
+                bad,
+                ref_count
+            );
+        }
+        let num_bad = r.ref_ids().iter().filter(is_bad).count();
+        ctx.num_bad_refs.fetch_add(num_bad, AtomicOrdering::Relaxed);
+    }
+    match ctx.format {
+        OutputFormat::Tsv => r.write_rows(pos.record_index, ctx, buf),
+        OutputFormat::Sam => r.write_sam(pos.record_index, ctx, buf),
+        OutputFormat::Json | OutputFormat::Ndjson => r.write_records(pos, tags, ctx, buf),
+    }
+}
+
+/// Decode the chunk held in `chunk_bytes` (including its header), along
+/// with the tags beyond the fixed fields of its records if they have any
+/// (in which case there is an entry for each record).
+fn decode_chunk<
+    RecordContext: std::fmt::Debug + Clone + libradicl::record::RecordContext,
+    RecordType: std::fmt::Debug + libradicl::record::MappedRecord<ParsingContext = RecordContext>,
+>(
+    chunk_bytes: &[u8],
+    chunk_index: usize,
+    tag_context: &RecordContext,
+    ctx: &ExtraRecordInfo,
+) -> anyhow::Result<(libradicl::chunk::Chunk<RecordType>, Vec<RecordTags>)> {
+    let Some(ref layout) = ctx.tag_layout else {
+        let chunk = libradicl::chunk::Chunk::<RecordType>::from_bytes(
+            &mut std::io::Cursor::new(chunk_bytes),
+            tag_context,
+        );
+        return Ok((chunk, Vec::new()));
+    };
+    let (fixed_bytes, tags) = layout.split_chunk(chunk_bytes).with_context(|| {
+        format!(
+            "chunk {} can't be laid out by its tag descriptions",
+            chunk_index
+        )
+    })?;
+    let chunk = libradicl::chunk::Chunk::<RecordType>::from_bytes(
+        &mut std::io::Cursor::new(fixed_bytes),
+        tag_context,
+    );
+    Ok((chunk, tags))
+}
+
+/// Decode the chunk held in `chunk_bytes` (including its header), which
+/// has index `chunk_index`, and whose first record has index
+/// `first_record_index`, in the input, and format each of its records
+/// that passes the filters of `ctx`.
+fn format_chunk<
+    RecordContext: std::fmt::Debug + Clone + libradicl::record::RecordContext,
+    RecordType: std::fmt::Debug
+        + libradicl::record::MappedRecord<ParsingContext = RecordContext>
+        + FilterRecord
+        + CheckRecord
+        + WriteMappingRecord
+        + WriteTabularRecord
+        + WriteSamRecord,
+>(
+    chunk_bytes: &[u8],
+    chunk_index: usize,
+    first_record_index: usize,
+    tag_context: &RecordContext,
+    ctx: &ExtraRecordInfo,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let (mut chunk, tags) =
+        decode_chunk::<RecordContext, RecordType>(chunk_bytes, chunk_index, tag_context, ctx)?;
+    let mut formatted = Vec::with_capacity(chunk.reads.len());
+    for (i, r) in chunk.reads.iter_mut().enumerate() {
+        let pos = RecordPosition {
+            record_index: first_record_index + i,
+            chunk: chunk_index,
+            record_in_chunk: i,
+        };
+        // the record is checked before the filters, which index its fields
+        r.check_fields(&pos, ctx)?;
+        if !r.passes_filters(ctx) {
+            continue;
+        }
+        let mut buf = Vec::new();
+        format_record(r, &pos, tags.get(i), ctx, &mut buf)?;
+        formatted.push(buf);
+    }
+    Ok(formatted)
+}
+
+/// Writes formatted records to the output stream, placing the
+/// appropriate separators between them, and keeps track of when the
+/// requested number of records (or chunks) has been written. Because the
+/// separator is only ever written once a following record is known to
+/// exist, the output is well-formed regardless of where (or why) the
+/// output stops, e.g. at an empty final chunk, or when `--max-chunks` or
+/// `--max-records` truncates the output. The same emitter is used for
+/// all of the inputs whose records are written as one sequence (or, with
+/// `--split-output`, as one sequence per chunk).
+pub struct RecordEmitter {
+    format: OutputFormat,
+    compact: bool,
+    split_output: Option<std::path::PathBuf>,
+    max_records: usize,
+    max_chunks: usize,
+    print_column_names: bool,
+    // whether a line marking the start of each chunk is written
+    chunk_boundaries: bool,
+    num_written: usize,
+    // the number of records written in the current sequence
+    num_in_sequence: usize,
+    // the number of chunks, and the index of the next record, over all
+    // of the inputs read so far
+    num_chunks: usize,
+    record_index: usize,
+    // the index of the next chunk within the input, which differs from
+    // `num_chunks` if the first chunks were skipped
+    chunk_index: usize,
+}
+
+impl RecordEmitter {
+    /// Create an emitter for records written in the format, and with
+    /// the limits, given in `ctx`.
+    pub fn new(ctx: &ExtraRecordInfo) -> Self {
+        Self {
+            format: ctx.format,
+            compact: ctx.compact,
+            split_output: ctx.split_output.clone(),
+            max_records: ctx.max_records.unwrap_or(usize::MAX),
+            max_chunks: ctx.max_chunks.unwrap_or(usize::MAX),
+            print_column_names: ctx.print_column_names,
+            chunk_boundaries: ctx.chunk_boundaries && ctx.format == OutputFormat::Ndjson,
+            num_written: 0,
+            num_in_sequence: 0,
+            num_chunks: 0,
+            record_index: 0,
+            chunk_index: 0,
+        }
+    }
+
+    fn emit(&mut self, rec: &[u8], output_stream: &mut dyn Write) -> anyhow::Result<()> {
+        // since records may be filtered out, we can't know if a record
+        // is the last one until we've seen the rest, so the separator
+        // is written *before* every record except the first.
+        match self.format {
+            OutputFormat::Json if self.num_in_sequence > 0 && self.compact => {
+                write!(output_stream, ",")?
+            }
+            OutputFormat::Json if self.num_in_sequence > 0 => writeln!(output_stream, ",")?,
+            OutputFormat::Ndjson if self.num_in_sequence > 0 => writeln!(output_stream)?,
+            _ => {}
+        }
+        output_stream.write_all(rec)?;
+        self.num_written += 1;
+        self.num_in_sequence += 1;
+        Ok(())
+    }
+
+    /// With `--split-output`, replace `output_stream` by a new file for the
+    /// records of the chunk with index `chunk_index`, and begin the
+    /// sequence of its records; otherwise, do nothing.
+    fn start_chunk(
+        &mut self,
+        chunk_index: usize,
+        output_stream: &mut Box<dyn Write + '_>,
+    ) -> anyhow::Result<()> {
+        let Some(ref dir) = self.split_output else {
+            return Ok(());
+        };
+        let ext = if self.format == OutputFormat::Ndjson {
+            "ndjson"
+        } else {
+            "json"
+        };
+        let path = dir.join(format!("{}.{}", chunk_index, ext));
+        *output_stream = Box::new(buffered_writer(std::fs::File::create(&path)?));
+        if self.format == OutputFormat::Json {
+            if self.compact {
+                write!(output_stream, "[")?;
+            } else {
+                writeln!(output_stream, "[")?;
+            }
+        }
+        self.num_in_sequence = 0;
+        Ok(())
+    }
+
+    /// With `--include-chunk-boundaries`, write the line marking the start
+    /// of the chunk with index `chunk_index`, which has `nrec` records (in
+    /// the input, whether or not they are written); otherwise, do nothing.
+    /// The marker doesn't count towards the number of records written.
+    fn mark_chunk(
+        &mut self,
+        chunk_index: usize,
+        nrec: u32,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        if !self.chunk_boundaries {
+            return Ok(());
+        }
+        if self.num_in_sequence > 0 {
+            writeln!(output_stream)?;
+        }
+        write!(
+            output_stream,
+            "{{\"_chunk_start\":{},\"nrec\":{}}}",
+            chunk_index, nrec
+        )?;
+        self.num_in_sequence += 1;
+        Ok(())
+    }
+
+    /// With `--split-output`, finish the sequence of records of the current
+    /// chunk and its file; otherwise, do nothing.
+    fn end_chunk(&mut self, output_stream: &mut Box<dyn Write + '_>) -> anyhow::Result<()> {
+        if self.split_output.is_none() {
+            return Ok(());
+        }
+        self.finish(output_stream)?;
+        if self.format == OutputFormat::Json {
+            writeln!(output_stream, "]")?;
+        }
+        output_stream.flush()?;
+        Ok(())
+    }
+
+    /// Write the row of column names of the tabular format, if it is
+    /// requested and hasn't been written yet.
+    fn write_column_names(
+        &mut self,
+        column_names: &str,
+        output_stream: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        if self.format == OutputFormat::Tsv && self.print_column_names {
+            writeln!(output_stream, "{}", column_names)?;
+            self.print_column_names = false;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` once the requested number of records has been written
+    fn records_done(&self) -> bool {
+        self.num_written >= self.max_records
+    }
+
+    /// the number of records that may still be written
+    fn records_remaining(&self) -> usize {
+        self.max_records.saturating_sub(self.num_written)
+    }
+
+    /// the number of chunks that may still be read
+    fn chunks_remaining(&self) -> usize {
+        self.max_chunks.saturating_sub(self.num_chunks)
+    }
+
+    /// Returns `true` once no more chunks should be read
+    pub fn done(&self) -> bool {
+        self.records_done() || self.num_chunks >= self.max_chunks
+    }
+
+    /// Finish the sequence of records written by this emitter
+    pub fn finish(&self, output_stream: &mut dyn Write) -> anyhow::Result<()> {
+        // the line-oriented formats end each of their lines themselves,
+        // and compact JSON output has no line breaks between its records
+        let is_json = match self.format {
+            OutputFormat::Json => !self.compact,
+            OutputFormat::Ndjson => true,
+            OutputFormat::Tsv | OutputFormat::Sam => false,
+        };
+        if self.num_in_sequence > 0 && is_json {
+            writeln!(output_stream)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the whole of the next chunk (including its header) of `ifile`,
+/// whose position is the byte offset within the input, returning its
+/// bytes and number of records. If the input ends before the chunk does (e.g.
+/// the file is a partial copy), this fails with an error naming the
+/// chunk, and the offset at which it starts, rather than decoding a
+/// partial chunk.
+fn read_chunk_bytes<R: BufRead>(
+    ifile: &mut CountingReader<R>,
+    chunk_num: usize,
+) -> anyhow::Result<(Vec<u8>, u32)> {
+    let offset = ifile.position();
+    let truncated = || {
+        format!(
+            "chunk {} (at byte offset {}) is truncated",
+            chunk_num, offset
+        )
+    };
+    let (nbytes, nrec) = read_chunk_header(ifile).with_context(truncated)?;
+    if nbytes < 8 {
+        bail!(
+            "chunk {} (at byte offset {}) has an invalid size of {} bytes",
+            chunk_num,
+            offset,
+            nbytes
+        );
+    }
+    let mut chunk_bytes = vec![0u8; nbytes as usize];
+    chunk_bytes[0..4].copy_from_slice(&nbytes.to_le_bytes());
+    chunk_bytes[4..8].copy_from_slice(&nrec.to_le_bytes());
+    ifile
+        .read_exact(&mut chunk_bytes[8..])
+        .with_context(truncated)?;
+    count_chunk_read(nrec as u64);
+    Ok((chunk_bytes, nrec))
+}
+
+/// Write the records of `ifile`, a RAD file with the given `prelude`
+/// whose next chunk is at byte offset `start_offset`, through `emitter`.
+/// This does not finish the sequence of records, so
+/// that the records of several inputs can be written by the same emitter.
+pub fn write_records<
+    RecordContext: std::fmt::Debug + Clone + Sync + libradicl::record::RecordContext,
+    RecordType: std::fmt::Debug
+        + libradicl::record::MappedRecord<ParsingContext = RecordContext>
+        + FilterRecord
+        + CheckRecord
+        + WriteMappingRecord
+        + WriteTabularRecord
+        + WriteSamRecord,
+    R: std::io::BufRead,
+>(
+    prelude: &libradicl::header::RadPrelude,
+    extra_record_info: &ExtraRecordInfo,
+    emitter: &mut RecordEmitter,
+    ifile: &mut R,
+    start_offset: u64,
+    output_stream: &mut Box<dyn Write + '_>,
+) -> anyhow::Result<()> {
+    let tag_context = prelude.get_record_context::<RecordContext>()?;
+    // never try to read more chunks than this file has (the requested
+    // number of chunks, and of records, is enforced by the emitter).
+    let num_chunks = if prelude.hdr.num_chunks > 0 {
+        prelude.hdr.num_chunks as usize
+    } else {
+        usize::MAX - 1
+    };
+    let mut chunk_num = 0;
+    // chunks are read whole before they are decoded, so that a truncated
+    // input is reported as such, at its offset within the input.
+    let mut ifile = CountingReader::with_position(ifile, start_offset);
+
+    match extra_record_info.columns {
+        Some(ref columns) => emitter.write_column_names(&columns.join("\t"), output_stream)?,
+        None => emitter.write_column_names(RecordType::column_names(), output_stream)?,
+    }
+
+    if extra_record_info.threads > 1 {
+        // chunks are read sequentially here, but are decoded and formatted
+        // in parallel, a batch at a time, and then written in file order.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(extra_record_info.threads)
+            .build()?;
+        let batch_size = 4 * extra_record_info.threads;
+        let mut batch = Vec::<(usize, usize, u32, Vec<u8>)>::with_capacity(batch_size);
+        while !emitter.done() {
+            batch.clear();
+            // no more chunks are read than could be written: once the batch
+            // holds as many records as may still be written, the limit is
+            // reached (at the earliest) in its last chunk, and the chunks
+            // after it are neither read nor counted.
+            let mut batch_records = 0_usize;
+            let mut chunk_idx = emitter.chunk_index;
+            let mut record_idx = emitter.record_index;
+            while batch.len() < batch_size
+                && batch.len() < emitter.chunks_remaining()
+                && batch_records < emitter.records_remaining()
+                && chunk_num < num_chunks
+                && libradicl::utils::has_data_left(&mut ifile)?
+            {
+                let (chunk_bytes, nrec) = read_chunk_bytes(&mut ifile, chunk_num)?;
+                batch.push((chunk_idx, record_idx, nrec, chunk_bytes));
+                batch_records += nrec as usize;
+                record_idx += nrec as usize;
+                chunk_idx += 1;
+                chunk_num += 1;
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            let formatted: Vec<anyhow::Result<Vec<Vec<u8>>>> = pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|(chunk_idx, first_idx, _, chunk_bytes)| {
+                        format_chunk::<RecordContext, RecordType>(
+                            chunk_bytes,
+                            *chunk_idx,
+                            *first_idx,
+                            &tag_context,
+                            extra_record_info,
+                        )
+                    })
+                    .collect()
+            });
+            for ((chunk_idx, _, nrec, _), recs) in batch.iter().zip(formatted) {
+                emitter.start_chunk(*chunk_idx, output_stream)?;
+                emitter.mark_chunk(*chunk_idx, *nrec, output_stream)?;
+                for rec in recs? {
+                    emitter.emit(&rec, output_stream)?;
+                    if emitter.records_done() {
+                        break;
+                    }
+                }
+                emitter.end_chunk(output_stream)?;
+                emitter.record_index += *nrec as usize;
+                emitter.chunk_index += 1;
+                emitter.num_chunks += 1;
+                extra_record_info.progress.inc(1);
+                if emitter.records_done() {
+                    break;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let mut rec_buf = Vec::<u8>::new();
+    while chunk_num < num_chunks && !emitter.done() && libradicl::utils::has_data_left(&mut ifile)?
+    {
+        // write out each chunk.
+        let (chunk_bytes, nrec) = read_chunk_bytes(&mut ifile, chunk_num)?;
+        let (mut chunk, tags) = decode_chunk::<RecordContext, RecordType>(
+            &chunk_bytes,
+            emitter.chunk_index,
+            &tag_context,
+            extra_record_info,
+        )?;
+        emitter.start_chunk(emitter.chunk_index, output_stream)?;
+        emitter.mark_chunk(emitter.chunk_index, nrec, output_stream)?;
+        for (i, r) in chunk.reads.iter_mut().enumerate() {
+            let pos = RecordPosition {
+                record_index: emitter.record_index,
+                chunk: emitter.chunk_index,
+                record_in_chunk: i,
+            };
+            emitter.record_index += 1;
+            r.check_fields(&pos, extra_record_info)?;
+            if !r.passes_filters(extra_record_info) {
+                continue;
+            }
+            format_record(r, &pos, tags.get(i), extra_record_info, &mut rec_buf)?;
+            emitter.emit(&rec_buf, output_stream)?;
+            if emitter.records_done() {
+                break;
+            }
+        }
+        emitter.end_chunk(output_stream)?;
+        emitter.chunk_index += 1;
+        emitter.num_chunks += 1;
+        chunk_num += 1;
+        extra_record_info.progress.inc(1);
+    }
+    Ok(())
+}
+
+/// JSON representation of a single reference of the header, as printed
+/// with `--refs-objects`
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct RefJson<'a> {
+    pub id: usize,
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+}
+
+/// JSON representation of the references of the header; either their
+/// names, in the order of their IDs, or an object for each reference
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum RefsJson<'a> {
+    Names(Vec<&'a str>),
+    Objects(Vec<RefJson<'a>>),
+}
+
+/// JSON representation of the basic RAD header
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct RadHeaderJson<'a> {
+    pub is_paired: u8,
+    pub ref_count: u64,
+    pub refs: RefsJson<'a>,
+    /// the length of each reference (parallel to `refs`), if the file
+    /// records them in its [REF_LENGTHS_TAG] file-level tag (and the
+    /// references aren't listed as objects, which include their lengths)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ref_lengths: Option<Vec<u64>>,
+    /// the number of chunks, or `None` if it isn't known (i.e. the file
+    /// was written in a streaming fashion and leaves it as 0)
+    #[serde(serialize_with = "serialize_num_chunks")]
+    #[schemars(schema_with = "num_chunks_schema")]
+    pub num_chunks: Option<u64>,
+}
+
+/// Serialize a number of chunks that may not be known as either the
+/// number or the string `"unknown"`.
+fn serialize_num_chunks<S: serde::Serializer>(
+    num_chunks: &Option<u64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match num_chunks {
+        Some(n) => serializer.serialize_u64(*n),
+        None => serializer.serialize_str("unknown"),
+    }
+}
+
+/// The JSON schema of a number of chunks written by [serialize_num_chunks]
+fn num_chunks_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    serde_json::from_value(serde_json::json!({
+        "anyOf": [
+            { "type": "integer", "format": "uint64", "minimum": 0 },
+            { "type": "string", "enum": ["unknown"] }
+        ]
+    }))
+    .expect("the schema of num_chunks is valid")
+}
+
+/// The JSON schema of the output of `view`, giving the schema of the
+/// header and of the records of each type of RAD file. The JSON and NDJSON
+/// output are made of these (separately or, with `--format json`, as the
+/// fields of the header alongside a `mapped_records` array).
+pub fn output_schema() -> serde_json::Value {
+    serde_json::json!({
+        "title": "radtk view output",
+        "radtk_version": env!("CARGO_PKG_VERSION"),
+        "header": schemars::schema_for!(HeaderJson<'static>),
+        "bulk_record": schemars::schema_for!(BulkRecordJson<'static>),
+        "single_cell_record": schemars::schema_for!(SingleCellRecordJson<'static>),
+    })
+}
+
+impl RadHeaderJson<'_> {
+    /// Keep only the first `max_refs` references, along with their
+    /// lengths; a list of reference names is followed by a `"..."`
+    /// sentinel if any were removed. `ref_count` is unchanged.
+    pub fn truncate_refs(&mut self, max_refs: usize) {
+        match self.refs {
+            RefsJson::Names(ref mut names) => {
+                if names.len() > max_refs {
+                    names.truncate(max_refs);
+                    names.push("...");
+                }
+            }
+            RefsJson::Objects(ref mut refs) => refs.truncate(max_refs),
+        }
+        if let Some(ref mut lens) = self.ref_lengths {
+            lens.truncate(max_refs);
+        }
+    }
+
+    /// List the references as objects giving the ID, name and (if known)
+    /// length of each, rather than as a list of names
+    pub fn use_ref_objects(&mut self) {
+        let RefsJson::Names(ref names) = self.refs else {
+            return;
+        };
+        let lens = self.ref_lengths.take();
+        let refs = names
+            .iter()
+            .enumerate()
+            .map(|(id, &name)| RefJson {
+                id,
+                name,
+                length: lens.as_ref().map(|l| l[id]),
+            })
+            .collect();
+        self.refs = RefsJson::Objects(refs);
+    }
+}
+
+/// JSON representation of the description of a single tag
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct TagDescJson<'a> {
+    pub name: &'a str,
+    pub desc: String,
+}
+
+/// The stable, human-readable name of a RAD type (e.g. `u32`, `string`
+/// or `array<u8>`), as used in the tag descriptions of the header.
+pub fn typeid_to_str(typeid: &libradicl::rad_types::RadType) -> String {
+    use libradicl::rad_types::{RadAtomicId, RadFloatId, RadIntId, RadType};
+    let int_str = |i: &RadIntId| match i {
+        RadIntId::U8 => "u8",
+        RadIntId::U16 => "u16",
+        RadIntId::U32 => "u32",
+        RadIntId::U64 => "u64",
+    };
+    let float_str = |f: &RadFloatId| match f {
+        RadFloatId::F32 => "f32",
+        RadFloatId::F64 => "f64",
+    };
+    match typeid {
+        RadType::Bool => "bool".to_string(),
+        RadType::Int(i) => int_str(i).to_string(),
+        RadType::Float(f) => float_str(f).to_string(),
+        RadType::StrType => "string".to_string(),
+        // the type of the length of an array is an encoding detail, so
+        // only the type of its elements is named.
+        RadType::Array(_, elem) => {
+            let elem_str = match elem {
+                RadAtomicId::Int(i) => int_str(i),
+                RadAtomicId::Float(f) => float_str(f),
+                RadAtomicId::String => "string",
+            };
+            format!("array<{}>", elem_str)
+        }
+    }
+}
+
+/// JSON representation of a section of tag descriptions
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct TagSectionJson<'a> {
+    pub label: String,
+    pub tag_desc: Vec<TagDescJson<'a>>,
+}
+
+impl<'a> From<&'a libradicl::rad_types::TagSection> for TagSectionJson<'a> {
+    fn from(ts: &'a libradicl::rad_types::TagSection) -> Self {
+        Self {
+            label: format!("{:?}", ts.label),
+            tag_desc: ts
+                .tags
+                .iter()
+                .map(|td| TagDescJson {
+                    name: &td.name,
+                    desc: typeid_to_str(&td.typeid),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// JSON representation of the file, read and alignment-level
+/// tag descriptions
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct TagDescriptionsJson<'a> {
+    pub file_tag_desc: TagSectionJson<'a>,
+    pub read_tag_desc: TagSectionJson<'a>,
+    pub aln_tag_desc: TagSectionJson<'a>,
+}
+
+/// JSON representation of the value of a file-level tag
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct FileTagJson<'a> {
+    pub name: &'a str,
+    pub val: serde_json::Value,
+}
+
+/// Convert a tag value to the corresponding native JSON value (i.e. a
+/// number, string, boolean or array thereof).
+pub fn tag_value_to_json(tv: &libradicl::rad_types::TagValue) -> serde_json::Value {
+    use libradicl::rad_types::TagValue;
+    match tv {
+        TagValue::Bool(x) => serde_json::json!(x),
+        TagValue::U8(x) => serde_json::json!(x),
+        TagValue::U16(x) => serde_json::json!(x),
+        TagValue::U32(x) => serde_json::json!(x),
+        TagValue::U64(x) => serde_json::json!(x),
+        TagValue::F32(x) => serde_json::json!(x),
+        TagValue::F64(x) => serde_json::json!(x),
+        TagValue::ArrayU8(x) => serde_json::json!(x),
+        TagValue::ArrayU16(x) => serde_json::json!(x),
+        TagValue::ArrayU32(x) => serde_json::json!(x),
+        TagValue::ArrayU64(x) => serde_json::json!(x),
+        TagValue::ArrayF32(x) => serde_json::json!(x),
+        TagValue::ArrayF64(x) => serde_json::json!(x),
+        TagValue::String(x) => serde_json::json!(x),
+        TagValue::ArrayString(x) => serde_json::json!(x),
+    }
+}
+
+/// The name of the file-level tag in which the lengths of the references
+/// may be recorded, as an array with one entry per reference
+pub const REF_LENGTHS_TAG: &str = "ref_lengths";
+
+/// The lengths of the references of the file described by `prelude`, if
+/// its file-level tags record them (as an integer array with one entry
+/// per reference) in the [REF_LENGTHS_TAG] tag
+pub fn ref_lengths(
+    prelude: &libradicl::header::RadPrelude,
+    file_tag_map: &libradicl::rad_types::TagMap,
+) -> Option<Vec<u64>> {
+    use libradicl::rad_types::TagValue;
+    let lens: Vec<u64> = match file_tag_map.get(REF_LENGTHS_TAG)? {
+        TagValue::ArrayU8(x) => x.iter().map(|&l| l as u64).collect(),
+        TagValue::ArrayU16(x) => x.iter().map(|&l| l as u64).collect(),
+        TagValue::ArrayU32(x) => x.iter().map(|&l| l as u64).collect(),
+        TagValue::ArrayU64(x) => x.clone(),
+        tv => {
+            warn!(
+                "the {} file tag is not an integer array ({:?}); ignoring it",
+                REF_LENGTHS_TAG, tv
+            );
+            return None;
+        }
+    };
+    if lens.len() as u64 != prelude.hdr.ref_count {
+        warn!(
+            "the {} file tag has {} entries, but there are {} references; ignoring it",
+            REF_LENGTHS_TAG,
+            lens.len(),
+            prelude.hdr.ref_count
+        );
+        return None;
+    }
+    Some(lens)
+}
+
+/// JSON representation of everything in a RAD file that precedes
+/// the mapped records
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct HeaderJson<'a> {
+    pub rad_header: RadHeaderJson<'a>,
+    pub tag_descriptions: TagDescriptionsJson<'a>,
+    pub file_tags: Vec<FileTagJson<'a>>,
+}
+
+impl<'a> HeaderJson<'a> {
+    pub fn new(
+        prelude: &'a libradicl::header::RadPrelude,
+        file_tag_map: &libradicl::rad_types::TagMap,
+    ) -> Self {
+        let rad_header = RadHeaderJson {
+            is_paired: prelude.hdr.is_paired,
+            ref_count: prelude.hdr.ref_count,
+            refs: RefsJson::Names(prelude.hdr.ref_names.iter().map(|n| n.as_str()).collect()),
+            ref_lengths: ref_lengths(prelude, file_tag_map),
+            num_chunks: (prelude.hdr.num_chunks > 0).then_some(prelude.hdr.num_chunks),
+        };
+
+        let tag_descriptions = TagDescriptionsJson {
+            file_tag_desc: (&prelude.file_tags).into(),
+            read_tag_desc: (&prelude.read_tags).into(),
+            aln_tag_desc: (&prelude.aln_tags).into(),
+        };
+
+        // file tags
+        let file_tags = prelude
+            .file_tags
+            .tags
+            .iter()
+            .filter_map(|td| {
+                file_tag_map.get(&td.name).map(|tv| FileTagJson {
+                    name: &td.name,
+                    val: tag_value_to_json(tv),
+                })
+            })
+            .collect();
+
+        Self {
+            rad_header,
+            tag_descriptions,
+            file_tags,
+        }
+    }
+
+    /// Sort the tag descriptions of each section, and the file-level tag
+    /// values, by name
+    pub fn sort_tags(&mut self) {
+        for section in [
+            &mut self.tag_descriptions.file_tag_desc,
+            &mut self.tag_descriptions.read_tag_desc,
+            &mut self.tag_descriptions.aln_tag_desc,
+        ] {
+            section.tag_desc.sort_by(|a, b| a.name.cmp(b.name));
+        }
+        self.file_tags.sort_by(|a, b| a.name.cmp(b.name));
+    }
+}
+
+/// Write the header of the RAD file with the given `prelude` and
+/// file-level tags in `format`. If the prelude doesn't record the number
+/// of chunks, but it is known otherwise, it can be given as `num_chunks`.
+/// If given, `ref_names` are printed in place of the names of the
+/// references in the prelude. With `compact`, JSON output has no
+/// insignificant whitespace, and with `sort_tags`, the tags are listed in
+/// the order of their names. With `ref_objects`, the references of JSON
+/// output are listed as objects (see [RadHeaderJson::use_ref_objects]).
+#[allow(clippy::too_many_arguments)]
+pub fn write_header<'a>(
+    prelude: &'a libradicl::header::RadPrelude,
+    file_tag_map: &libradicl::rad_types::TagMap,
+    format: OutputFormat,
+    compact: bool,
+    max_refs: Option<usize>,
+    num_chunks: Option<u64>,
+    ref_names: Option<&'a [String]>,
+    sort_tags: bool,
+    ref_objects: bool,
+    output_stream: &mut Box<dyn Write + '_>,
+) -> anyhow::Result<()> {
+    let mut header = HeaderJson::new(prelude, file_tag_map);
+    if sort_tags {
+        header.sort_tags();
+    }
+    if num_chunks.is_some() {
+        header.rad_header.num_chunks = num_chunks;
+    }
+    if let Some(names) = ref_names {
+        header.rad_header.refs = RefsJson::Names(names.iter().map(|n| n.as_str()).collect());
+    }
+    // the lengths are still needed for the @SQ lines of SAM output
+    if ref_objects && format != OutputFormat::Sam {
+        header.rad_header.use_ref_objects();
+    }
+    // every reference gets an @SQ line in SAM output, whatever `max_refs`
+    if let Some(n) = max_refs.filter(|_| format != OutputFormat::Sam) {
+        header.rad_header.truncate_refs(n);
+    }
+    match format {
+        OutputFormat::Json if compact => {
+            write!(output_stream, "\"rad_header\":")?;
+            serde_json::to_writer(&mut *output_stream, &header.rad_header)?;
+            write!(output_stream, ",\"tag_descriptions\":")?;
+            serde_json::to_writer(&mut *output_stream, &header.tag_descriptions)?;
+            write!(output_stream, ",\"file_tags\":")?;
+            serde_json::to_writer(&mut *output_stream, &header.file_tags)?;
+            write!(output_stream, ",")?;
+        }
+        OutputFormat::Json => {
+            write!(output_stream, "\"rad_header\" : ")?;
+            serde_json::to_writer_pretty(&mut *output_stream, &header.rad_header)?;
+            writeln!(output_stream, ",")?;
+
+            write!(output_stream, "\"tag_descriptions\" : ")?;
+            serde_json::to_writer_pretty(&mut *output_stream, &header.tag_descriptions)?;
+            writeln!(output_stream, ",")?;
+
+            write!(output_stream, "\"file_tags\" : ")?;
+            serde_json::to_writer_pretty(&mut *output_stream, &header.file_tags)?;
+            writeln!(output_stream, ",")?;
+        }
+        OutputFormat::Ndjson => {
+            serde_json::to_writer(&mut *output_stream, &header)?;
+            writeln!(output_stream)?;
+        }
+        // the tabular format has no place for the header
+        OutputFormat::Tsv => {}
+        OutputFormat::Sam => {
+            // unless the file records the reference lengths, every
+            // reference is given the maximum length SAM allows.
+            let lens = header.rad_header.ref_lengths;
+            writeln!(output_stream, "@HD\tVN:1.6\tSO:unknown")?;
+            for (i, name) in ref_names
+                .unwrap_or(&prelude.hdr.ref_names)
+                .iter()
+                .enumerate()
+            {
+                let len = lens.as_ref().map_or(i32::MAX as u64, |l| l[i]);
+                writeln!(output_stream, "@SQ\tSN:{}\tLN:{}", name, len)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the [libradicl::header::RadPrelude] from `reader`, decoding the
+/// reference names with [String::from_utf8_lossy] so that names which are
+/// not valid UTF-8 have the offending bytes replaced by U+FFFD rather
+/// than causing the parse to fail.
+///
+/// The header (is_paired, ref_count, the length-prefixed names and
+/// num_chunks) is read here, re-encoded with the sanitized names, and
+/// handed to `RadPrelude::from_bytes` followed by the rest of the stream.
+pub fn read_prelude_lossy<R: std::io::BufRead>(
+    reader: &mut R,
+) -> anyhow::Result<libradicl::header::RadPrelude> {
+    let mut hdr_buf = Vec::<u8>::new();
+
+    let mut is_paired = [0u8; 1];
+    reader.read_exact(&mut is_paired)?;
+    hdr_buf.extend_from_slice(&is_paired);
+
+    let mut ref_count = [0u8; 8];
+    reader.read_exact(&mut ref_count)?;
+    hdr_buf.extend_from_slice(&ref_count);
+
+    let mut name_buf = Vec::<u8>::new();
+    let mut num_replaced = 0_usize;
+    for _ in 0..u64::from_le_bytes(ref_count) {
+        let mut name_len = [0u8; 2];
+        reader.read_exact(&mut name_len)?;
+        name_buf.resize(u16::from_le_bytes(name_len) as usize, 0);
+        reader.read_exact(name_buf.as_mut_slice())?;
+
+        let name = String::from_utf8_lossy(&name_buf);
+        if let std::borrow::Cow::Owned(_) = name {
+            num_replaced += 1;
+        }
+        let Ok(new_len) = u16::try_from(name.len()) else {
+            bail!(
+                "sanitized reference name ({}) is too long to be represented",
+                name
+            );
+        };
+        hdr_buf.extend_from_slice(&new_len.to_le_bytes());
+        hdr_buf.extend_from_slice(name.as_bytes());
+    }
+
+    let mut num_chunks = [0u8; 8];
+    reader.read_exact(&mut num_chunks)?;
+    hdr_buf.extend_from_slice(&num_chunks);
+
+    if num_replaced > 0 {
+        warn!(
+            "{} reference names were not valid UTF-8 and were decoded lossily",
+            num_replaced
+        );
+    }
+
+    let mut sanitized = std::io::Cursor::new(hdr_buf).chain(reader);
+    libradicl::header::RadPrelude::from_bytes(&mut sanitized)
+}
+
+/// Attempt to determine the type of a RAD file from its prelude and
+/// file-level tags. Single-cell RAD files carry both the `cblen` and `ulen`
+/// file-level tags, while bulk RAD files carry neither. If only one of
+/// these tags is present, the type is ambiguous and `RadFileType::Unknown`
+/// is returned.
+pub fn detect_rad_type(
+    prelude: &libradicl::header::RadPrelude,
+    file_tag_map: &libradicl::rad_types::TagMap,
+) -> RadFileType {
+    let has_file_tag = |name: &str| {
+        prelude.file_tags.tags.iter().any(|td| td.name == name) && file_tag_map.get(name).is_some()
+    };
+    match (has_file_tag("cblen"), has_file_tag("ulen")) {
+        (true, true) => RadFileType::SingleCell,
+        (false, false) => RadFileType::Bulk,
+        _ => RadFileType::Unknown,
+    }
+}
+
+/// Determine the type of RAD file to use; if the user `requested` a
+/// specific (known) type, that is used, otherwise the type is detected
+/// with [detect_rad_type]. It is an error if the type cannot be detected.
+pub fn resolve_rad_type(
+    requested: Option<&RadFileType>,
+    prelude: &libradicl::header::RadPrelude,
+    file_tag_map: &libradicl::rad_types::TagMap,
+    input: &std::path::Path,
+) -> anyhow::Result<RadFileType> {
+    match requested {
+        Some(RadFileType::Unknown) | None => {
+            let detected = detect_rad_type(prelude, file_tag_map);
+            if detected == RadFileType::Unknown {
+                error!(
+                    "Could not determine the type of the RAD file ({}); please provide it with --rad-type",
+                    input.display()
+                );
+                bail!("Unable to detect RAD file type");
+            }
+            info!("detected RAD file type as {:?}", detected);
+            Ok(detected)
+        }
+        Some(t) => Ok(t.clone()),
+    }
+}
+
+/// Resolve each entry of `refs`, which may be either the name of a
+/// reference or its numeric ID, to the corresponding reference ID. Names
+/// are matched first, so that a reference whose name happens to be a
+/// number is found by name.
+pub fn parse_ref_filter(
+    refs: &[String],
+    prelude: &libradicl::header::RadPrelude,
+) -> anyhow::Result<HashSet<u32>> {
+    let name_to_id: HashMap<&str, u32> = prelude
+        .hdr
+        .ref_names
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.as_str(), i as u32))
+        .collect();
+
+    let mut ids = HashSet::new();
+    for r in refs {
+        if let Some(&id) = name_to_id.get(r.as_str()) {
+            ids.insert(id);
+        } else if let Some(id) = r
+            .parse::<u32>()
+            .ok()
+            .filter(|&id| (id as u64) < prelude.hdr.ref_count)
+        {
+            ids.insert(id);
+        } else {
+            bail!(
+                "{} is neither the name of a reference nor a valid reference ID",
+                r
+            );
+        }
+    }
+    Ok(ids)
+}
+
+/// Write the mapped records of `ifile`, which is a RAD file of type
+/// `rad_type` whose next chunk is at byte offset `start_offset`, to
+/// `output_stream`, filling in any information in
+/// `extra_record_info` that is specific to the type of the records.
+pub fn write_mapped_records<R: BufRead>(
+    rad_type: &RadFileType,
+    prelude: &libradicl::header::RadPrelude,
+    file_tag_map: &libradicl::rad_types::TagMap,
+    extra_record_info: &mut ExtraRecordInfo,
+    emitter: &mut RecordEmitter,
+    ifile: &mut R,
+    start_offset: u64,
+    output_stream: &mut Box<dyn Write + '_>,
+) -> anyhow::Result<()> {
+    // the records of each input are laid out by its own tag descriptions
+    extra_record_info.tag_layout = num_decoded_tags(rad_type)
+        .and_then(|(num_read, num_aln)| RecordTagLayout::from_prelude(prelude, num_read, num_aln));
+    match rad_type {
+        RadFileType::Bulk => {
+            write_records::<PiscemBulkRecordContext, PiscemBulkReadRecord, R>(
+                prelude,
+                extra_record_info,
+                emitter,
+                ifile,
+                start_offset,
+                output_stream,
+            )?;
+        }
+        RadFileType::SingleCell => {
+            let cblen: u64 = file_tag_map
+                .get("cblen")
+                .expect("tag map must contain \"cblen\" value")
+                .try_into()?;
+
+            let ulen: u64 = file_tag_map
+                .get("ulen")
+                .expect("tag map must contain \"ulen\" value")
+                .try_into()?;
+
+            extra_record_info.bc_len = cblen as usize;
+            extra_record_info.umi_len = ulen as usize;
+
+            write_records::<AlevinFryRecordContext, AlevinFryReadRecord, R>(
+                prelude,
+                extra_record_info,
+                emitter,
+                ifile,
+                start_offset,
+                output_stream,
+            )?;
+        }
+        RadFileType::Unknown => bail!("Unknown RadFileType not supported yet"),
+    }
+    Ok(())
+}
+
+/// Parse a range of chunks given as `START-END`
+fn parse_chunk_range(s: &str) -> anyhow::Result<(usize, usize)> {
+    let Some((start, end)) = s.split_once('-') else {
+        bail!("the chunk range {} is not of the form START-END", s);
+    };
+    let start: usize = start.parse()?;
+    let end: usize = end.parse()?;
+    if start >= end {
+        bail!("the chunk range {} is empty", s);
+    }
+    Ok((start, end))
+}
+
+/// The number of read-level and alignment-level tags that the records of
+/// each type are decoded into (see [SupportedRecord::NUM_FIXED_TAGS])
+fn num_decoded_tags(rad_type: &RadFileType) -> Option<(usize, usize)> {
+    match rad_type {
+        RadFileType::Bulk => Some(PiscemBulkReadRecord::NUM_FIXED_TAGS),
+        RadFileType::SingleCell => Some(AlevinFryReadRecord::NUM_FIXED_TAGS),
+        RadFileType::Unknown => None,
+    }
+}
+
+/// Parse a `--require-tag` of the form `NAME=VALUE`
+fn parse_required_tag(s: &str) -> anyhow::Result<(String, String)> {
+    let Some((name, value)) = s.split_once('=') else {
+        bail!("the required tag {} is not of the form NAME=VALUE", s);
+    };
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parses_equal<T: std::str::FromStr + PartialEq>(s: &str, x: &T) -> bool {
+    s.trim().parse::<T>().is_ok_and(|v| v == *x)
+}
+
+fn parses_all_equal<T: std::str::FromStr + PartialEq>(s: &str, xs: &[T]) -> bool {
+    let parts: Vec<&str> = if s.is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').collect()
+    };
+    parts.len() == xs.len() && parts.iter().zip(xs).all(|(p, x)| parses_equal(p, x))
+}
+
+/// Returns `true` if `value`, parsed as a value of the type of `tv`, is
+/// equal to `tv`. The elements of array values are separated by ','.
+pub fn tag_value_matches(tv: &libradicl::rad_types::TagValue, value: &str) -> bool {
+    use libradicl::rad_types::TagValue;
+    match tv {
+        TagValue::Bool(x) => parses_equal(value, x),
+        TagValue::U8(x) => parses_equal(value, x),
+        TagValue::U16(x) => parses_equal(value, x),
+        TagValue::U32(x) => parses_equal(value, x),
+        TagValue::U64(x) => parses_equal(value, x),
+        TagValue::F32(x) => parses_equal(value, x),
+        TagValue::F64(x) => parses_equal(value, x),
+        TagValue::ArrayU8(x) => parses_all_equal(value, x),
+        TagValue::ArrayU16(x) => parses_all_equal(value, x),
+        TagValue::ArrayU32(x) => parses_all_equal(value, x),
+        TagValue::ArrayU64(x) => parses_all_equal(value, x),
+        TagValue::ArrayF32(x) => parses_all_equal(value, x),
+        TagValue::ArrayF64(x) => parses_all_equal(value, x),
+        TagValue::String(x) => x == value,
+        TagValue::ArrayString(x) => {
+            let parts: Vec<&str> = if value.is_empty() {
+                Vec::new()
+            } else {
+                value.split(',').collect()
+            };
+            parts.len() == x.len() && parts.iter().zip(x).all(|(p, s)| *p == s.as_str())
+        }
+    }
+}
+
+/// Advance `ifile`, the input at `path` positioned just past its prelude
+/// and file-level tags (i.e. at `first_chunk_offset`), to the start of
+/// chunk `start`. If the input has an up-to-date chunk index, the file is
+/// reopened and seeked directly to the chunk, otherwise the preceding
+/// chunks are skipped by reading only their headers. Returns the positioned reader,
+/// the number of records in the skipped chunks, and the byte offset of
+/// chunk `start`.
+pub fn seek_to_chunk(
+    path: &std::path::Path,
+    ifile: Box<dyn BufRead>,
+    first_chunk_offset: u64,
+    start: usize,
+) -> anyhow::Result<(Box<dyn BufRead>, usize, u64)> {
+    if let Some(index) = ChunkIndex::for_input(path)? {
+        // only uncompressed files are indexed, so the offsets can be
+        // seeked to in the file itself.
+        let Some(entry) = index.entries.get(start) else {
+            bail!(
+                "chunk {} is past the end of {}, which has {} chunks",
+                start,
+                path.display(),
+                index.entries.len()
+            );
+        };
+        let skipped = index.entries[..start].iter().map(|e| e.nrec as usize).sum();
+        let mut f = std::fs::File::open(path)?;
+        f.seek(std::io::SeekFrom::Start(entry.offset))?;
+        // the file may have been rewritten to the same length since it
+        // was indexed, so check that a matching chunk header is there
+        let mut header = [0u8; 8];
+        let header_matches = f.read_exact(&mut header).is_ok()
+            && header[..4] == entry.nbytes.to_le_bytes()
+            && header[4..] == entry.nrec.to_le_bytes();
+        if header_matches {
+            f.seek(std::io::SeekFrom::Start(entry.offset))?;
+            return Ok((Box::new(buffered_reader(f)), skipped, entry.offset));
+        }
+        warn!(
+            "the chunk index of {} doesn't match the file (chunk {} isn't at byte offset {}); rebuild it with `radtk index`",
+            path.display(),
+            start,
+            entry.offset
+        );
+    }
+
+    let mut ifile = CountingReader::with_position(ifile, first_chunk_offset);
+    let mut skipped = 0;
+    let mut num_skipped_chunks = 0;
+    if start > 0 {
+        walk_chunk_headers(&mut ifile, |_, _, nrec| {
+            skipped += nrec as usize;
+            num_skipped_chunks += 1;
+            num_skipped_chunks < start
+        })?;
+    }
+    if num_skipped_chunks < start {
+        bail!(
+            "chunk {} is past the end of {}, which has {} chunks",
+            start,
+            path.display(),
+            num_skipped_chunks
+        );
+    }
+    let offset = ifile.position();
+    Ok((ifile.into_inner(), skipped, offset))
+}
+
+/// The number of chunks of the input at `path` recorded by its chunk
+/// index, or `None` if it has no (up-to-date) index
+fn indexed_num_chunks(path: &std::path::Path) -> anyhow::Result<Option<u64>> {
+    Ok(ChunkIndex::for_input(path)?.map(|index| index.entries.len() as u64))
+}
+
+pub fn view(view_opts: &ViewOpts) -> anyhow::Result<()> {
+    let mut writer = match view_opts.output {
+        Some(ref path) => {
+            // a FIFO is written to as a stream, so it isn't truncated
+            let f = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(!is_fifo(path))
+                .open(path)?;
+            let compression = view_opts
+                .compress
+                .unwrap_or_else(|| Compression::from_path(path));
+            let f: Box<dyn Write> = Box::new(buffered_writer(f));
+            compressed_writer(f, compression)?
+        }
+        None => {
+            let stdout: Box<dyn Write> = Box::new(io::stdout());
+            compressed_writer(stdout, view_opts.compress.unwrap_or(Compression::None))?
+        }
+    };
+    write_view(view_opts, Box::new(&mut writer))?;
+    // the output is only complete once its compressed stream is finished
+    writer.finish()?;
+    Ok(())
+}
+
+/// Write the output of `view` to `output_stream`
+fn write_view(view_opts: &ViewOpts, mut output_stream: Box<dyn Write + '_>) -> anyhow::Result<()> {
+    if view_opts.emit_schema {
+        if view_opts.compact {
+            serde_json::to_writer(&mut output_stream, &output_schema())?;
+        } else {
+            serde_json::to_writer_pretty(&mut output_stream, &output_schema())?;
+        }
+        writeln!(output_stream)?;
+        output_stream.flush()?;
+        return Ok(());
+    }
+
+    let first_input = view_opts
+        .input
+        .first()
+        .expect("at least one input is required");
+    if view_opts.input.len() > 1 && view_opts.input.iter().any(is_stdin) {
+        bail!("stdin can't be read as one of several inputs to view");
+    }
+
+    let open_input = |path: &std::path::Path| -> anyhow::Result<ParsedRad> {
+        if view_opts.lossy_names {
+            ParsedRad::from_reader_with(open_rad_input(path)?, read_prelude_lossy)
+        } else {
+            open_and_parse(path)
+        }
+    };
+
+    let ParsedRad {
+        reader: mut ifile,
+        prelude,
+        tag_map: file_tag_map,
+        first_chunk_offset,
+        ..
+    } = open_input(first_input)?;
+    for (name, value) in view_opts.require_tag.iter() {
+        match file_tag_map.get(name) {
+            Some(tv) if tag_value_matches(tv, value) => {}
+            Some(tv) => bail!(
+                "the file-level tag {} of {} is {}, but --require-tag requires {}",
+                name,
+                first_input.display(),
+                tag_value_to_json(tv),
+                value
+            ),
+            None => bail!(
+                "{} has no file-level tag {}, which --require-tag requires",
+                first_input.display(),
+                name
+            ),
+        }
+    }
+    if !libradicl::utils::has_data_left(&mut ifile)? {
+        info!("{} contains no chunks", first_input.display());
+    }
+    let mut skipped_records = 0;
+    let mut skipped_chunks = 0;
+    let mut start_offset = first_chunk_offset;
+    let start = view_opts
+        .chunk_range
+        .map(|(start, _)| start)
+        .or(view_opts.skip_chunks);
+    if let Some(start) = start {
+        if view_opts.input.len() > 1 {
+            bail!("--chunk-range and --skip-chunks can only be used with a single input");
+        }
+        (ifile, skipped_records, start_offset) =
+            seek_to_chunk(first_input, ifile, first_chunk_offset, start)?;
+        skipped_chunks = start;
+    }
+
+    let rad_type = resolve_rad_type(
+        view_opts.rad_type.as_ref(),
+        &prelude,
+        &file_tag_map,
+        first_input,
+    )?;
+    // the single-cell record layout has a single list of alignments, with
+    // no per-mate fields, even when the reads were paired.
+    if rad_type == RadFileType::SingleCell && prelude.hdr.is_paired != 0 {
+        warn!(
+            "{} holds paired-end single-cell records, but their layout carries no per-mate information; each alignment describes the pair as a whole",
+            first_input.display()
+        );
+    }
+
+    let format = view_opts.format;
+    if format == OutputFormat::Sam && rad_type != RadFileType::Bulk {
+        bail!("--format sam can only be used with bulk RAD files");
+    }
+    if view_opts.reference_fasta.is_some() && format != OutputFormat::Sam {
+        bail!("--reference-fasta can only be used with --format sam");
+    }
+    if let Some(ref columns) = view_opts.columns {
+        check_columns(columns, &rad_type, format)?;
+    }
+    if let (Some(min), Some(max)) = (view_opts.min_alns, view_opts.max_alns) {
+        if min > max {
+            bail!("--min-alns ({}) must not exceed --max-alns ({})", min, max);
+        }
+    }
+    // with `--split-output`, the records of each chunk are written to their
+    // own file, so there is no enclosing object for the whole output.
+    let split_output = view_opts.split_output.is_some();
+    if let Some(ref dir) = view_opts.split_output {
+        if !matches!(format, OutputFormat::Json | OutputFormat::Ndjson) {
+            bail!("--split-output can only be used with --format json or ndjson");
+        }
+        std::fs::create_dir_all(dir)?;
+    }
+    let renamed_refs = view_opts
+        .rename_refs
+        .as_ref()
+        .map(|f| read_ref_renames(f, &prelude))
+        .transpose()?;
+    let compact = view_opts.compact;
+    if format == OutputFormat::Json && !split_output {
+        if compact {
+            write!(output_stream, "{{")?;
+        } else {
+            writeln!(output_stream, "{{")?;
+        }
+    }
+
+    if !view_opts.no_header && !split_output {
+        // files written in a streaming fashion don't record their number
+        // of chunks; it is taken from the chunk index if there is one (the
+        // input is read only once, so it can't be counted before the
+        // header is written), and is otherwise reported as unknown.
+        let is_json = matches!(format, OutputFormat::Json | OutputFormat::Ndjson);
+        let num_chunks = if is_json
+            && prelude.hdr.num_chunks == 0
+            && !is_stdin(first_input)
+            && !is_url(first_input)
+        {
+            let num_chunks = indexed_num_chunks(first_input)?;
+            if num_chunks.is_none() {
+                info!(
+                    "{} doesn't record its number of chunks, so it is reported as unknown; index the file to have it reported",
+                    first_input.display()
+                );
+            }
+            num_chunks
+        } else {
+            None
+        };
+        write_header(
+            &prelude,
+            &file_tag_map,
+            format,
+            compact,
+            view_opts.max_refs_in_header,
+            num_chunks,
+            renamed_refs.as_deref(),
+            view_opts.deterministic,
+            view_opts.refs_objects,
+            &mut output_stream,
+        )?;
+    }
+
+    if view_opts.header_only {
+        if format == OutputFormat::Json {
+            if compact {
+                writeln!(output_stream, "\"mapped_records\":[]}}")?;
+            } else {
+                writeln!(output_stream, "\"mapped_records\" : []")?;
+                writeln!(output_stream, "}}")?;
+            }
+        }
+        output_stream.flush()?;
+        return Ok(());
+    }
+
+    let mut extra_record_info = ExtraRecordInfo::new(&prelude, format);
+    extra_record_info.use_ref_name = view_opts.use_ref_name;
+    extra_record_info.renamed_refs = renamed_refs;
+    if let Some(ref fa_path) = view_opts.reference_fasta {
+        let fa = IndexedFasta::open(fa_path)?;
+        if let Some(missing) = prelude.hdr.ref_names.iter().find(|n| !fa.contains(n)) {
+            bail!(
+                "reference {} of {} is not in the reference FASTA {}",
+                missing,
+                first_input.display(),
+                fa_path.display()
+            );
+        }
+        extra_record_info.reference = Some(fa);
+    }
+    extra_record_info.max_chunks = view_opts.max_chunks;
+    extra_record_info.max_records = view_opts.max_records;
+    if let Some((start, end)) = view_opts.chunk_range {
+        let range_len = end - start;
+        extra_record_info.max_chunks = Some(
+            view_opts
+                .max_chunks
+                .map_or(range_len, |mc| mc.min(range_len)),
+        );
+    }
+    extra_record_info.print_column_names = !view_opts.no_header;
+    extra_record_info.compact = compact;
+    extra_record_info.columns.clone_from(&view_opts.columns);
+    extra_record_info.threads = view_opts.threads;
+    extra_record_info.sort_alns = view_opts.sort_alns;
+    extra_record_info.drop_unmapped = view_opts.drop_unmapped;
+    extra_record_info.min_alns = view_opts.min_alns;
+    extra_record_info.max_alns = view_opts.max_alns;
+    extra_record_info.max_alns_per_record = view_opts.max_alns_per_record;
+    extra_record_info.emit_offsets = view_opts.emit_offsets;
+    extra_record_info.chunk_boundaries = view_opts.include_chunk_boundaries;
+    extra_record_info.positions_1based = view_opts.positions_1based;
+    extra_record_info.skip_bad_refs = view_opts.skip_bad_refs;
+    extra_record_info.lenient = view_opts.lenient;
+    extra_record_info
+        .split_output
+        .clone_from(&view_opts.split_output);
+    // the progress bar is drawn to stderr, so it never mixes with the
+    // output when that is written to stdout.
+    // (if there are several inputs, their total number of chunks isn't known)
+    let total_chunks = if view_opts.input.len() == 1 {
+        prelude.hdr.num_chunks
+    } else {
+        0
+    };
+    extra_record_info.progress = chunk_progress_bar(total_chunks, view_opts.quiet);
+    if let Some(ref rf) = view_opts.ref_filter {
+        extra_record_info.ref_filter = Some(parse_ref_filter(rf, &prelude)?);
+        extra_record_info.prune_alns = view_opts.prune_alns;
+    }
+    if !view_opts.region.is_empty() {
+        if rad_type != RadFileType::Bulk {
+            bail!("--region can only be used with bulk RAD files, whose alignments have positions");
+        }
+        let regions = view_opts
+            .region
+            .iter()
+            .map(|r| Region::parse(r, &prelude))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        extra_record_info.regions = Some(regions);
+    }
+    if let Some(ref bc_file) = view_opts.barcodes {
+        if rad_type != RadFileType::SingleCell {
+            bail!("--barcodes can only be used with single-cell RAD files");
+        }
+        let cblen: u64 = file_tag_map
+            .get("cblen")
+            .expect("tag map must contain \"cblen\" value")
+            .try_into()?;
+        extra_record_info.barcodes = Some(read_barcode_list(bc_file, cblen as usize)?);
+    }
+
+    if format == OutputFormat::Json && !split_output {
+        if compact {
+            write!(output_stream, "\"mapped_records\":[")?;
+        } else {
+            writeln!(output_stream, "\"mapped_records\" : [")?;
+        }
+    }
+    let mut emitter = RecordEmitter::new(&extra_record_info);
+    // records (and chunks) keep their indices within the whole file
+    emitter.record_index = skipped_records;
+    emitter.chunk_index = skipped_chunks;
+    write_mapped_records(
+        &rad_type,
+        &prelude,
+        &file_tag_map,
+        &mut extra_record_info,
+        &mut emitter,
+        &mut ifile,
+        start_offset,
+        &mut output_stream,
+    )?;
+
+    // the records of any further inputs continue the same sequence of
+    // records, so their preludes must be compatible with the first.
+    for in_file in view_opts.input.iter().skip(1) {
+        if emitter.done() {
+            break;
+        }
+        let ParsedRad {
+            reader: mut ifile,
+            prelude: new_prelude,
+            tag_map: new_tag_map,
+            first_chunk_offset,
+            ..
+        } = open_input(in_file)?;
+        if !(preludes_compatible(&prelude, &new_prelude)
+            && file_tag_values_equal(&prelude, &file_tag_map, &new_tag_map))
+        {
+            error!(
+                "The prelude or file-level tags for ({}) is incompatible with the prelude or file-level tags for ({}); cannot proceed",
+                first_input.display(),
+                in_file.display()
+            );
+            bail!("Incompatible input RAD files.");
+        }
+        if !libradicl::utils::has_data_left(&mut ifile)? {
+            info!("{} contains no chunks", in_file.display());
+        }
+        write_mapped_records(
+            &rad_type,
+            &new_prelude,
+            &new_tag_map,
+            &mut extra_record_info,
+            &mut emitter,
+            &mut ifile,
+            first_chunk_offset,
+            &mut output_stream,
+        )?;
+    }
+    if !split_output {
+        emitter.finish(&mut output_stream)?;
+    }
+
+    if format == OutputFormat::Json && !split_output {
+        if compact {
+            writeln!(output_stream, "]}}")?;
+        } else {
+            writeln!(output_stream, "]")?;
+            writeln!(output_stream, "}}")?;
+        }
+    }
+    output_stream.flush()?;
+    extra_record_info.progress.finish();
+
+    let num_bad_refs = extra_record_info.num_bad_refs.load(AtomicOrdering::Relaxed);
+    if num_bad_refs > 0 {
+        warn!(
+            "dropped {} alignments to reference IDs not in the header",
+            num_bad_refs
+        );
+    }
+    let num_truncated = extra_record_info
+        .num_truncated
+        .load(AtomicOrdering::Relaxed);
+    if num_truncated > 0 {
+        warn!(
+            "truncated the alignments of {} malformed records",
+            num_truncated
+        );
+    }
 
     Ok(())
 }